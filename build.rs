@@ -0,0 +1,11 @@
+extern crate error_chain;
+extern crate hangups_codegen;
+
+use error_chain::ChainedError;
+
+fn main() {
+    match hangups_codegen::compile_protos(&["src/example.proto", "src/hangouts.proto"]) {
+        Ok(()) => {}
+        Err(e) => panic!("{}", e.display_chain()),
+    }
+}
@@ -0,0 +1,139 @@
+// Subset of descriptor.proto needed to generate pblite code.
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct FileDescriptor {
+    pub name: String,
+    pub package: Option<String>,
+    /// "proto2" or "proto3", if declared.
+    pub syntax: Option<String>,
+    pub message_types: Vec<MessageDescriptor>,
+    pub enum_types: Vec<EnumDescriptor>,
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct MessageDescriptor {
+    pub name: String,
+    pub fields: Vec<FieldDescriptor>,
+    /// Names of the oneofs referenced by `FieldDescriptor::oneof_index`.
+    pub oneof_decls: Vec<String>,
+    pub nested_types: Vec<MessageDescriptor>,
+    pub enum_types: Vec<EnumDescriptor>,
+    /// Whether this is the synthetic entry message of a map field, with fields key = 1 and
+    /// value = 2.
+    pub map_entry: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FieldDescriptor {
+    pub name: String,
+    pub number: u32,
+    pub label: Label,
+    pub field_type: FieldType,
+    /// Fully-qualified name of the message or enum type, eg. ".package.Message".
+    pub type_name: Option<String>,
+    /// Index into `MessageDescriptor::oneof_decls` if the field is a member of a oneof.
+    pub oneof_index: Option<usize>,
+    /// Declared default value as text, eg. "5", "true", "foo" or an enum value name. Like in
+    /// descriptors from protoc, bytes defaults use C-style escape sequences, eg. "\001".
+    pub default_value: Option<String>,
+    /// Value of the packed option, if set.
+    pub packed: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Label {
+    Optional,
+    Required,
+    Repeated,
+}
+impl Label {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            1 => Some(Label::Optional),
+            2 => Some(Label::Required),
+            3 => Some(Label::Repeated),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FieldType {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Group,
+    Message,
+    Bytes,
+    Uint32,
+    Enum,
+    Sfixed32,
+    Sfixed64,
+    Sint32,
+    Sint64,
+}
+impl FieldType {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            1 => Some(FieldType::Double),
+            2 => Some(FieldType::Float),
+            3 => Some(FieldType::Int64),
+            4 => Some(FieldType::Uint64),
+            5 => Some(FieldType::Int32),
+            6 => Some(FieldType::Fixed64),
+            7 => Some(FieldType::Fixed32),
+            8 => Some(FieldType::Bool),
+            9 => Some(FieldType::String),
+            10 => Some(FieldType::Group),
+            11 => Some(FieldType::Message),
+            12 => Some(FieldType::Bytes),
+            13 => Some(FieldType::Uint32),
+            14 => Some(FieldType::Enum),
+            15 => Some(FieldType::Sfixed32),
+            16 => Some(FieldType::Sfixed64),
+            17 => Some(FieldType::Sint32),
+            18 => Some(FieldType::Sint64),
+            _ => None,
+        }
+    }
+
+    /// Returns the scalar type for a type name used in a .proto file.
+    pub fn from_scalar_name(name: &str) -> Option<Self> {
+        match name {
+            "double" => Some(FieldType::Double),
+            "float" => Some(FieldType::Float),
+            "int64" => Some(FieldType::Int64),
+            "uint64" => Some(FieldType::Uint64),
+            "int32" => Some(FieldType::Int32),
+            "fixed64" => Some(FieldType::Fixed64),
+            "fixed32" => Some(FieldType::Fixed32),
+            "bool" => Some(FieldType::Bool),
+            "string" => Some(FieldType::String),
+            "bytes" => Some(FieldType::Bytes),
+            "uint32" => Some(FieldType::Uint32),
+            "sfixed32" => Some(FieldType::Sfixed32),
+            "sfixed64" => Some(FieldType::Sfixed64),
+            "sint32" => Some(FieldType::Sint32),
+            "sint64" => Some(FieldType::Sint64),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct EnumDescriptor {
+    pub name: String,
+    pub values: Vec<EnumValueDescriptor>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumValueDescriptor {
+    pub name: String,
+    pub number: i32,
+}
@@ -0,0 +1,303 @@
+use descriptor::{EnumDescriptor, EnumValueDescriptor, FieldDescriptor, FieldType, FileDescriptor,
+                 Label, MessageDescriptor};
+use std::str;
+use {ErrorKind, Result};
+
+fn invalid(message: &str) -> ::Error {
+    ErrorKind::InvalidDescriptor(message.to_owned()).into()
+}
+
+enum FieldValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Minimal reader for the protobuf binary wire format, sufficient for descriptor.proto.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in 0..10 {
+            let (&byte, rest) = self.bytes.split_first().ok_or(invalid("truncated varint"))?;
+            self.bytes = rest;
+            value |= ((byte & 0x7f) as u64) << (7 * shift);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("varint is too long"))
+    }
+
+    fn read_slice(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            return Err(invalid("truncated field"));
+        }
+        let (slice, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(slice)
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u32, FieldValue<'a>)>> {
+        if self.bytes.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.read_varint()?;
+        let value = match tag & 0x7 {
+            0 => FieldValue::Varint(self.read_varint()?),
+            1 => {
+                self.read_slice(8)?;
+                FieldValue::Fixed
+            }
+            2 => {
+                let length = self.read_varint()? as usize;
+                FieldValue::Bytes(self.read_slice(length)?)
+            }
+            5 => {
+                self.read_slice(4)?;
+                FieldValue::Fixed
+            }
+            _ => return Err(invalid("unsupported wire type")),
+        };
+        Ok(Some(((tag >> 3) as u32, value)))
+    }
+}
+
+fn read_fields<'a, F>(bytes: &'a [u8], mut on_field: F) -> Result<()>
+where
+    F: FnMut(u32, FieldValue<'a>) -> Result<()>,
+{
+    let mut reader = Reader { bytes: bytes };
+    while let Some((number, value)) = reader.next_field()? {
+        on_field(number, value)?;
+    }
+    Ok(())
+}
+
+fn read_string(value: FieldValue) -> Result<String> {
+    match value {
+        FieldValue::Bytes(bytes) => str::from_utf8(bytes)
+            .map(|s| s.to_owned())
+            .or(Err(invalid("invalid string"))),
+        _ => Err(invalid("expected string")),
+    }
+}
+
+fn read_varint(value: FieldValue) -> Result<u64> {
+    match value {
+        FieldValue::Varint(n) => Ok(n),
+        _ => Err(invalid("expected varint")),
+    }
+}
+
+fn read_bytes<'a>(value: FieldValue<'a>) -> Result<&'a [u8]> {
+    match value {
+        FieldValue::Bytes(bytes) => Ok(bytes),
+        _ => Err(invalid("expected message")),
+    }
+}
+
+fn read_file(bytes: &[u8]) -> Result<FileDescriptor> {
+    let mut file = FileDescriptor::default();
+    read_fields(bytes, |number, value| {
+        match number {
+            1 => file.name = read_string(value)?,
+            2 => file.package = Some(read_string(value)?),
+            4 => file.message_types.push(read_message(read_bytes(value)?)?),
+            5 => file.enum_types.push(read_enum(read_bytes(value)?)?),
+            12 => file.syntax = Some(read_string(value)?),
+            _ => {}
+        };
+        Ok(())
+    })?;
+    Ok(file)
+}
+
+fn read_message(bytes: &[u8]) -> Result<MessageDescriptor> {
+    let mut message = MessageDescriptor::default();
+    read_fields(bytes, |number, value| {
+        match number {
+            1 => message.name = read_string(value)?,
+            2 => message.fields.push(read_field(read_bytes(value)?)?),
+            3 => message.nested_types.push(read_message(read_bytes(value)?)?),
+            4 => message.enum_types.push(read_enum(read_bytes(value)?)?),
+            7 => message.map_entry = read_map_entry_option(read_bytes(value)?)?,
+            8 => message.oneof_decls.push(read_oneof(read_bytes(value)?)?),
+            _ => {}
+        };
+        Ok(())
+    })?;
+    Ok(message)
+}
+
+fn read_map_entry_option(bytes: &[u8]) -> Result<bool> {
+    let mut map_entry = false;
+    read_fields(bytes, |number, value| {
+        if number == 7 {
+            map_entry = read_varint(value)? != 0;
+        }
+        Ok(())
+    })?;
+    Ok(map_entry)
+}
+
+fn read_oneof(bytes: &[u8]) -> Result<String> {
+    let mut name = None;
+    read_fields(bytes, |number, value| {
+        if number == 1 {
+            name = Some(read_string(value)?);
+        }
+        Ok(())
+    })?;
+    name.ok_or(invalid("oneof is missing name"))
+}
+
+fn read_field(bytes: &[u8]) -> Result<FieldDescriptor> {
+    let mut name = None;
+    let mut field_number = None;
+    let mut label = Label::Optional;
+    let mut field_type = None;
+    let mut type_name = None;
+    let mut oneof_index = None;
+    let mut default_value = None;
+    let mut packed = None;
+    let mut proto3_optional = false;
+    read_fields(bytes, |number, value| {
+        match number {
+            1 => name = Some(read_string(value)?),
+            3 => field_number = Some(read_varint(value)? as u32),
+            4 => {
+                label = Label::from_u32(read_varint(value)? as u32).ok_or(invalid("invalid label"))?
+            }
+            5 => {
+                field_type = Some(FieldType::from_u32(read_varint(value)? as u32)
+                    .ok_or(invalid("invalid type"))?)
+            }
+            6 => type_name = Some(read_string(value)?),
+            7 => default_value = Some(read_string(value)?),
+            8 => packed = read_packed_option(read_bytes(value)?)?,
+            9 => oneof_index = Some(read_varint(value)? as usize),
+            17 => proto3_optional = read_varint(value)? != 0,
+            _ => {}
+        };
+        Ok(())
+    })?;
+    Ok(FieldDescriptor {
+        name: name.ok_or(invalid("field is missing name"))?,
+        number: field_number.ok_or(invalid("field is missing number"))?,
+        label: label,
+        field_type: field_type.ok_or(invalid("field is missing type"))?,
+        type_name: type_name,
+        // proto3 optional fields are wrapped in a synthetic oneof which is not a real choice.
+        oneof_index: if proto3_optional { None } else { oneof_index },
+        default_value: default_value,
+        packed: packed,
+    })
+}
+
+fn read_packed_option(bytes: &[u8]) -> Result<Option<bool>> {
+    let mut packed = None;
+    read_fields(bytes, |number, value| {
+        if number == 2 {
+            packed = Some(read_varint(value)? != 0);
+        }
+        Ok(())
+    })?;
+    Ok(packed)
+}
+
+fn read_enum(bytes: &[u8]) -> Result<EnumDescriptor> {
+    let mut enum_descriptor = EnumDescriptor::default();
+    read_fields(bytes, |number, value| {
+        match number {
+            1 => enum_descriptor.name = read_string(value)?,
+            2 => enum_descriptor.values.push(read_enum_value(read_bytes(value)?)?),
+            _ => {}
+        };
+        Ok(())
+    })?;
+    Ok(enum_descriptor)
+}
+
+fn read_enum_value(bytes: &[u8]) -> Result<EnumValueDescriptor> {
+    let mut name = None;
+    let mut value_number = 0;
+    read_fields(bytes, |number, value| {
+        match number {
+            1 => name = Some(read_string(value)?),
+            2 => value_number = read_varint(value)? as i32,
+            _ => {}
+        };
+        Ok(())
+    })?;
+    Ok(EnumValueDescriptor {
+        name: name.ok_or(invalid("enum value is missing name"))?,
+        number: value_number,
+    })
+}
+
+/// Parses a serialized FileDescriptorSet, as written by `protoc --descriptor_set_out`.
+pub fn parse(bytes: &[u8]) -> Result<Vec<FileDescriptor>> {
+    let mut files = Vec::new();
+    read_fields(bytes, |number, value| {
+        if number == 1 {
+            files.push(read_file(read_bytes(value)?)?);
+        }
+        Ok(())
+    })?;
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use descriptor::{FieldType, Label};
+    use descriptor_set;
+
+    fn length_delimited(number: u8, bytes: &[u8]) -> Vec<u8> {
+        let mut field = vec![number << 3 | 2, bytes.len() as u8];
+        field.extend_from_slice(bytes);
+        field
+    }
+
+    #[test]
+    fn test_parse_descriptor_set() {
+        // message Foo { repeated .Bar bar = 2; } enum Bar { BAR_ONE = 1; }
+        let mut field = length_delimited(1, b"bar");
+        field.extend_from_slice(&[3 << 3, 2, 4 << 3, 3, 5 << 3, 14]);
+        field.extend(length_delimited(6, b".Bar"));
+        let mut message = length_delimited(1, b"Foo");
+        message.extend(length_delimited(2, &field));
+        let mut enum_value = length_delimited(1, b"BAR_ONE");
+        enum_value.extend_from_slice(&[2 << 3, 1]);
+        let mut enum_descriptor = length_delimited(1, b"Bar");
+        enum_descriptor.extend(length_delimited(2, &enum_value));
+        let mut file = length_delimited(1, b"foo.proto");
+        file.extend(length_delimited(4, &message));
+        file.extend(length_delimited(5, &enum_descriptor));
+        let descriptor_set = length_delimited(1, &file);
+
+        let files = descriptor_set::parse(&descriptor_set).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "foo.proto");
+        let field = &files[0].message_types[0].fields[0];
+        assert_eq!(field.name, "bar");
+        assert_eq!(field.number, 2);
+        assert_eq!(field.label, Label::Repeated);
+        assert_eq!(field.field_type, FieldType::Enum);
+        assert_eq!(field.type_name, Some(".Bar".to_owned()));
+        assert_eq!(files[0].enum_types[0].values[0].name, "BAR_ONE");
+        assert_eq!(files[0].enum_types[0].values[0].number, 1);
+    }
+
+    #[test]
+    fn test_parse_truncated_descriptor_set() {
+        let result = descriptor_set::parse(&[1 << 3 | 2, 10, 0]);
+        assert_eq!(
+            result.err().expect("expected error").description(),
+            "invalid descriptor"
+        );
+    }
+}
@@ -0,0 +1,1731 @@
+use descriptor::{EnumDescriptor, EnumValueDescriptor, FieldDescriptor, FieldType, FileDescriptor,
+                 Label, MessageDescriptor};
+use std::collections::{HashMap, HashSet};
+use {ErrorKind, Result};
+
+const RUST_KEYWORDS: &[&str] = &["type"];
+
+/// Names and type references used while generating a single file.
+struct Context {
+    /// Prefix of fully-qualified names in this file's package, eg. ".package.".
+    package_prefix: String,
+    /// Fully-qualified names of the messages and enums defined in this file.
+    local_types: HashSet<String>,
+    /// Key and value fields of map entry messages, by fully-qualified name.
+    map_entries: HashMap<String, (FieldDescriptor, FieldDescriptor)>,
+    /// Names of the values of enums, by fully-qualified name.
+    enum_values: HashMap<String, HashSet<String>>,
+    /// Whether repeated scalar fields are packed unless declared otherwise.
+    packed_by_default: bool,
+}
+impl Context {
+    /// Returns the Rust name for a fully-qualified message or enum name.
+    ///
+    /// Nested types are flattened into the file's module by joining their names with
+    /// underscores, eg. ".package.Outer.Inner" becomes "Outer_Inner".
+    fn get_type_name(&self, full_name: &str) -> Result<String> {
+        if !self.local_types.contains(full_name) {
+            return Err(ErrorKind::UnresolvedType(full_name.to_owned()).into());
+        }
+        Ok(full_name[self.package_prefix.len()..].replace('.', "_"))
+    }
+
+    /// Returns the fully-qualified name, without the leading dot, for a name relative to the
+    /// package.
+    fn get_full_name(&self, name: &str) -> String {
+        format!("{}{}", &self.package_prefix[1..], name)
+    }
+
+    /// Returns the key and value fields if the field is a map.
+    fn get_map_entry(
+        &self,
+        field: &FieldDescriptor,
+    ) -> Option<&(FieldDescriptor, FieldDescriptor)> {
+        match (field.label, &field.type_name) {
+            (Label::Repeated, &Some(ref type_name)) => self.map_entries.get(type_name),
+            _ => None,
+        }
+    }
+
+    /// Returns whether a repeated field is encoded as a single length-delimited value in the
+    /// binary format.
+    fn is_packed(&self, field: &FieldDescriptor) -> bool {
+        let is_scalar = match field.field_type {
+            FieldType::String | FieldType::Bytes | FieldType::Message | FieldType::Group => false,
+            _ => true,
+        };
+        field.label == Label::Repeated && is_scalar
+            && field.packed.unwrap_or(self.packed_by_default)
+    }
+}
+
+/// A message or enum with its name relative to the package, eg. "Outer.Inner".
+struct Scoped<'a, T: 'a> {
+    name: String,
+    descriptor: &'a T,
+}
+impl<'a, T> Scoped<'a, T> {
+    fn get_rust_name(&self) -> String {
+        self.name.replace('.', "_")
+    }
+
+    fn get_attributes(&self) -> &'static str {
+        if self.name.contains('.') {
+            "#[allow(non_camel_case_types)]\n"
+        } else {
+            ""
+        }
+    }
+}
+
+fn collect_types<'a>(
+    scope: &str,
+    messages: &'a [MessageDescriptor],
+    enums: &'a [EnumDescriptor],
+    scoped_messages: &mut Vec<Scoped<'a, MessageDescriptor>>,
+    scoped_enums: &mut Vec<Scoped<'a, EnumDescriptor>>,
+) {
+    for message in messages {
+        let name = format!("{}{}", scope, message.name);
+        scoped_messages.push(Scoped {
+            name: name.to_owned(),
+            descriptor: message,
+        });
+        collect_types(
+            &format!("{}.", name),
+            &message.nested_types,
+            &message.enum_types,
+            scoped_messages,
+            scoped_enums,
+        );
+    }
+    for enum_descriptor in enums {
+        scoped_enums.push(Scoped {
+            name: format!("{}{}", scope, enum_descriptor.name),
+            descriptor: enum_descriptor,
+        });
+    }
+}
+
+/// Generates Rust source implementing `pblite::Message` and `pblite::Enum` for a .proto file.
+pub fn generate(file: &FileDescriptor) -> Result<String> {
+    let mut messages = Vec::new();
+    let mut enums = Vec::new();
+    collect_types("", &file.message_types, &file.enum_types, &mut messages, &mut enums);
+    let package_prefix = match file.package {
+        Some(ref package) => format!(".{}.", package),
+        None => ".".to_owned(),
+    };
+    let local_types = messages
+        .iter()
+        .map(|message| &message.name)
+        .chain(enums.iter().map(|enum_descriptor| &enum_descriptor.name))
+        .map(|name| format!("{}{}", package_prefix, name))
+        .collect();
+    let mut map_entries = HashMap::new();
+    for message in messages.iter().filter(|message| message.descriptor.map_entry) {
+        let fields = &message.descriptor.fields;
+        let key = fields.iter().find(|field| field.number == 1);
+        let value = fields.iter().find(|field| field.number == 2);
+        match (key, value) {
+            (Some(key), Some(value)) => map_entries.insert(
+                format!("{}{}", package_prefix, message.name),
+                (key.clone(), value.clone()),
+            ),
+            _ => return Err(ErrorKind::Unsupported("incomplete map entries".to_owned()).into()),
+        };
+    }
+    let enum_values = enums
+        .iter()
+        .map(|enum_descriptor| {
+            let names = enum_descriptor
+                .descriptor
+                .values
+                .iter()
+                .map(|value| value.name.to_owned())
+                .collect();
+            (format!("{}{}", package_prefix, enum_descriptor.name), names)
+        })
+        .collect();
+    let context = Context {
+        package_prefix: package_prefix,
+        local_types: local_types,
+        map_entries: map_entries,
+        enum_values: enum_values,
+        packed_by_default: file.syntax.as_ref().map_or(false, |syntax| syntax == "proto3"),
+    };
+    let mut output = format!(
+        "\
+// automatically generated from {path}
+
+extern crate serde_json;
+
+use pblite;
+use pblite::{{Enum, Message}};
+use json;
+use wire;
+",
+        path = file.name,
+    );
+    // Map entries are decoded directly into maps, so they are not generated.
+    for message in messages.iter().filter(|message| !message.descriptor.map_entry) {
+        output.push_str(&gen_struct(&context, message)?);
+    }
+    for enum_descriptor in &enums {
+        output.push_str(&gen_enum(&context, enum_descriptor));
+    }
+    Ok(output)
+}
+
+fn gen_struct(context: &Context, message: &Scoped<MessageDescriptor>) -> Result<String> {
+    let fields = &message.descriptor.fields;
+    Ok(format!(
+        r#"
+#[derive(Debug, Default, PartialEq, Clone, Serialize)]
+{attributes}pub struct {rust_name} {{
+{fields}    #[serde(skip_serializing_if = "::std::collections::BTreeMap::is_empty")]
+    pub unknown_fields: pblite::UnknownFields,
+    #[serde(skip)]
+    pub binary_unknown_fields: wire::UnknownFields,
+}}
+impl Message for {rust_name} {{
+    fn descriptor() -> &'static pblite::MessageDescriptor {{
+{descriptor}    }}
+    fn get_name(&self) -> &str {{
+        "{name}"
+    }}
+    fn set_field(
+        &mut self,
+        number: usize,
+        field_value: &serde_json::Value,
+        {sink}: &mut pblite::WarningSink,
+    ) -> pblite::Result<()> {{
+        match number {{
+{matches}            _ => pblite::read_unknown_field(&mut self.unknown_fields, number, field_value),
+        }};
+        Ok(())
+    }}
+    fn read_field(&mut self, number: usize, reader: &mut pblite::Reader) -> pblite::Result<()> {{
+        match number {{
+{reader_matches}            _ => reader.read_unknown_field(&mut self.unknown_fields, number)?,
+        }};
+        Ok(())
+    }}
+    fn get_fields(&self) -> Vec<(usize, serde_json::Value)> {{
+        let mut fields = vec![
+{writes}        ];
+        fields.extend(pblite::write_unknown_fields(&self.unknown_fields));
+        fields
+    }}
+{check_required}}}
+impl wire::Message for {rust_name} {{
+    fn merge_field(&mut self, number: u32, field_value: wire::FieldValue) -> wire::Result<()> {{
+        match number {{
+{binary_matches}            _ => wire::read_unknown_field(&mut self.binary_unknown_fields, number, field_value),
+        }};
+        Ok(())
+    }}
+    fn write_fields(&self, writer: &mut wire::Writer) {{
+{binary_writes}        wire::write_unknown_fields(writer, &self.binary_unknown_fields);
+    }}
+{check_initialized}}}
+impl json::Message for {rust_name} {{
+{set_json_field}    fn get_json_fields(&self) -> Vec<(&'static str, serde_json::Value)> {{
+        vec![
+{json_writes}        ]
+    }}
+}}
+{accessors}{oneofs}"#,
+        attributes = message.get_attributes(),
+        name = message.name,
+        rust_name = message.get_rust_name(),
+        fields = join(fields.iter().enumerate().map(|(index, field)| {
+            match field.oneof_index {
+                // Oneofs are declared once, in place of their first member.
+                Some(oneof_index) => if is_first_oneof_member(message.descriptor, index) {
+                    gen_oneof_field(message, oneof_index)
+                } else {
+                    Ok(String::new())
+                },
+                None => gen_field(context, field),
+            }
+        }))?,
+        matches = join(fields.iter().map(|field| match field.oneof_index {
+            Some(oneof_index) => gen_oneof_match(context, message, oneof_index, field),
+            None => gen_match(context, field),
+        }))?,
+        // The sink is only passed on to the functions reading messages.
+        sink = if fields.iter().any(|field| has_message_values(context, field)) {
+            "sink"
+        } else {
+            "_sink"
+        },
+        reader_matches = join(fields.iter().map(|field| match field.oneof_index {
+            Some(oneof_index) => gen_oneof_reader_match(message, oneof_index, field),
+            None => gen_reader_match(context, field),
+        }))?,
+        descriptor = gen_descriptor(context, message)?,
+        writes = join(fields.iter().map(|field| match field.oneof_index {
+            Some(oneof_index) => gen_oneof_write(message, oneof_index, field),
+            None => gen_write(context, field),
+        }))?,
+        check_required = gen_check_required(fields),
+        binary_matches = join(fields
+            .iter()
+            .map(|field| gen_binary_match(context, message, field)))?,
+        binary_writes = join(fields
+            .iter()
+            .map(|field| gen_binary_write(context, message, field)))?,
+        check_initialized = gen_check_initialized(context, message),
+        set_json_field = gen_set_json_field(context, message)?,
+        json_writes = join(fields.iter().map(|field| gen_json_write(context, message, field)))?,
+        accessors = gen_accessors(context, message)?,
+        oneofs = join((0..message.descriptor.oneof_decls.len())
+            .map(|oneof_index| gen_oneof_enum(context, message, oneof_index)))?,
+    ))
+}
+
+fn gen_descriptor(context: &Context, message: &Scoped<MessageDescriptor>) -> Result<String> {
+    let rust_name = message.get_rust_name();
+    let nested_messages = message
+        .descriptor
+        .nested_types
+        .iter()
+        .filter(|nested| !nested.map_entry)
+        .map(|nested| format!("{}_{}::descriptor", rust_name, nested.name))
+        .collect::<Vec<_>>();
+    let nested_enums = message
+        .descriptor
+        .enum_types
+        .iter()
+        .map(|nested| format!("{}_{}::descriptor", rust_name, nested.name))
+        .collect::<Vec<_>>();
+    Ok(format!(
+        "        static DESCRIPTOR: pblite::MessageDescriptor = pblite::MessageDescriptor {{
+            name: {name:?},
+            full_name: {full_name:?},
+            fields: &[
+{fields}            ],
+            nested_messages: &[{nested_messages}],
+            nested_enums: &[{nested_enums}],
+        }};
+        &DESCRIPTOR
+",
+        name = message.name,
+        full_name = context.get_full_name(&message.name),
+        fields = join(message
+            .descriptor
+            .fields
+            .iter()
+            .map(|field| gen_field_descriptor(context, message, field)))?,
+        nested_messages = nested_messages.join(", "),
+        nested_enums = nested_enums.join(", "),
+    ))
+}
+
+fn gen_field_descriptor(
+    context: &Context,
+    message: &Scoped<MessageDescriptor>,
+    field: &FieldDescriptor,
+) -> Result<String> {
+    let (key_type, value_field) = match context.get_map_entry(field) {
+        Some(&(ref key, ref value)) => (Some(get_descriptor_field_type(key)?), value),
+        None => (None, field),
+    };
+    let type_descriptor = |field_type| -> Result<String> {
+        Ok(if value_field.field_type == field_type {
+            format!("Some({}::descriptor)", get_rust_element_type(context, value_field)?)
+        } else {
+            "None".to_owned()
+        })
+    };
+    Ok(format!(
+        "                pblite::FieldDescriptor {{
+                    name: {name:?},
+                    number: {number},
+                    label: pblite::Label::{label:?},
+                    field_type: pblite::FieldType::{field_type},
+                    message_type: {message_type},
+                    enum_type: {enum_type},
+                    oneof: {oneof},
+                    map_key_type: {map_key_type},
+                }},
+",
+        name = field.name,
+        number = field.number,
+        label = field.label,
+        field_type = get_descriptor_field_type(value_field)?,
+        message_type = type_descriptor(FieldType::Message)?,
+        enum_type = type_descriptor(FieldType::Enum)?,
+        oneof = match field.oneof_index {
+            Some(oneof_index) => format!("Some({:?})", message.descriptor.oneof_decls[oneof_index]),
+            None => "None".to_owned(),
+        },
+        map_key_type = match key_type {
+            Some(key_type) => format!("Some(pblite::FieldType::{})", key_type),
+            None => "None".to_owned(),
+        },
+    ))
+}
+
+fn get_descriptor_field_type(field: &FieldDescriptor) -> Result<&'static str> {
+    Ok(match field.field_type {
+        FieldType::Double => "Double",
+        FieldType::Float => "Float",
+        FieldType::Int64 => "Int64",
+        FieldType::Uint64 => "Uint64",
+        FieldType::Int32 => "Int32",
+        FieldType::Fixed64 => "Fixed64",
+        FieldType::Fixed32 => "Fixed32",
+        FieldType::Bool => "Bool",
+        FieldType::String => "String",
+        FieldType::Message => "Message",
+        FieldType::Bytes => "Bytes",
+        FieldType::Uint32 => "Uint32",
+        FieldType::Enum => "Enum",
+        FieldType::Sfixed32 => "Sfixed32",
+        FieldType::Sfixed64 => "Sfixed64",
+        FieldType::Sint32 => "Sint32",
+        FieldType::Sint64 => "Sint64",
+        FieldType::Group => return Err(ErrorKind::Unsupported("groups".to_owned()).into()),
+    })
+}
+
+fn is_first_oneof_member(message: &MessageDescriptor, index: usize) -> bool {
+    let oneof_index = message.fields[index].oneof_index;
+    !message.fields[..index]
+        .iter()
+        .any(|field| field.oneof_index == oneof_index)
+}
+
+fn get_oneof_rust_name(message: &Scoped<MessageDescriptor>, oneof_index: usize) -> String {
+    format!(
+        "{}_{}",
+        message.get_rust_name(),
+        get_enum_name(&message.descriptor.oneof_decls[oneof_index])
+    )
+}
+
+fn gen_oneof_field(message: &Scoped<MessageDescriptor>, oneof_index: usize) -> Result<String> {
+    let raw_name = &message.descriptor.oneof_decls[oneof_index];
+    Ok(format!(
+        r#"    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "{raw_name}")]
+    pub {name}: Option<{rust_type}>,
+"#,
+        raw_name = raw_name,
+        name = sanitize_name(raw_name),
+        rust_type = get_oneof_rust_name(message, oneof_index),
+    ))
+}
+
+fn gen_oneof_match(
+    context: &Context,
+    message: &Scoped<MessageDescriptor>,
+    oneof_index: usize,
+    field: &FieldDescriptor,
+) -> Result<String> {
+    if field.label != Label::Optional {
+        return Err(ErrorKind::Unsupported("repeated oneof fields".to_owned()).into());
+    }
+    let (reader, sink) = get_value_reader(context, field, "read_oneof");
+    Ok(format!(
+        "            {number} => pblite::{reader}(&mut self.{name}, field_value, {sink}&{method}, \
+         {rust_type}::{variant})?,\n",
+        number = field.number - 1,
+        name = sanitize_name(&message.descriptor.oneof_decls[oneof_index]),
+        reader = reader,
+        sink = sink,
+        method = get_read_method(field)?,
+        rust_type = get_oneof_rust_name(message, oneof_index),
+        variant = get_enum_name(&field.name),
+    ))
+}
+
+fn gen_oneof_reader_match(
+    message: &Scoped<MessageDescriptor>,
+    oneof_index: usize,
+    field: &FieldDescriptor,
+) -> Result<String> {
+    Ok(format!(
+        "            {number} => reader.read_oneof(&mut self.{name}, &{method}, \
+         {rust_type}::{variant})?,\n",
+        number = field.number - 1,
+        name = sanitize_name(&message.descriptor.oneof_decls[oneof_index]),
+        method = get_reader_method(field)?,
+        rust_type = get_oneof_rust_name(message, oneof_index),
+        variant = get_enum_name(&field.name),
+    ))
+}
+
+fn gen_oneof_write(
+    message: &Scoped<MessageDescriptor>,
+    oneof_index: usize,
+    field: &FieldDescriptor,
+) -> Result<String> {
+    // Only the member that is set is written, so at most one field of the oneof is encoded.
+    Ok(format!(
+        "            ({number}, match self.{name} {{ Some({rust_type}::{variant}(ref value)) => \
+         {method}(value), _ => serde_json::Value::Null }}),\n",
+        number = field.number - 1,
+        name = sanitize_name(&message.descriptor.oneof_decls[oneof_index]),
+        method = get_write_method(field)?,
+        rust_type = get_oneof_rust_name(message, oneof_index),
+        variant = get_enum_name(&field.name),
+    ))
+}
+
+fn gen_oneof_enum(
+    context: &Context,
+    message: &Scoped<MessageDescriptor>,
+    oneof_index: usize,
+) -> Result<String> {
+    let variants = message
+        .descriptor
+        .fields
+        .iter()
+        .filter(|field| field.oneof_index == Some(oneof_index))
+        .map(|field| {
+            Ok(format!(
+                "    #[serde(rename = \"{raw_name}\")]\n    {variant}({rust_type}),\n",
+                raw_name = field.name,
+                variant = get_enum_name(&field.name),
+                rust_type = get_rust_element_type(context, field)?,
+            ))
+        });
+    Ok(format!(
+        "
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[allow(non_camel_case_types)]
+pub enum {name} {{
+{variants}}}
+",
+        name = get_oneof_rust_name(message, oneof_index),
+        variants = join(variants)?,
+    ))
+}
+
+fn gen_field(context: &Context, field: &FieldDescriptor) -> Result<String> {
+    Ok(format!(
+        r#"    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "{raw_name}")]
+    pub {name}: {rust_type},
+"#,
+        raw_name = field.name,
+        name = sanitize_name(&field.name),
+        rust_type = get_rust_type(context, field)?,
+    ))
+}
+
+/// Returns whether a field's values are messages, or for map fields, whether the map's values
+/// are.
+fn has_message_values(context: &Context, field: &FieldDescriptor) -> bool {
+    let value = context.get_map_entry(field).map_or(field, |&(_, ref value)| value);
+    value.field_type == FieldType::Message
+}
+
+/// Returns the name of the pblite function `reader` for a field, and the sink argument to pass
+/// to it. Messages are read with the `*_with_sink` functions, so that errors skipped within them
+/// are recorded when decoding leniently.
+fn get_value_reader(
+    context: &Context,
+    field: &FieldDescriptor,
+    reader: &str,
+) -> (String, &'static str) {
+    if has_message_values(context, field) {
+        (format!("{}_with_sink", reader), "sink, ")
+    } else {
+        (reader.to_owned(), "")
+    }
+}
+
+fn gen_match(context: &Context, field: &FieldDescriptor) -> Result<String> {
+    if let Some(&(ref key, ref value)) = context.get_map_entry(field) {
+        let (reader, sink) = get_value_reader(context, field, "read_map");
+        return Ok(format!(
+            "            {number} => self.{name} = pblite::{reader}(field_value, {sink}\
+             &{key_method}, &{value_method})?,\n",
+            number = field.number - 1,
+            name = sanitize_name(&field.name),
+            reader = reader,
+            sink = sink,
+            key_method = get_read_method(key)?,
+            value_method = get_read_method(value)?,
+        ));
+    }
+    let (reader, sink) = get_value_reader(
+        context,
+        field,
+        match field.label {
+            // Required fields are checked once the whole message has been read.
+            Label::Optional | Label::Required => "read_optional",
+            Label::Repeated => "read_array",
+        },
+    );
+    Ok(format!(
+        "            {number} => self.{name} = pblite::{reader}(field_value, {sink}&{method})?,\n",
+        number = field.number - 1,
+        name = sanitize_name(&field.name),
+        reader = reader,
+        sink = sink,
+        method = get_read_method(field)?,
+    ))
+}
+
+fn gen_reader_match(context: &Context, field: &FieldDescriptor) -> Result<String> {
+    if let Some(&(ref key, ref value)) = context.get_map_entry(field) {
+        return Ok(format!(
+            "            {number} => self.{name} = reader.read_map(&{key_method}, \
+             &{value_method})?,\n",
+            number = field.number - 1,
+            name = sanitize_name(&field.name),
+            key_method = get_reader_method(key)?,
+            value_method = get_reader_method(value)?,
+        ));
+    }
+    let reader = match field.label {
+        Label::Optional | Label::Required => "read_optional",
+        Label::Repeated => "read_array",
+    };
+    Ok(format!(
+        "            {number} => self.{name} = reader.{reader}(&{method})?,\n",
+        number = field.number - 1,
+        name = sanitize_name(&field.name),
+        reader = reader,
+        method = get_reader_method(field)?,
+    ))
+}
+
+fn gen_write(context: &Context, field: &FieldDescriptor) -> Result<String> {
+    if let Some(&(ref key, ref value)) = context.get_map_entry(field) {
+        return Ok(format!(
+            "            ({number}, pblite::write_map(&self.{name}, &{key_method}, \
+             &{value_method})),\n",
+            number = field.number - 1,
+            name = sanitize_name(&field.name),
+            key_method = get_write_method(key)?,
+            value_method = get_write_method(value)?,
+        ));
+    }
+    let writer = match field.label {
+        Label::Optional | Label::Required => "write_optional",
+        Label::Repeated => "write_array",
+    };
+    Ok(format!(
+        "            ({number}, pblite::{writer}(&self.{name}, &{method})),\n",
+        number = field.number - 1,
+        name = sanitize_name(&field.name),
+        writer = writer,
+        method = get_write_method(field)?,
+    ))
+}
+
+fn gen_binary_match(
+    context: &Context,
+    message: &Scoped<MessageDescriptor>,
+    field: &FieldDescriptor,
+) -> Result<String> {
+    let number = field.number;
+    let name = sanitize_name(&field.name);
+    let is_message = field.field_type == FieldType::Message;
+    if let Some(oneof_index) = field.oneof_index {
+        let rust_type = get_oneof_rust_name(message, oneof_index);
+        let variant = get_enum_name(&field.name);
+        let set = format!(
+            "self.{name} = Some({rust_type}::{variant}({method}(field_value)?))",
+            name = sanitize_name(&message.descriptor.oneof_decls[oneof_index]),
+            rust_type = rust_type,
+            variant = variant,
+            method = get_wire_read_method(field)?,
+        );
+        if !is_message {
+            return Ok(format!("            {} => {},\n", number, set));
+        }
+        // Like other singular message fields, a message that is already set is merged.
+        return Ok(format!(
+            "            {number} => if let Some({rust_type}::{variant}(ref mut value)) = \
+             self.{name} {{\n                wire::merge_message(value, field_value)?\n            \
+             }} else {{\n                {set}\n            }}\n",
+            number = number,
+            name = sanitize_name(&message.descriptor.oneof_decls[oneof_index]),
+            rust_type = rust_type,
+            variant = variant,
+            set = set,
+        ));
+    }
+    if let Some(&(ref key, ref value)) = context.get_map_entry(field) {
+        return Ok(format!(
+            "            {number} => wire::read_map_entry(&mut self.{name}, field_value, \
+             &{key_method}, &{value_method})?,\n",
+            number = number,
+            name = name,
+            key_method = get_wire_read_method(key)?,
+            value_method = get_wire_read_method(value)?,
+        ));
+    }
+    Ok(match field.label {
+        Label::Repeated => format!(
+            "            {number} => wire::read_repeated(&mut self.{name}, field_value, \
+             wire::WireType::{wire_type}, &{method})?,\n",
+            number = number,
+            name = name,
+            wire_type = get_wire_type(field)?,
+            method = get_wire_read_method(field)?,
+        ),
+        Label::Optional | Label::Required if is_message => format!(
+            "            {number} => wire::merge_message(\
+             self.{name}.get_or_insert_with(Default::default), field_value)?,\n",
+            number = number,
+            name = name,
+        ),
+        Label::Optional | Label::Required => format!(
+            "            {number} => self.{name} = Some({method}(field_value)?),\n",
+            number = number,
+            name = name,
+            method = get_wire_read_method(field)?,
+        ),
+    })
+}
+
+/// Generates `check_initialized` for messages with message fields, whose required fields are
+/// checked once the outer message has been read.
+fn gen_check_initialized(context: &Context, message: &Scoped<MessageDescriptor>) -> String {
+    let is_message = |field: &FieldDescriptor| field.field_type == FieldType::Message;
+    let checks: String = message
+        .descriptor
+        .fields
+        .iter()
+        .filter_map(|field| {
+            let name = sanitize_name(&field.name);
+            if let Some(oneof_index) = field.oneof_index {
+                if !is_message(field) {
+                    return None;
+                }
+                return Some(format!(
+                    "        if let Some({rust_type}::{variant}(ref value)) = self.{name} {{\n            \
+                     wire::check_message(value)?;\n        }}\n",
+                    name = sanitize_name(&message.descriptor.oneof_decls[oneof_index]),
+                    rust_type = get_oneof_rust_name(message, oneof_index),
+                    variant = get_enum_name(&field.name),
+                ));
+            }
+            let method = match context.get_map_entry(field) {
+                Some(&(_, ref value)) if is_message(value) => "check_map",
+                Some(_) => return None,
+                None if !is_message(field) => return None,
+                None if field.label == Label::Repeated => "check_repeated",
+                None => "check_optional",
+            };
+            Some(format!("        wire::{}(&self.{})?;\n", method, name))
+        })
+        .collect();
+    if checks.is_empty() {
+        return String::new();
+    }
+    format!(
+        "    fn check_initialized(&self) -> wire::Result<()> {{\n        \
+         self.check_required()?;\n{checks}        Ok(())\n    }}\n",
+        checks = checks,
+    )
+}
+
+fn gen_binary_write(
+    context: &Context,
+    message: &Scoped<MessageDescriptor>,
+    field: &FieldDescriptor,
+) -> Result<String> {
+    let number = field.number;
+    let name = sanitize_name(&field.name);
+    if let Some(oneof_index) = field.oneof_index {
+        return Ok(format!(
+            "        if let Some({rust_type}::{variant}(ref value)) = self.{name} {{\n            \
+             writer.write_field({number}, {method}(value));\n        }}\n",
+            number = number,
+            name = sanitize_name(&message.descriptor.oneof_decls[oneof_index]),
+            rust_type = get_oneof_rust_name(message, oneof_index),
+            variant = get_enum_name(&field.name),
+            method = get_wire_write_method(field)?,
+        ));
+    }
+    if let Some(&(ref key, ref value)) = context.get_map_entry(field) {
+        return Ok(format!(
+            "        wire::write_map(writer, {number}, &self.{name}, &{key_method}, \
+             &{value_method});\n",
+            number = number,
+            name = name,
+            key_method = get_wire_write_method(key)?,
+            value_method = get_wire_write_method(value)?,
+        ));
+    }
+    let writer = match field.label {
+        Label::Repeated if context.is_packed(field) => "write_packed",
+        Label::Repeated => "write_repeated",
+        Label::Optional | Label::Required => "write_optional",
+    };
+    Ok(format!(
+        "        wire::{writer}(writer, {number}, &self.{name}, &{method});\n",
+        writer = writer,
+        number = number,
+        name = name,
+        method = get_wire_write_method(field)?,
+    ))
+}
+
+fn gen_set_json_field(context: &Context, message: &Scoped<MessageDescriptor>) -> Result<String> {
+    let fields = &message.descriptor.fields;
+    if fields.is_empty() {
+        return Ok("    fn set_json_field(&mut self, _name: &str, _field_value: &serde_json::Value) \
+                   -> json::Result<bool> {\n        Ok(false)\n    }\n"
+            .to_owned());
+    }
+    Ok(format!(
+        "    fn set_json_field(&mut self, name: &str, field_value: &serde_json::Value) \
+         -> json::Result<bool> {{
+        match name {{
+{matches}            _ => return Ok(false),
+        }};
+        Ok(true)
+    }}
+",
+        matches = join(fields.iter().map(|field| gen_json_match(context, message, field)))?,
+    ))
+}
+
+fn gen_json_match(
+    context: &Context,
+    message: &Scoped<MessageDescriptor>,
+    field: &FieldDescriptor,
+) -> Result<String> {
+    // Parsers accept both the JSON name and the original field name.
+    let json_name = get_json_name(&field.name);
+    let pattern = if json_name == field.name {
+        format!("{:?}", json_name)
+    } else {
+        format!("{:?} | {:?}", json_name, field.name)
+    };
+    let name = sanitize_name(&field.name);
+    let read = if let Some(oneof_index) = field.oneof_index {
+        format!(
+            "json::read_oneof(&mut self.{name}, field_value, &{method}, {rust_type}::{variant})?",
+            name = sanitize_name(&message.descriptor.oneof_decls[oneof_index]),
+            method = get_json_read_method(field)?,
+            rust_type = get_oneof_rust_name(message, oneof_index),
+            variant = get_enum_name(&field.name),
+        )
+    } else if let Some(&(ref key, ref value)) = context.get_map_entry(field) {
+        format!(
+            "self.{name} = json::read_map(field_value, &{key_method}, &{value_method})?",
+            name = name,
+            key_method = get_json_read_method(key)?,
+            value_method = get_json_read_method(value)?,
+        )
+    } else {
+        format!(
+            "self.{name} = json::{reader}(field_value, &{method})?",
+            name = name,
+            reader = match field.label {
+                Label::Repeated => "read_array",
+                Label::Optional | Label::Required => "read_optional",
+            },
+            method = get_json_read_method(field)?,
+        )
+    };
+    Ok(format!("            {} => {},\n", pattern, read))
+}
+
+fn gen_json_write(
+    context: &Context,
+    message: &Scoped<MessageDescriptor>,
+    field: &FieldDescriptor,
+) -> Result<String> {
+    let name = sanitize_name(&field.name);
+    let write = if let Some(oneof_index) = field.oneof_index {
+        format!(
+            "match self.{name} {{ Some({rust_type}::{variant}(ref value)) => {method}(value), \
+             _ => serde_json::Value::Null }}",
+            name = sanitize_name(&message.descriptor.oneof_decls[oneof_index]),
+            rust_type = get_oneof_rust_name(message, oneof_index),
+            variant = get_enum_name(&field.name),
+            method = get_json_write_method(field)?,
+        )
+    } else if let Some(&(ref key, ref value)) = context.get_map_entry(field) {
+        format!(
+            "json::write_map(&self.{name}, &{key_method}, &{value_method})",
+            name = name,
+            key_method = get_json_write_method(key)?,
+            value_method = get_json_write_method(value)?,
+        )
+    } else {
+        format!(
+            "json::{writer}(&self.{name}, &{method})",
+            name = name,
+            writer = match field.label {
+                Label::Repeated => "write_array",
+                Label::Optional | Label::Required => "write_optional",
+            },
+            method = get_json_write_method(field)?,
+        )
+    };
+    Ok(format!("            ({:?}, {}),\n", get_json_name(&field.name), write))
+}
+
+fn gen_check_required(fields: &[FieldDescriptor]) -> String {
+    let checks: String = fields
+        .iter()
+        .filter(|field| field.label == Label::Required)
+        .map(|field| {
+            format!(
+                "        pblite::check_required(&self.{name}, \"{raw_name}\")?;\n",
+                name = sanitize_name(&field.name),
+                raw_name = field.name,
+            )
+        })
+        .collect();
+    if checks.is_empty() {
+        return String::new();
+    }
+    format!(
+        "    fn check_required(&self) -> pblite::Result<()> {{\n{checks}        Ok(())\n    }}\n",
+        checks = checks,
+    )
+}
+
+fn gen_accessors(context: &Context, message: &Scoped<MessageDescriptor>) -> Result<String> {
+    let accessors = join(message
+        .descriptor
+        .fields
+        .iter()
+        .filter(|field| field.oneof_index.is_none())
+        .map(|field| gen_accessor(context, field)))?;
+    if accessors.is_empty() {
+        return Ok(accessors);
+    }
+    Ok(format!(
+        "impl {rust_name} {{\n{accessors}}}\n",
+        rust_name = message.get_rust_name(),
+        accessors = accessors,
+    ))
+}
+
+/// Generates a method returning the value of a singular scalar or enum field, or its default.
+fn gen_accessor(context: &Context, field: &FieldDescriptor) -> Result<String> {
+    if field.label == Label::Repeated || field.field_type == FieldType::Message {
+        return Ok(String::new());
+    }
+    let (return_type, value) = match (field.field_type, &field.default_value) {
+        (FieldType::String, default_value) => (
+            "&str".to_owned(),
+            format!(
+                "self.{name}.as_ref().map_or({default:?}, String::as_str)",
+                name = sanitize_name(&field.name),
+                default = default_value.as_ref().map_or("", |value| value.as_str()),
+            ),
+        ),
+        (FieldType::Bytes, default_value) => {
+            // Like in descriptors from protoc, bytes defaults are escaped.
+            let default = match default_value {
+                &Some(ref default_value) => unescape_bytes(default_value).ok_or_else(|| {
+                    ErrorKind::InvalidDescriptor(format!(
+                        "invalid default value '{}' for field '{}'",
+                        default_value, field.name
+                    ))
+                })?,
+                &None => Vec::new(),
+            };
+            (
+                "&[u8]".to_owned(),
+                format!(
+                    "self.{name}.as_ref().map_or(&{default:?}[..], Vec::as_slice)",
+                    name = sanitize_name(&field.name),
+                    default = default,
+                ),
+            )
+        }
+        (_, default_value) => {
+            let default = match default_value {
+                &Some(ref default_value) => {
+                    format!("unwrap_or({})", gen_default_value(context, field, default_value)?)
+                }
+                &None => "unwrap_or_default()".to_owned(),
+            };
+            (
+                get_rust_element_type(context, field)?,
+                format!(
+                    "self.{name}{clone}.{default}",
+                    name = sanitize_name(&field.name),
+                    // Enums are not Copy.
+                    clone = if field.field_type == FieldType::Enum { ".clone()" } else { "" },
+                    default = default,
+                ),
+            )
+        }
+    };
+    Ok(format!(
+        "    pub fn {name}(&self) -> {return_type} {{\n        {value}\n    }}\n",
+        name = sanitize_name(&field.name),
+        return_type = return_type,
+        value = value,
+    ))
+}
+
+/// Returns a Rust expression for the declared default value of a scalar or enum field.
+fn gen_default_value(
+    context: &Context,
+    field: &FieldDescriptor,
+    default_value: &str,
+) -> Result<String> {
+    let invalid = || -> ::Error {
+        ErrorKind::InvalidDescriptor(format!(
+            "invalid default value '{}' for field '{}'",
+            default_value, field.name
+        )).into()
+    };
+    Ok(match field.field_type {
+        FieldType::Bool => match default_value {
+            "true" | "false" => default_value.to_owned(),
+            _ => return Err(invalid()),
+        },
+        FieldType::Double | FieldType::Float => {
+            let float_type = get_rust_element_type(context, field)?;
+            // Finite values are written as parsed, so that they are valid literals in range.
+            let value = match (default_value, field.field_type) {
+                ("inf", _) => return Ok(format!("::std::{}::INFINITY", float_type)),
+                ("-inf", _) => return Ok(format!("::std::{}::NEG_INFINITY", float_type)),
+                ("nan", _) => return Ok(format!("::std::{}::NAN", float_type)),
+                (_, FieldType::Float) => default_value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|value| value.is_finite())
+                    .map(|value| format!("{:?}", value)),
+                _ => default_value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|value| value.is_finite())
+                    .map(|value| format!("{:?}", value)),
+            };
+            format!("{}{}", value.ok_or_else(invalid)?, float_type)
+        }
+        FieldType::Enum => {
+            let type_name = field
+                .type_name
+                .as_ref()
+                .ok_or(ErrorKind::UnresolvedType(field.name.to_owned()))?;
+            let is_value = context
+                .enum_values
+                .get(type_name)
+                .map_or(false, |names| names.contains(default_value));
+            if !is_value {
+                return Err(invalid());
+            }
+            format!(
+                "{}::{}",
+                get_rust_element_type(context, field)?,
+                get_enum_name(default_value)
+            )
+        }
+        _ => {
+            // Integers are written as parsed, so that they are valid literals of the field's type.
+            let value = match get_rust_element_type(context, field)?.as_str() {
+                "u32" => default_value.parse::<u32>().ok().map(|value| value.to_string()),
+                "u64" => default_value.parse::<u64>().ok().map(|value| value.to_string()),
+                "i32" => default_value.parse::<i32>().ok().map(|value| value.to_string()),
+                "i64" => default_value.parse::<i64>().ok().map(|value| value.to_string()),
+                _ => None,
+            };
+            value.ok_or_else(invalid)?
+        }
+    })
+}
+
+/// Returns the bytes of text with C-style escape sequences, as written by protoc for bytes
+/// defaults, or `None` if an escape sequence is invalid.
+fn unescape_bytes(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut input = text.bytes().peekable();
+    while let Some(byte) = input.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let escaped = input.next()?;
+        bytes.push(match escaped {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'v' => 0x0b,
+            b'\\' | b'\'' | b'"' | b'?' => escaped,
+            _ if (escaped as char).is_digit(8) => {
+                // Up to three octal digits.
+                let mut value = (escaped - b'0') as u32;
+                for _ in 0..2 {
+                    match input.peek().and_then(|&digit| (digit as char).to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            input.next();
+                        }
+                        None => break,
+                    }
+                }
+                if value > 0xff {
+                    return None;
+                }
+                value as u8
+            }
+            b'x' | b'X' => {
+                // Up to two hex digits.
+                let mut value = 0;
+                let mut digit_count = 0;
+                while digit_count < 2 {
+                    match input.peek().and_then(|&digit| (digit as char).to_digit(16)) {
+                        Some(digit) => {
+                            value = value * 16 + digit;
+                            digit_count += 1;
+                            input.next();
+                        }
+                        None => break,
+                    }
+                }
+                if digit_count == 0 {
+                    return None;
+                }
+                value as u8
+            }
+            _ => return None,
+        });
+    }
+    Some(bytes)
+}
+
+fn gen_enum(context: &Context, enum_descriptor: &Scoped<EnumDescriptor>) -> String {
+    let rust_name = enum_descriptor.get_rust_name();
+    format!(
+        "
+#[derive(Debug, PartialEq, Clone, Serialize)]
+{attributes}pub enum {name} {{
+{values}    Unknown(u32),
+}}
+impl Enum for {name} {{
+    fn descriptor() -> &'static pblite::EnumDescriptor {{
+        static DESCRIPTOR: pblite::EnumDescriptor = pblite::EnumDescriptor {{
+            name: {raw_name:?},
+            full_name: {full_name:?},
+            values: &[
+{value_descriptors}            ],
+        }};
+        &DESCRIPTOR
+    }}
+    fn from_u32(value: u32) -> pblite::Result<Self> {{
+        match value {{
+{matches}            _ => Ok({name}::Unknown(value)),
+        }}
+    }}
+    fn to_u32(&self) -> u32 {{
+        match self {{
+{to_matches}            &{name}::Unknown(value) => value,
+        }}
+    }}
+}}
+impl json::Enum for {name} {{
+    fn from_name(name: &str) -> Option<Self> {{
+        match name {{
+{name_matches}            _ => None,
+        }}
+    }}
+    fn to_name(&self) -> Option<&'static str> {{
+        match self {{
+{to_name_matches}            &{name}::Unknown(_) => None,
+        }}
+    }}
+}}
+impl Default for {name} {{
+    fn default() -> Self {{
+        {default}
+    }}
+}}
+",
+        attributes = enum_descriptor.get_attributes(),
+        name = rust_name,
+        raw_name = enum_descriptor.name,
+        full_name = context.get_full_name(&enum_descriptor.name),
+        value_descriptors = gen_enum_lines(
+            &rust_name,
+            enum_descriptor.descriptor,
+            gen_enum_value_descriptor,
+        ),
+        values = gen_enum_lines(&rust_name, enum_descriptor.descriptor, gen_enum_value),
+        matches = gen_enum_lines(&rust_name, enum_descriptor.descriptor, gen_enum_match),
+        to_matches = gen_enum_lines(&rust_name, enum_descriptor.descriptor, gen_enum_to_match),
+        name_matches = gen_enum_lines(&rust_name, enum_descriptor.descriptor, gen_enum_name_match),
+        to_name_matches = gen_enum_lines(
+            &rust_name,
+            enum_descriptor.descriptor,
+            gen_enum_to_name_match,
+        ),
+        // Like proto2, the default is the first value declared.
+        default = match enum_descriptor.descriptor.values.first() {
+            Some(value) => format!("{}::{}", rust_name, get_enum_name(&value.name)),
+            None => format!("{}::Unknown(0)", rust_name),
+        },
+    )
+}
+
+fn gen_enum_lines(
+    rust_name: &str,
+    enum_descriptor: &EnumDescriptor,
+    gen_line: fn(&str, &EnumValueDescriptor) -> String,
+) -> String {
+    enum_descriptor
+        .values
+        .iter()
+        .map(|value| gen_line(rust_name, value))
+        .collect()
+}
+
+fn gen_enum_value(_rust_name: &str, value: &EnumValueDescriptor) -> String {
+    format!("    {value_name},\n", value_name = get_enum_name(&value.name))
+}
+
+fn gen_enum_value_descriptor(_rust_name: &str, value: &EnumValueDescriptor) -> String {
+    format!(
+        "                pblite::EnumValueDescriptor {{ name: {name:?}, number: {number} }},\n",
+        name = value.name,
+        number = value.number,
+    )
+}
+
+fn gen_enum_match(rust_name: &str, value: &EnumValueDescriptor) -> String {
+    format!(
+        "            {number} => Ok({name}::{value_name}),\n",
+        number = value.number as u32,
+        name = rust_name,
+        value_name = get_enum_name(&value.name),
+    )
+}
+
+fn gen_enum_to_match(rust_name: &str, value: &EnumValueDescriptor) -> String {
+    format!(
+        "            &{name}::{value_name} => {number},\n",
+        number = value.number as u32,
+        name = rust_name,
+        value_name = get_enum_name(&value.name),
+    )
+}
+
+fn gen_enum_name_match(rust_name: &str, value: &EnumValueDescriptor) -> String {
+    format!(
+        "            {raw_name:?} => Some({name}::{value_name}),\n",
+        raw_name = value.name,
+        name = rust_name,
+        value_name = get_enum_name(&value.name),
+    )
+}
+
+fn gen_enum_to_name_match(rust_name: &str, value: &EnumValueDescriptor) -> String {
+    format!(
+        "            &{name}::{value_name} => Some({raw_name:?}),\n",
+        raw_name = value.name,
+        name = rust_name,
+        value_name = get_enum_name(&value.name),
+    )
+}
+
+fn join<I: Iterator<Item = Result<String>>>(lines: I) -> Result<String> {
+    lines.collect()
+}
+
+fn get_rust_type(context: &Context, field: &FieldDescriptor) -> Result<String> {
+    if let Some(&(ref key, ref value)) = context.get_map_entry(field) {
+        return Ok(format!(
+            "Option<::std::collections::BTreeMap<{}, {}>>",
+            get_rust_element_type(context, key)?,
+            get_rust_element_type(context, value)?
+        ));
+    }
+    let rust_type = get_rust_element_type(context, field)?;
+    Ok(match field.label {
+        Label::Repeated => format!("Option<Vec<{}>>", rust_type),
+        Label::Optional | Label::Required => format!("Option<{}>", rust_type),
+    })
+}
+
+fn get_rust_element_type(context: &Context, field: &FieldDescriptor) -> Result<String> {
+    Ok(match field.field_type {
+        FieldType::String => "String",
+        FieldType::Bytes => "Vec<u8>",
+        FieldType::Bool => "bool",
+        FieldType::Uint32 | FieldType::Fixed32 => "u32",
+        FieldType::Uint64 | FieldType::Fixed64 => "u64",
+        FieldType::Int32 | FieldType::Sint32 | FieldType::Sfixed32 => "i32",
+        FieldType::Int64 | FieldType::Sint64 | FieldType::Sfixed64 => "i64",
+        FieldType::Double => "f64",
+        FieldType::Float => "f32",
+        FieldType::Message | FieldType::Enum => {
+            return context.get_type_name(field
+                .type_name
+                .as_ref()
+                .ok_or(ErrorKind::UnresolvedType(field.name.to_owned()))?)
+        }
+        FieldType::Group => return Err(ErrorKind::Unsupported("groups".to_owned()).into()),
+    }.to_owned())
+}
+
+fn get_read_method(field: &FieldDescriptor) -> Result<String> {
+    if field.field_type == FieldType::Message {
+        return Ok("pblite::read_message_with_sink".to_owned());
+    }
+    Ok(format!("pblite::read_{}", get_method_type_name(field)?))
+}
+
+fn get_reader_method(field: &FieldDescriptor) -> Result<String> {
+    Ok(format!("pblite::Reader::read_{}", get_method_type_name(field)?))
+}
+
+fn get_write_method(field: &FieldDescriptor) -> Result<String> {
+    Ok(format!("pblite::write_{}", get_method_type_name(field)?))
+}
+
+fn get_method_type_name(field: &FieldDescriptor) -> Result<&'static str> {
+    Ok(match field.field_type {
+        FieldType::String => "string",
+        FieldType::Bytes => "bytes",
+        FieldType::Bool => "bool",
+        FieldType::Uint32 | FieldType::Fixed32 => "uint32",
+        FieldType::Uint64 | FieldType::Fixed64 => "uint64",
+        FieldType::Int32 | FieldType::Sint32 | FieldType::Sfixed32 => "int32",
+        FieldType::Int64 | FieldType::Sint64 | FieldType::Sfixed64 => "int64",
+        FieldType::Double => "double",
+        FieldType::Float => "float",
+        FieldType::Message => "message",
+        FieldType::Enum => "enum",
+        FieldType::Group => return Err(ErrorKind::Unsupported("groups".to_owned()).into()),
+    })
+}
+
+fn get_json_read_method(field: &FieldDescriptor) -> Result<String> {
+    Ok(format!("json::read_{}", get_method_type_name(field)?))
+}
+
+fn get_json_write_method(field: &FieldDescriptor) -> Result<String> {
+    Ok(format!("json::write_{}", get_method_type_name(field)?))
+}
+
+/// Returns the lowerCamelCase name of a field in the canonical JSON mapping, eg. "fooBar".
+fn get_json_name(name: &str) -> String {
+    let mut json_name = String::new();
+    let mut capitalize = false;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            json_name.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+fn get_wire_read_method(field: &FieldDescriptor) -> Result<String> {
+    Ok(format!("wire::read_{}", get_wire_method_type_name(field)?))
+}
+
+fn get_wire_write_method(field: &FieldDescriptor) -> Result<String> {
+    Ok(format!("wire::write_{}", get_wire_method_type_name(field)?))
+}
+
+/// Like `get_method_type_name`, but distinguishing types with the same Rust type but a different
+/// binary encoding.
+fn get_wire_method_type_name(field: &FieldDescriptor) -> Result<&'static str> {
+    Ok(match field.field_type {
+        FieldType::Fixed32 => "fixed32",
+        FieldType::Fixed64 => "fixed64",
+        FieldType::Sint32 => "sint32",
+        FieldType::Sint64 => "sint64",
+        FieldType::Sfixed32 => "sfixed32",
+        FieldType::Sfixed64 => "sfixed64",
+        _ => get_method_type_name(field)?,
+    })
+}
+
+fn get_wire_type(field: &FieldDescriptor) -> Result<&'static str> {
+    Ok(match field.field_type {
+        FieldType::Double | FieldType::Fixed64 | FieldType::Sfixed64 => "Fixed64",
+        FieldType::Float | FieldType::Fixed32 | FieldType::Sfixed32 => "Fixed32",
+        FieldType::String | FieldType::Bytes | FieldType::Message => "LengthDelimited",
+        FieldType::Group => return Err(ErrorKind::Unsupported("groups".to_owned()).into()),
+        _ => "Varint",
+    })
+}
+
+fn get_enum_name(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().expect("word is not empty");
+            first.to_uppercase().chain(chars.flat_map(|c| c.to_lowercase())).collect::<String>()
+        })
+        .collect()
+}
+
+fn sanitize_name(name: &str) -> String {
+    if RUST_KEYWORDS.contains(&name) {
+        format!("{}_pb", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use generator;
+    use parser;
+
+    #[test]
+    fn test_get_enum_name() {
+        assert_eq!(generator::get_enum_name("EXAMPLE_ENUM_NAME"), "ExampleEnumName");
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(generator::sanitize_name("foo"), "foo");
+        assert_eq!(generator::sanitize_name("type"), "type_pb");
+    }
+
+    #[test]
+    fn test_generate() {
+        let file = parser::parse(
+            "message Foo { optional string type = 1; repeated Bar bar = 3; }\n\
+             enum Bar { BAR_ONE = 1; }",
+            "foo.proto",
+        ).unwrap();
+        let output = generator::generate(&file).unwrap();
+        assert!(output.starts_with("// automatically generated from foo.proto\n"));
+        assert!(output.contains("    pub type_pb: Option<String>,\n"));
+        assert!(output.contains("    pub bar: Option<Vec<Bar>>,\n"));
+        assert!(output.contains("        _sink: &mut pblite::WarningSink,\n"));
+        assert!(output.contains(
+            "            2 => self.bar = pblite::read_array(field_value, &pblite::read_enum)?,\n"
+        ));
+        assert!(output.contains(
+            "            2 => self.bar = reader.read_array(&pblite::Reader::read_enum)?,\n"
+        ));
+        assert!(output.contains(
+            "            (2, pblite::write_array(&self.bar, &pblite::write_enum)),\n"
+        ));
+        assert!(output.contains("            1 => Ok(Bar::BarOne),\n"));
+    }
+
+    #[test]
+    fn test_generate_descriptor() {
+        let file = parser::parse(
+            "package foo;\n\
+             message Foo {\n\
+               message Inner {}\n\
+               repeated Bar bar = 3;\n\
+               map<string, Inner> inners = 4;\n\
+               oneof choice { string baz = 5; }\n\
+             }\n\
+             enum Bar { BAR_ONE = 1; }",
+            "foo.proto",
+        ).unwrap();
+        let output = generator::generate(&file).unwrap();
+        assert!(output.contains("            name: \"Foo\",\n            full_name: \"foo.Foo\",\n"));
+        assert!(output.contains(
+            "                pblite::FieldDescriptor {\n\
+             \x20                   name: \"bar\",\n\
+             \x20                   number: 3,\n\
+             \x20                   label: pblite::Label::Repeated,\n\
+             \x20                   field_type: pblite::FieldType::Enum,\n\
+             \x20                   message_type: None,\n\
+             \x20                   enum_type: Some(Bar::descriptor),\n\
+             \x20                   oneof: None,\n\
+             \x20                   map_key_type: None,\n"
+        ));
+        assert!(output.contains(
+            "                    field_type: pblite::FieldType::Message,\n\
+             \x20                   message_type: Some(Foo_Inner::descriptor),\n\
+             \x20                   enum_type: None,\n\
+             \x20                   oneof: None,\n\
+             \x20                   map_key_type: Some(pblite::FieldType::String),\n"
+        ));
+        assert!(output.contains("                    oneof: Some(\"choice\"),\n"));
+        assert!(output.contains("            nested_messages: &[Foo_Inner::descriptor],\n"));
+        assert!(output.contains("            nested_enums: &[],\n"));
+        assert!(output.contains(
+            "                pblite::EnumValueDescriptor { name: \"BAR_ONE\", number: 1 },\n"
+        ));
+    }
+
+    #[test]
+    fn test_generate_nested() {
+        let file = parser::parse(
+            "package foo.bar;\n\
+             message Outer {\n\
+               message Inner { enum Kind { KIND_ONE = 1; } }\n\
+               optional Inner inner = 1;\n\
+               optional Inner.Kind kind = 2;\n\
+               optional .foo.bar.Outer outer = 3;\n\
+             }",
+            "foo.proto",
+        ).unwrap();
+        let output = generator::generate(&file).unwrap();
+        assert!(output.contains("pub struct Outer {"));
+        assert!(output.contains("#[allow(non_camel_case_types)]\npub struct Outer_Inner {"));
+        assert!(output.contains("#[allow(non_camel_case_types)]\npub enum Outer_Inner_Kind {"));
+        assert!(output.contains("    pub inner: Option<Outer_Inner>,\n"));
+        assert!(output.contains("    pub kind: Option<Outer_Inner_Kind>,\n"));
+        assert!(output.contains("    pub outer: Option<Outer>,\n"));
+        assert!(output.contains("        sink: &mut pblite::WarningSink,\n"));
+        assert!(output.contains(
+            "            0 => self.inner = pblite::read_optional_with_sink(field_value, sink, \
+             &pblite::read_message_with_sink)?,\n"
+        ));
+        assert!(output.contains("        \"Outer.Inner\"\n"));
+        assert!(output.contains("            1 => Ok(Outer_Inner_Kind::KindOne),\n"));
+    }
+
+    #[test]
+    fn test_generate_oneof() {
+        let file = parser::parse(
+            "message Foo { oneof choice { string bar = 1; Foo baz = 2; } }",
+            "foo.proto",
+        ).unwrap();
+        let output = generator::generate(&file).unwrap();
+        assert!(output.contains("    pub choice: Option<Foo_Choice>,\n"));
+        assert!(output.contains("pub enum Foo_Choice {\n"));
+        assert!(output.contains("    Bar(String),\n"));
+        assert!(output.contains("    Baz(Foo),\n"));
+        assert!(output.contains(
+            "            1 => pblite::read_oneof_with_sink(&mut self.choice, field_value, sink, \
+             &pblite::read_message_with_sink, Foo_Choice::Baz)?,\n"
+        ));
+        assert!(output.contains(
+            "            1 => reader.read_oneof(&mut self.choice, &pblite::Reader::read_message, \
+             Foo_Choice::Baz)?,\n"
+        ));
+        assert_eq!(output.matches("pub choice").count(), 1);
+    }
+
+    #[test]
+    fn test_generate_map() {
+        let file = parser::parse("message Foo { map<uint32, Foo> foo_bar = 1; }", "foo.proto")
+            .unwrap();
+        let output = generator::generate(&file).unwrap();
+        assert!(output.contains(
+            "    pub foo_bar: Option<::std::collections::BTreeMap<u32, Foo>>,\n"
+        ));
+        assert!(output.contains(
+            "            0 => self.foo_bar = pblite::read_map_with_sink(field_value, sink, \
+             &pblite::read_uint32, &pblite::read_message_with_sink)?,\n"
+        ));
+        assert!(output.contains(
+            "            0 => self.foo_bar = reader.read_map(&pblite::Reader::read_uint32, \
+             &pblite::Reader::read_message)?,\n"
+        ));
+        assert!(output.contains(
+            "            (0, pblite::write_map(&self.foo_bar, &pblite::write_uint32, \
+             &pblite::write_message)),\n"
+        ));
+        assert!(!output.contains("FooBarEntry"));
+    }
+
+    #[test]
+    fn test_generate_binary() {
+        let file = parser::parse(
+            "message Foo {\n\
+               optional sint32 foo = 1;\n\
+               repeated fixed64 bar = 2 [packed = true];\n\
+               repeated string baz = 3;\n\
+               oneof choice { Foo qux = 4; }\n\
+               optional Foo quux = 5;\n\
+             }",
+            "foo.proto",
+        ).unwrap();
+        let output = generator::generate(&file).unwrap();
+        // Generated messages serialize their field names, so they only implement `Deserialize`
+        // as pblite through `pblite_serde::Proto`.
+        assert!(!output.contains("serde::Deserialize"));
+        assert!(output.contains("impl wire::Message for Foo {\n"));
+        assert!(output.contains(
+            "            1 => self.foo = Some(wire::read_sint32(field_value)?),\n"
+        ));
+        assert!(output.contains(
+            "            2 => wire::read_repeated(&mut self.bar, field_value, \
+             wire::WireType::Fixed64, &wire::read_fixed64)?,\n"
+        ));
+        assert!(output.contains(
+            "            4 => if let Some(Foo_Choice::Qux(ref mut value)) = self.choice {\n                \
+             wire::merge_message(value, field_value)?\n            } else {\n                \
+             self.choice = Some(Foo_Choice::Qux(wire::read_message(field_value)?))\n            }\n"
+        ));
+        assert!(output.contains(
+            "            5 => wire::merge_message(self.quux.get_or_insert_with(Default::default), \
+             field_value)?,\n"
+        ));
+        assert!(output.contains(
+            "            _ => wire::read_unknown_field(&mut self.binary_unknown_fields, number, \
+             field_value),\n"
+        ));
+        assert!(output.contains(
+            "    fn check_initialized(&self) -> wire::Result<()> {\n        \
+             self.check_required()?;\n        \
+             if let Some(Foo_Choice::Qux(ref value)) = self.choice {\n            \
+             wire::check_message(value)?;\n        }\n        \
+             wire::check_optional(&self.quux)?;\n        Ok(())\n    }\n"
+        ));
+        assert!(output.contains("        wire::write_optional(writer, 1, &self.foo, &wire::write_sint32);\n"));
+        assert!(output.contains("        wire::write_packed(writer, 2, &self.bar, &wire::write_fixed64);\n"));
+        assert!(output.contains("        wire::write_repeated(writer, 3, &self.baz, &wire::write_string);\n"));
+        assert!(output.contains(
+            "        if let Some(Foo_Choice::Qux(ref value)) = self.choice {\n            \
+             writer.write_field(4, wire::write_message(value));\n        }\n"
+        ));
+    }
+
+    #[test]
+    fn test_generate_packed_by_default() {
+        let file = parser::parse(
+            "syntax = \"proto3\"; message Foo { repeated int32 foo = 1; }",
+            "foo.proto",
+        ).unwrap();
+        let output = generator::generate(&file).unwrap();
+        assert!(output.contains("        wire::write_packed(writer, 1, &self.foo, &wire::write_int32);\n"));
+    }
+
+    #[test]
+    fn test_get_json_name() {
+        assert_eq!(generator::get_json_name("foo_bar_baz"), "fooBarBaz");
+        assert_eq!(generator::get_json_name("i18n_data"), "i18nData");
+        assert_eq!(generator::get_json_name("e164"), "e164");
+    }
+
+    #[test]
+    fn test_generate_json() {
+        let file = parser::parse(
+            "message Foo {\n\
+               optional int64 foo_bar = 1;\n\
+               repeated Baz baz = 2;\n\
+               oneof choice { string qux = 3; }\n\
+             }\n\
+             enum Baz { BAZ_ONE = 1; }",
+            "foo.proto",
+        ).unwrap();
+        let output = generator::generate(&file).unwrap();
+        assert!(output.contains("impl json::Message for Foo {\n"));
+        assert!(output.contains(
+            "            \"fooBar\" | \"foo_bar\" => \
+             self.foo_bar = json::read_optional(field_value, &json::read_int64)?,\n"
+        ));
+        assert!(output.contains(
+            "            \"baz\" => self.baz = json::read_array(field_value, &json::read_enum)?,\n"
+        ));
+        assert!(output.contains(
+            "            \"qux\" => json::read_oneof(&mut self.choice, field_value, \
+             &json::read_string, Foo_Choice::Qux)?,\n"
+        ));
+        assert!(output.contains(
+            "            (\"fooBar\", json::write_optional(&self.foo_bar, &json::write_int64)),\n"
+        ));
+        assert!(output.contains("            \"BAZ_ONE\" => Some(Baz::BazOne),\n"));
+        assert!(output.contains("            &Baz::BazOne => Some(\"BAZ_ONE\"),\n"));
+    }
+
+    #[test]
+    fn test_generate_other_package_unsupported() {
+        let mut file = parser::parse("message Foo { optional Foo foo = 1; }", "foo.proto").unwrap();
+        file.message_types[0].fields[0].type_name = Some(".other.Foo".to_owned());
+        assert_eq!(
+            generator::generate(&file).err().expect("expected error").description(),
+            "unresolved type"
+        );
+    }
+
+    #[test]
+    fn test_generate_required() {
+        let file = parser::parse("message Foo { required string foo = 1; }", "foo.proto").unwrap();
+        let output = generator::generate(&file).unwrap();
+        assert!(output.contains("    pub foo: Option<String>,\n"));
+        assert!(output.contains(
+            "            0 => self.foo = pblite::read_optional(field_value, &pblite::read_string)?,\n"
+        ));
+        assert!(output.contains("        pblite::check_required(&self.foo, \"foo\")?;\n"));
+    }
+
+    #[test]
+    fn test_generate_defaults() {
+        let file = parser::parse(
+            "message Foo {\n\
+               optional uint32 foo = 1 [default = 5];\n\
+               optional string bar = 2 [default = \"bar\"];\n\
+               optional Baz baz = 3 [default = BAZ_TWO];\n\
+               optional double qux = 4 [default = -inf];\n\
+               optional bool quux = 5;\n\
+               optional Foo foo_message = 6;\n\
+               optional uint64 big = 7 [default = 18446744073709551615];\n\
+               optional sint32 small = 8 [default = -0x10];\n\
+               optional float ratio = 9 [default = 1e-3];\n\
+               optional bytes data = 10 [default = \"a\\nb\\\\\"];\n\
+             }\n\
+             enum Baz { BAZ_ONE = 1; BAZ_TWO = 2; }",
+            "foo.proto",
+        ).unwrap();
+        let output = generator::generate(&file).unwrap();
+        assert!(output.contains("    pub fn foo(&self) -> u32 {\n        self.foo.unwrap_or(5)\n"));
+        assert!(output.contains(
+            "    pub fn bar(&self) -> &str {\n        \
+             self.bar.as_ref().map_or(\"bar\", String::as_str)\n"
+        ));
+        assert!(output.contains(
+            "    pub fn baz(&self) -> Baz {\n        self.baz.clone().unwrap_or(Baz::BazTwo)\n"
+        ));
+        assert!(output.contains("        self.qux.unwrap_or(::std::f64::NEG_INFINITY)\n"));
+        assert!(output.contains("        self.quux.unwrap_or_default()\n"));
+        assert!(!output.contains("pub fn foo_message"));
+        assert!(output.contains("impl Default for Baz {\n    fn default() -> Self {\n        Baz::BazOne\n"));
+        assert!(output.contains("        self.big.unwrap_or(18446744073709551615)\n"));
+        assert!(output.contains("        self.small.unwrap_or(-16)\n"));
+        assert!(output.contains("        self.ratio.unwrap_or(0.001f32)\n"));
+        assert!(output.contains(
+            "        self.data.as_ref().map_or(&[97, 10, 98, 92][..], Vec::as_slice)\n"
+        ));
+    }
+
+    #[test]
+    fn test_unescape_bytes() {
+        assert_eq!(
+            generator::unescape_bytes("a\\001\\xff\\x7\\a\\\\\\\"\\'\\?\\0\\18"),
+            Some(vec![b'a', 1, 0xff, 7, 7, b'\\', b'"', b'\'', b'?', 0, 1, b'8'])
+        );
+        assert_eq!(generator::unescape_bytes("\\q"), None);
+        assert_eq!(generator::unescape_bytes("\\x"), None);
+        assert_eq!(generator::unescape_bytes("\\400"), None);
+        assert_eq!(generator::unescape_bytes("a\\"), None);
+    }
+
+    #[test]
+    fn test_generate_invalid_default() {
+        let invalid = [
+            "message Foo { optional bool foo = 1 [default = 5]; }",
+            "message Foo { optional uint32 foo = 1 [default = -1]; }",
+            "message Foo { optional int32 foo = 1 [default = 2147483648]; }",
+            "message Foo { optional int64 foo = 1 [default = 9223372036854775808]; }",
+            "message Foo { optional float foo = 1 [default = 1e39]; }",
+            "message Foo { optional Bar foo = 1 [default = BAR_TWO]; } enum Bar { BAR_ONE = 1; }",
+        ];
+        for proto in &invalid {
+            let file = parser::parse(proto, "foo.proto").unwrap();
+            assert_eq!(
+                generator::generate(&file).err().expect("expected error").description(),
+                "invalid descriptor",
+                "{}",
+                proto
+            );
+        }
+    }
+}
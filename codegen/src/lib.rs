@@ -0,0 +1,113 @@
+//! Generates pblite message types from .proto files.
+//!
+//! Intended to be called from a build script:
+//!
+//! ```no_run
+//! extern crate hangups_codegen;
+//!
+//! fn main() {
+//!     hangups_codegen::compile_protos(&["src/example.proto"]).unwrap();
+//! }
+//! ```
+//!
+//! Each .proto file is written to `OUT_DIR` as a Rust file with the same stem, for use with
+//! `include!`. The generated code expects the including crate to have `pblite`, `json` and
+//! `wire` modules at its root.
+
+#[macro_use]
+extern crate error_chain;
+
+pub mod descriptor;
+pub mod descriptor_set;
+pub mod generator;
+pub mod parser;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+error_chain! {
+    errors {
+        Parse(line: usize, message: String) {
+            description("parse error"),
+            display("line {}: {}", line, message),
+        }
+        UnresolvedType(name: String) {
+            description("unresolved type"),
+            display("type '{}' is not defined", name),
+        }
+        InvalidDescriptor(message: String) {
+            description("invalid descriptor"),
+            display("invalid descriptor: {}", message),
+        }
+        Unsupported(feature: String) {
+            description("unsupported feature"),
+            display("{} are not supported", feature),
+        }
+    }
+    foreign_links {
+        Io(::std::io::Error);
+        Var(::std::env::VarError);
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn write_file(out_dir: &Path, file_name: &str, contents: &str) -> Result<()> {
+    let stem = Path::new(file_name)
+        .file_stem()
+        .ok_or(format!("invalid file name: {}", file_name))?;
+    let mut out_path = out_dir.join(stem);
+    out_path.set_extension("rs");
+    File::create(&out_path)?.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+fn get_out_dir() -> Result<PathBuf> {
+    Ok(PathBuf::from(env::var("OUT_DIR")?))
+}
+
+/// Generates code for .proto files into `OUT_DIR`.
+pub fn compile_protos<P: AsRef<Path>>(protos: &[P]) -> Result<()> {
+    compile_protos_to(protos, &get_out_dir()?)
+}
+
+/// Generates code for .proto files into `out_dir`.
+pub fn compile_protos_to<P: AsRef<Path>>(protos: &[P], out_dir: &Path) -> Result<()> {
+    for proto in protos {
+        let path = proto.as_ref();
+        let name = path.to_string_lossy().into_owned();
+        || -> Result<_> {
+            let source = String::from_utf8(read_file(path)?).chain_err(|| "file is not utf8")?;
+            let file = parser::parse(&source, &name)?;
+            write_file(out_dir, &name, &generator::generate(&file)?)
+        }()
+            .chain_err(|| format!("failed to compile {}", name))?;
+        println!("cargo:rerun-if-changed={}", name);
+    }
+    Ok(())
+}
+
+/// Generates code for every file in a serialized FileDescriptorSet into `OUT_DIR`.
+pub fn compile_descriptor_set<P: AsRef<Path>>(path: P) -> Result<()> {
+    compile_descriptor_set_to(path, &get_out_dir()?)
+}
+
+/// Generates code for every file in a serialized FileDescriptorSet into `out_dir`.
+pub fn compile_descriptor_set_to<P: AsRef<Path>>(path: P, out_dir: &Path) -> Result<()> {
+    let path = path.as_ref();
+    let files = descriptor_set::parse(&read_file(path)?)
+        .chain_err(|| format!("failed to read {}", path.display()))?;
+    for file in files {
+        let contents = generator::generate(&file)
+            .chain_err(|| format!("failed to compile {}", file.name))?;
+        write_file(out_dir, &file.name, &contents)?;
+    }
+    println!("cargo:rerun-if-changed={}", path.display());
+    Ok(())
+}
@@ -0,0 +1,1048 @@
+use descriptor::{EnumDescriptor, EnumValueDescriptor, FieldDescriptor, FieldType, FileDescriptor,
+                 Label, MessageDescriptor};
+use std::collections::HashMap;
+use {ErrorKind, Result};
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Ident(String),
+    Int(String),
+    Float(String),
+    /// A string's bytes, which may be any bytes after escape sequences are replaced.
+    Str(Vec<u8>),
+    Symbol(char),
+}
+
+struct Tokenizer<'a> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>,
+    line: usize,
+}
+impl<'a> Tokenizer<'a> {
+    fn new(source: &'a str) -> Self {
+        Tokenizer {
+            chars: source.chars().peekable(),
+            line: 1,
+        }
+    }
+
+    fn error(&self, message: &str) -> ::Error {
+        ErrorKind::Parse(self.line, message.to_owned()).into()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<()> {
+        loop {
+            match self.chars.peek().cloned() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('/') => {
+                    self.bump();
+                    match self.bump() {
+                        Some('/') => while self.chars.peek().map_or(false, |&c| c != '\n') {
+                            self.bump();
+                        },
+                        Some('*') => {
+                            let mut previous = None;
+                            loop {
+                                match self.bump() {
+                                    Some('/') if previous == Some('*') => break,
+                                    Some(c) => previous = Some(c),
+                                    None => return Err(self.error("unterminated comment")),
+                                }
+                            }
+                        }
+                        _ => return Err(self.error("expected comment")),
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.chars.peek().cloned() {
+            if !predicate(c) {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+        s
+    }
+
+    /// Reads up to `max_count` digits in `radix`, returning their value and count.
+    fn read_digits(&mut self, radix: u32, max_count: usize) -> (u32, usize) {
+        let (mut value, mut count) = (0, 0);
+        while count < max_count {
+            match self.chars.peek().and_then(|c| c.to_digit(radix)) {
+                Some(digit) => {
+                    value = value * radix + digit;
+                    count += 1;
+                    self.bump();
+                }
+                None => break,
+            }
+        }
+        (value, count)
+    }
+
+    /// Reads a string up to `quote`, replacing escape sequences like protoc.
+    fn read_string(&mut self, quote: char) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let push_char = |bytes: &mut Vec<u8>, c: char| {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes())
+        };
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(bytes),
+                Some('\\') => match self.bump() {
+                    Some('a') => bytes.push(0x07),
+                    Some('b') => bytes.push(0x08),
+                    Some('f') => bytes.push(0x0c),
+                    Some('n') => bytes.push(b'\n'),
+                    Some('r') => bytes.push(b'\r'),
+                    Some('t') => bytes.push(b'\t'),
+                    Some('v') => bytes.push(0x0b),
+                    Some(c @ '\\') | Some(c @ '?') | Some(c @ '\'') | Some(c @ '"') => bytes.push(c as u8),
+                    Some(c) if c.is_digit(8) => {
+                        // Up to three octal digits.
+                        let (value, count) = self.read_digits(8, 2);
+                        let value = c.to_digit(8).expect("octal digit") * 8u32.pow(count as u32) + value;
+                        if value > 0xff {
+                            return Err(self.error("octal escape sequence out of range"));
+                        }
+                        bytes.push(value as u8)
+                    }
+                    Some('x') | Some('X') => match self.read_digits(16, 2) {
+                        (_, 0) => return Err(self.error("expected hex digits")),
+                        (value, _) => bytes.push(value as u8),
+                    },
+                    Some(c @ 'u') | Some(c @ 'U') => {
+                        let digit_count = if c == 'u' { 4 } else { 8 };
+                        match self.read_digits(16, digit_count) {
+                            (value, count) if count == digit_count => match ::std::char::from_u32(value) {
+                                Some(c) => push_char(&mut bytes, c),
+                                None => return Err(self.error("invalid unicode escape sequence")),
+                            },
+                            _ => return Err(self.error("expected hex digits")),
+                        }
+                    }
+                    _ => return Err(self.error("invalid escape sequence")),
+                },
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => push_char(&mut bytes, c),
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<(Token, usize)>> {
+        self.skip_whitespace_and_comments()?;
+        let line = self.line;
+        let c = match self.chars.peek().cloned() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let token = if c.is_alphabetic() || c == '_' {
+            Token::Ident(self.take_while(|c| c.is_alphanumeric() || c == '_' || c == '.'))
+        } else if c.is_digit(10) {
+            let mut number = self.take_while(|c| c.is_alphanumeric() || c == '.');
+            let is_hex = number.starts_with("0x") || number.starts_with("0X");
+            if !is_hex && (number.ends_with('e') || number.ends_with('E')) {
+                // Signed exponent, eg. "1e-5".
+                if let Some(sign) = self.chars.peek().cloned().filter(|&c| c == '+' || c == '-') {
+                    self.bump();
+                    number.push(sign);
+                    number.push_str(&self.take_while(|c| c.is_digit(10)));
+                }
+            }
+            if number.contains('.') || (!is_hex && (number.contains('e') || number.contains('E'))) {
+                Token::Float(number)
+            } else {
+                Token::Int(number)
+            }
+        } else if c == '"' || c == '\'' {
+            self.bump();
+            Token::Str(self.read_string(c)?)
+        } else if c == '.' {
+            // Leading dot of a fully-qualified type name.
+            self.bump();
+            let ident = self.take_while(|c| c.is_alphanumeric() || c == '_' || c == '.');
+            Token::Ident(format!(".{}", ident))
+        } else {
+            self.bump();
+            Token::Symbol(c)
+        };
+        Ok(Some((token, line)))
+    }
+}
+
+/// Field options that affect code generation.
+#[derive(Default)]
+struct FieldOptions {
+    default_value: Option<String>,
+    packed: Option<bool>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+impl Parser {
+    fn new(source: &str) -> Result<Self> {
+        let mut tokenizer = Tokenizer::new(source);
+        let mut tokens = Vec::new();
+        while let Some(token) = tokenizer.next_token()? {
+            tokens.push(token);
+        }
+        Ok(Parser {
+            tokens: tokens,
+            position: 0,
+        })
+    }
+
+    fn error(&self, message: &str) -> ::Error {
+        let line = self.tokens
+            .get(self.position)
+            .or(self.tokens.last())
+            .map_or(0, |&(_, line)| line);
+        ErrorKind::Parse(line, message.to_owned()).into()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|&(ref token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.peek().cloned().ok_or(self.error("unexpected end of file"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&Token::Symbol(symbol))
+    }
+
+    fn is_next_symbol(&self, symbol: char) -> bool {
+        self.tokens.get(self.position + 1).map(|&(ref token, _)| token)
+            == Some(&Token::Symbol(symbol))
+    }
+
+    fn is_ident(&self, ident: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Ident(ref s)) => s == ident,
+            _ => false,
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<()> {
+        match self.next()? {
+            Token::Symbol(c) if c == symbol => Ok(()),
+            _ => {
+                self.position -= 1;
+                Err(self.error(&format!("expected '{}'", symbol)))
+            }
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Ident(s) => Ok(s),
+            _ => {
+                self.position -= 1;
+                Err(self.error("expected identifier"))
+            }
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Str(bytes) => self.to_utf8(bytes),
+            _ => {
+                self.position -= 1;
+                Err(self.error("expected string"))
+            }
+        }
+    }
+
+    fn expect_int(&mut self) -> Result<i64> {
+        let negative = if self.is_symbol('-') {
+            self.position += 1;
+            true
+        } else {
+            false
+        };
+        let number = match self.next()? {
+            Token::Int(s) => parse_int(&s)
+                .filter(|&number| number <= i64::max_value() as u64)
+                .ok_or(self.error("invalid integer"))? as i64,
+            _ => {
+                self.position -= 1;
+                return Err(self.error("expected integer"));
+            }
+        };
+        Ok(if negative { -number } else { number })
+    }
+
+    /// Skips tokens up to and including the next ';' or balanced block.
+    fn skip_statement(&mut self) -> Result<()> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Token::Symbol(';') if depth == 0 => return Ok(()),
+                Token::Symbol('{') => depth += 1,
+                Token::Symbol('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Skips a constant, which may be an aggregate enclosed in braces.
+    fn skip_constant(&mut self) -> Result<()> {
+        if self.is_symbol('{') {
+            let mut depth = 0;
+            loop {
+                match self.next()? {
+                    Token::Symbol('{') => depth += 1,
+                    Token::Symbol('}') => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if self.is_symbol('-') {
+            self.position += 1;
+        }
+        self.next().map(|_| ())
+    }
+
+    fn parse_option_name(&mut self) -> Result<String> {
+        if self.is_symbol('(') {
+            self.position += 1;
+            let name = self.expect_ident()?;
+            self.expect_symbol(')')?;
+            let suffix = match self.peek() {
+                Some(&Token::Ident(ref s)) if s.starts_with('.') => s.to_owned(),
+                _ => String::new(),
+            };
+            if !suffix.is_empty() {
+                self.position += 1;
+            }
+            Ok(format!("({}){}", name, suffix))
+        } else {
+            self.expect_ident()
+        }
+    }
+
+    /// Converts a string's bytes to text, which must be UTF-8.
+    fn to_utf8(&self, bytes: Vec<u8>) -> Result<String> {
+        String::from_utf8(bytes).or(Err(self.error("invalid UTF-8 in string")))
+    }
+
+    /// Parses a scalar constant into its text, as stored in `FieldDescriptor::default_value`.
+    fn parse_constant(&mut self) -> Result<String> {
+        let sign = if self.is_symbol('-') {
+            self.position += 1;
+            "-"
+        } else {
+            ""
+        };
+        match self.next()? {
+            Token::Int(s) => parse_int(&s)
+                .map(|number| format!("{}{}", sign, number))
+                .ok_or(self.error("invalid integer")),
+            Token::Float(s) | Token::Ident(s) => Ok(format!("{}{}", sign, s)),
+            Token::Str(bytes) => if sign.is_empty() {
+                self.to_utf8(bytes)
+            } else {
+                Err(self.error("invalid constant"))
+            },
+            _ => Err(self.error("invalid constant")),
+        }
+    }
+
+    /// Parses a field's default value into its text. Like in descriptors from protoc, bytes
+    /// defaults are escaped, since they need not be UTF-8.
+    fn parse_default_value(&mut self, is_bytes: bool) -> Result<String> {
+        match self.peek().cloned() {
+            Some(Token::Str(bytes)) if is_bytes => {
+                self.position += 1;
+                Ok(escape_bytes(&bytes))
+            }
+            _ => self.parse_constant(),
+        }
+    }
+
+    /// Parses field options enclosed in brackets, if present, for a field of type `field_type`.
+    fn parse_field_options(&mut self, field_type: Option<FieldType>) -> Result<FieldOptions> {
+        let mut options = FieldOptions::default();
+        if !self.is_symbol('[') {
+            return Ok(options);
+        }
+        self.position += 1;
+        loop {
+            let name = self.parse_option_name()?;
+            self.expect_symbol('=')?;
+            match name.as_str() {
+                "default" => {
+                    let is_bytes = field_type == Some(FieldType::Bytes);
+                    options.default_value = Some(self.parse_default_value(is_bytes)?);
+                }
+                "packed" => match self.parse_constant()?.as_str() {
+                    "true" => options.packed = Some(true),
+                    "false" => options.packed = Some(false),
+                    _ => return Err(self.error("invalid packed option")),
+                },
+                _ => self.skip_constant()?,
+            }
+            if self.is_symbol(',') {
+                self.position += 1;
+            } else {
+                self.expect_symbol(']')?;
+                return Ok(options);
+            }
+        }
+    }
+
+    fn parse_file(&mut self, name: &str) -> Result<FileDescriptor> {
+        let mut file = FileDescriptor {
+            name: name.to_owned(),
+            ..Default::default()
+        };
+        while let Some(token) = self.peek().cloned() {
+            match token {
+                Token::Symbol(';') => self.position += 1,
+                Token::Ident(ref keyword) if keyword == "syntax" => {
+                    self.position += 1;
+                    self.expect_symbol('=')?;
+                    let syntax = self.expect_string()?;
+                    if syntax != "proto2" && syntax != "proto3" {
+                        return Err(self.error(&format!("unsupported syntax '{}'", syntax)));
+                    }
+                    file.syntax = Some(syntax);
+                    self.expect_symbol(';')?;
+                }
+                Token::Ident(ref keyword) if keyword == "package" => {
+                    self.position += 1;
+                    file.package = Some(self.expect_ident()?);
+                    self.expect_symbol(';')?;
+                }
+                Token::Ident(ref keyword) if keyword == "message" => {
+                    self.position += 1;
+                    file.message_types.push(self.parse_message()?);
+                }
+                Token::Ident(ref keyword) if keyword == "enum" => {
+                    self.position += 1;
+                    file.enum_types.push(self.parse_enum()?);
+                }
+                Token::Ident(ref keyword)
+                    if keyword == "import" || keyword == "option" || keyword == "service"
+                        || keyword == "extend" =>
+                {
+                    self.skip_statement()?
+                }
+                _ => return Err(self.error("unexpected token")),
+            }
+        }
+        Ok(file)
+    }
+
+    fn parse_message(&mut self) -> Result<MessageDescriptor> {
+        let mut message = MessageDescriptor {
+            name: self.expect_ident()?,
+            ..Default::default()
+        };
+        self.expect_symbol('{')?;
+        loop {
+            let token = self.peek().cloned().ok_or(self.error("unexpected end of file"))?;
+            match token {
+                Token::Symbol('}') => {
+                    self.position += 1;
+                    return Ok(message);
+                }
+                Token::Symbol(';') => self.position += 1,
+                Token::Ident(ref keyword) if keyword == "message" => {
+                    self.position += 1;
+                    message.nested_types.push(self.parse_message()?);
+                }
+                Token::Ident(ref keyword) if keyword == "enum" => {
+                    self.position += 1;
+                    message.enum_types.push(self.parse_enum()?);
+                }
+                Token::Ident(ref keyword) if keyword == "oneof" => {
+                    self.position += 1;
+                    self.parse_oneof(&mut message)?;
+                }
+                Token::Ident(ref keyword) if keyword == "map" && self.is_next_symbol('<') => {
+                    self.position += 1;
+                    self.parse_map_field(&mut message)?;
+                }
+                Token::Ident(ref keyword)
+                    if keyword == "option" || keyword == "reserved" || keyword == "extensions"
+                        || keyword == "extend" =>
+                {
+                    self.skip_statement()?
+                }
+                Token::Ident(_) => {
+                    let field = self.parse_field()?;
+                    message.fields.push(field);
+                }
+                _ => return Err(self.error("unexpected token")),
+            }
+        }
+    }
+
+    fn parse_oneof(&mut self, message: &mut MessageDescriptor) -> Result<()> {
+        let oneof_index = message.oneof_decls.len();
+        message.oneof_decls.push(self.expect_ident()?);
+        self.expect_symbol('{')?;
+        loop {
+            let token = self.peek().cloned().ok_or(self.error("unexpected end of file"))?;
+            match token {
+                Token::Symbol('}') => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                Token::Symbol(';') => self.position += 1,
+                Token::Ident(ref keyword) if keyword == "option" => self.skip_statement()?,
+                Token::Ident(ref keyword)
+                    if keyword == "optional" || keyword == "required" || keyword == "repeated" =>
+                {
+                    return Err(self.error("oneof fields may not have labels"))
+                }
+                Token::Ident(_) => {
+                    let mut field = self.parse_field()?;
+                    field.oneof_index = Some(oneof_index);
+                    message.fields.push(field);
+                }
+                _ => return Err(self.error("unexpected token")),
+            }
+        }
+    }
+
+    /// Parses a map field into a repeated field of a synthetic nested entry message.
+    fn parse_map_field(&mut self, message: &mut MessageDescriptor) -> Result<()> {
+        self.expect_symbol('<')?;
+        let key_type_name = self.expect_ident()?;
+        let key_type = match FieldType::from_scalar_name(&key_type_name) {
+            Some(FieldType::Double) | Some(FieldType::Float) | Some(FieldType::Bytes) | None => {
+                return Err(self.error("invalid map key type"))
+            }
+            Some(key_type) => key_type,
+        };
+        self.expect_symbol(',')?;
+        let value_type_name = self.expect_ident()?;
+        self.expect_symbol('>')?;
+        let name = self.expect_ident()?;
+        self.expect_symbol('=')?;
+        let number = self.expect_int()?;
+        self.parse_field_options(None)?;
+        self.expect_symbol(';')?;
+        let entry_name = format!("{}Entry", to_camel_case(&name));
+        let (value_type, value_type_name) = match FieldType::from_scalar_name(&value_type_name) {
+            Some(value_type) => (value_type, None),
+            None => (FieldType::Message, Some(value_type_name)),
+        };
+        message.nested_types.push(MessageDescriptor {
+            name: entry_name.to_owned(),
+            fields: vec![
+                FieldDescriptor {
+                    name: "key".to_owned(),
+                    number: 1,
+                    label: Label::Optional,
+                    field_type: key_type,
+                    type_name: None,
+                    oneof_index: None,
+                    default_value: None,
+                    packed: None,
+                },
+                FieldDescriptor {
+                    name: "value".to_owned(),
+                    number: 2,
+                    label: Label::Optional,
+                    field_type: value_type,
+                    type_name: value_type_name,
+                    oneof_index: None,
+                    default_value: None,
+                    packed: None,
+                },
+            ],
+            map_entry: true,
+            ..Default::default()
+        });
+        message.fields.push(FieldDescriptor {
+            name: name,
+            number: number as u32,
+            label: Label::Repeated,
+            field_type: FieldType::Message,
+            type_name: Some(entry_name),
+            oneof_index: None,
+            default_value: None,
+            packed: None,
+        });
+        Ok(())
+    }
+
+    fn parse_field(&mut self) -> Result<FieldDescriptor> {
+        let label = if self.is_ident("optional") {
+            self.position += 1;
+            Label::Optional
+        } else if self.is_ident("required") {
+            self.position += 1;
+            Label::Required
+        } else if self.is_ident("repeated") {
+            self.position += 1;
+            Label::Repeated
+        } else {
+            // proto3 fields without a label are optional.
+            Label::Optional
+        };
+        let type_name = self.expect_ident()?;
+        if type_name == "group" {
+            return Err(self.error("groups are not supported"));
+        }
+        let name = self.expect_ident()?;
+        self.expect_symbol('=')?;
+        let number = self.expect_int()?;
+        if number < 1 || number > 536_870_911 {
+            return Err(self.error("invalid field number"));
+        }
+        let (field_type, type_name) = match FieldType::from_scalar_name(&type_name) {
+            Some(field_type) => (field_type, None),
+            // Resolved to a message or enum once the whole file has been parsed.
+            None => (FieldType::Message, Some(type_name)),
+        };
+        let options = self.parse_field_options(Some(field_type))?;
+        self.expect_symbol(';')?;
+        Ok(FieldDescriptor {
+            name: name,
+            number: number as u32,
+            label: label,
+            field_type: field_type,
+            type_name: type_name,
+            oneof_index: None,
+            default_value: options.default_value,
+            packed: options.packed,
+        })
+    }
+
+    fn parse_enum(&mut self) -> Result<EnumDescriptor> {
+        let mut enum_descriptor = EnumDescriptor {
+            name: self.expect_ident()?,
+            ..Default::default()
+        };
+        self.expect_symbol('{')?;
+        loop {
+            let token = self.peek().cloned().ok_or(self.error("unexpected end of file"))?;
+            match token {
+                Token::Symbol('}') => {
+                    self.position += 1;
+                    return Ok(enum_descriptor);
+                }
+                Token::Symbol(';') => self.position += 1,
+                Token::Ident(ref keyword) if keyword == "option" || keyword == "reserved" => {
+                    self.skip_statement()?
+                }
+                Token::Ident(name) => {
+                    self.position += 1;
+                    self.expect_symbol('=')?;
+                    let number = self.expect_int()?;
+                    self.parse_field_options(None)?;
+                    self.expect_symbol(';')?;
+                    enum_descriptor.values.push(EnumValueDescriptor {
+                        name: name,
+                        number: number as i32,
+                    });
+                }
+                _ => return Err(self.error("unexpected token")),
+            }
+        }
+    }
+}
+
+/// Converts a field name to the name protoc uses for its map entry, eg. "foo_bar" to "FooBar".
+fn to_camel_case(name: &str) -> String {
+    let mut camel_case = String::new();
+    let mut capitalize = true;
+    for c in name.chars() {
+        if c == '_' {
+            capitalize = true;
+        } else if capitalize {
+            camel_case.extend(c.to_uppercase());
+            capitalize = false;
+        } else {
+            camel_case.push(c);
+        }
+    }
+    camel_case
+}
+
+/// Parses an unsigned integer literal, which is up to `u64::MAX` for uint64 defaults.
+fn parse_int(s: &str) -> Option<u64> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16).ok()
+    } else if s.len() > 1 && s.starts_with('0') {
+        u64::from_str_radix(&s[1..], 8).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Escapes bytes like protoc does for bytes defaults, with C-style escape sequences.
+fn escape_bytes(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'\n' => escaped.push_str("\\n"),
+            b'\r' => escaped.push_str("\\r"),
+            b'\t' => escaped.push_str("\\t"),
+            b'"' => escaped.push_str("\\\""),
+            b'\'' => escaped.push_str("\\'"),
+            b'\\' => escaped.push_str("\\\\"),
+            _ if byte >= 0x20 && byte < 0x7f => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum TypeKind {
+    Message,
+    Enum,
+}
+
+fn collect_types(
+    scope: &str,
+    messages: &[MessageDescriptor],
+    enums: &[EnumDescriptor],
+    types: &mut HashMap<String, TypeKind>,
+) {
+    for enum_descriptor in enums {
+        types.insert(format!("{}.{}", scope, enum_descriptor.name), TypeKind::Enum);
+    }
+    for message in messages {
+        let name = format!("{}.{}", scope, message.name);
+        collect_types(&name, &message.nested_types, &message.enum_types, types);
+        types.insert(name, TypeKind::Message);
+    }
+}
+
+/// Resolves a type reference the way protoc does, searching from the innermost scope outwards.
+fn resolve_type(
+    scope: &str,
+    name: &str,
+    types: &HashMap<String, TypeKind>,
+) -> Option<(String, TypeKind)> {
+    if name.starts_with('.') {
+        return types.get(name).map(|&kind| (name.to_owned(), kind));
+    }
+    let mut scope = scope.to_owned();
+    loop {
+        let candidate = format!("{}.{}", scope, name);
+        if let Some(&kind) = types.get(&candidate) {
+            return Some((candidate, kind));
+        }
+        match scope.rfind('.') {
+            Some(index) => scope.truncate(index),
+            None => return None,
+        }
+    }
+}
+
+fn resolve_message(
+    scope: &str,
+    message: &mut MessageDescriptor,
+    types: &HashMap<String, TypeKind>,
+) -> Result<()> {
+    let scope = format!("{}.{}", scope, message.name);
+    for field in &mut message.fields {
+        if let Some(type_name) = field.type_name.take() {
+            let (full_name, kind) = resolve_type(&scope, &type_name, types)
+                .ok_or(ErrorKind::UnresolvedType(type_name.to_owned()))?;
+            field.field_type = match kind {
+                TypeKind::Message => FieldType::Message,
+                TypeKind::Enum => FieldType::Enum,
+            };
+            field.type_name = Some(full_name);
+        }
+    }
+    for nested_type in &mut message.nested_types {
+        resolve_message(&scope, nested_type, types)?;
+    }
+    Ok(())
+}
+
+/// Parses the source of a .proto file into a file descriptor with fully-qualified type names.
+pub fn parse(source: &str, name: &str) -> Result<FileDescriptor> {
+    let mut file = Parser::new(source)?.parse_file(name)?;
+    let scope = match file.package {
+        Some(ref package) => format!(".{}", package),
+        None => String::new(),
+    };
+    let mut types = HashMap::new();
+    collect_types(&scope, &file.message_types, &file.enum_types, &mut types);
+    for message in &mut file.message_types {
+        resolve_message(&scope, message, &types)?;
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use descriptor::{FieldDescriptor, FieldType, Label};
+    use parser;
+
+    #[test]
+    fn test_parse_message() {
+        let file = parser::parse(
+            "syntax = \"proto2\";\n\
+             // comment\n\
+             message Foo {\n\
+               optional string bar = 1 [deprecated = true];\n\
+               /* block\n comment */\n\
+               repeated Baz baz = 2;\n\
+               reserved 3, 4;\n\
+             }\n\
+             enum Baz {\n\
+               option allow_alias = true;\n\
+               BAZ_ONE = 1;\n\
+               BAZ_TWO = 0x2;\n\
+             }\n",
+            "foo.proto",
+        ).unwrap();
+        assert_eq!(file.name, "foo.proto");
+        assert_eq!(file.package, None);
+        assert_eq!(file.message_types[0].name, "Foo");
+        assert_eq!(
+            file.message_types[0].fields,
+            vec![
+                FieldDescriptor {
+                    name: "bar".to_owned(),
+                    number: 1,
+                    label: Label::Optional,
+                    field_type: FieldType::String,
+                    type_name: None,
+                    oneof_index: None,
+                    default_value: None,
+                    packed: None,
+                },
+                FieldDescriptor {
+                    name: "baz".to_owned(),
+                    number: 2,
+                    label: Label::Repeated,
+                    field_type: FieldType::Enum,
+                    type_name: Some(".Baz".to_owned()),
+                    oneof_index: None,
+                    default_value: None,
+                    packed: None,
+                },
+            ]
+        );
+        assert_eq!(file.enum_types[0].name, "Baz");
+        assert_eq!(file.enum_types[0].values[1].name, "BAZ_TWO");
+        assert_eq!(file.enum_types[0].values[1].number, 2);
+    }
+
+    #[test]
+    fn test_parse_resolves_scopes() {
+        let file = parser::parse(
+            "package foo.bar;\n\
+             message Outer {\n\
+               message Inner {}\n\
+               optional Inner inner = 1;\n\
+               optional .foo.bar.Outer outer = 2;\n\
+               optional bar.Outer.Inner other = 3;\n\
+             }\n",
+            "foo.proto",
+        ).unwrap();
+        assert_eq!(file.package, Some("foo.bar".to_owned()));
+        let type_names: Vec<_> = file.message_types[0]
+            .fields
+            .iter()
+            .map(|field| field.type_name.clone().unwrap())
+            .collect();
+        assert_eq!(
+            type_names,
+            vec![".foo.bar.Outer.Inner", ".foo.bar.Outer", ".foo.bar.Outer.Inner"]
+        );
+    }
+
+    #[test]
+    fn test_parse_oneof() {
+        let file = parser::parse(
+            "message Foo {\n\
+               optional string foo = 1;\n\
+               oneof choice {\n\
+                 string bar = 2;\n\
+                 Foo baz = 3;\n\
+               }\n\
+             }\n",
+            "foo.proto",
+        ).unwrap();
+        let message = &file.message_types[0];
+        assert_eq!(message.oneof_decls, vec!["choice"]);
+        let oneof_indexes: Vec<_> = message.fields.iter().map(|field| field.oneof_index).collect();
+        assert_eq!(oneof_indexes, vec![None, Some(0), Some(0)]);
+        assert_eq!(message.fields[2].label, Label::Optional);
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let file = parser::parse(
+            "message Foo {\n\
+               optional int32 foo = 1 [deprecated = true, default = -0x10];\n\
+               optional double bar = 2 [default = 1.5e-3];\n\
+               optional string baz = 3 [default = \"baz\"];\n\
+               optional Qux qux = 4 [default = QUX_ONE];\n\
+               optional bool quux = 5;\n\
+               optional bytes data = 6 [default = \"a\\n\\\\\\\"\"];\n\
+               optional uint64 big = 7 [default = 0xffffffffffffffff];\n\
+             }\n\
+             enum Qux { QUX_ONE = 1; }",
+            "foo.proto",
+        ).unwrap();
+        let default_values: Vec<_> = file.message_types[0]
+            .fields
+            .iter()
+            .map(|field| field.default_value.clone())
+            .collect();
+        assert_eq!(
+            default_values,
+            vec![
+                Some("-16".to_owned()),
+                Some("1.5e-3".to_owned()),
+                Some("baz".to_owned()),
+                Some("QUX_ONE".to_owned()),
+                None,
+                Some("a\\n\\\\\\\"".to_owned()),
+                Some("18446744073709551615".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_string_escapes() {
+        let file = parser::parse(
+            "message Foo {\n\
+               optional string foo = 1 [default = \"\\a\\b\\f\\n\\r\\t\\v\\\\\\?\\'\\\"\\101\\x41\\u00e9\\U0001F600\"];\n\
+               optional bytes bar = 2 [default = '\\377\\x0\\0012'];\n\
+             }",
+            "foo.proto",
+        ).unwrap();
+        let default_values: Vec<_> = file.message_types[0]
+            .fields
+            .iter()
+            .map(|field| field.default_value.clone())
+            .collect();
+        assert_eq!(
+            default_values,
+            vec![
+                Some("\x07\x08\x0c\n\r\t\x0b\\?'\"AA\u{e9}\u{1f600}".to_owned()),
+                Some("\\377\\000\\0012".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_string_escapes() {
+        let invalid = [
+            ("\\q", "line 1: invalid escape sequence"),
+            ("\\x", "line 1: expected hex digits"),
+            ("\\u12", "line 1: expected hex digits"),
+            ("\\U00110000", "line 1: invalid unicode escape sequence"),
+            ("\\400", "line 1: octal escape sequence out of range"),
+            ("\\377", "line 1: invalid UTF-8 in string"),
+        ];
+        for &(escape, message) in &invalid {
+            let proto = format!("message Foo {{ optional string foo = 1 [default = \"{}\"]; }}", escape);
+            let result = parser::parse(&proto, "foo.proto");
+            assert_eq!(result.err().expect("expected error").to_string(), message, "{}", proto);
+        }
+    }
+
+    #[test]
+    fn test_parse_packed() {
+        let file = parser::parse(
+            "syntax = \"proto3\";\n\
+             message Foo {\n\
+               repeated int32 foo = 1 [packed = true];\n\
+               repeated int32 bar = 2 [packed = false];\n\
+               repeated int32 baz = 3;\n\
+             }\n",
+            "foo.proto",
+        ).unwrap();
+        assert_eq!(file.syntax, Some("proto3".to_owned()));
+        let packed: Vec<_> = file.message_types[0].fields.iter().map(|field| field.packed).collect();
+        assert_eq!(packed, vec![Some(true), Some(false), None]);
+    }
+
+    #[test]
+    fn test_parse_map() {
+        let file = parser::parse(
+            "package foo;\n\
+             message Foo {\n\
+               map<string, Foo> foo_bar = 1;\n\
+             }\n",
+            "foo.proto",
+        ).unwrap();
+        let message = &file.message_types[0];
+        assert_eq!(message.fields[0].name, "foo_bar");
+        assert_eq!(message.fields[0].label, Label::Repeated);
+        assert_eq!(
+            message.fields[0].type_name,
+            Some(".foo.Foo.FooBarEntry".to_owned())
+        );
+        let entry = &message.nested_types[0];
+        assert_eq!(entry.name, "FooBarEntry");
+        assert!(entry.map_entry);
+        assert_eq!(entry.fields[0].field_type, FieldType::String);
+        assert_eq!(entry.fields[1].type_name, Some(".foo.Foo".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_map_invalid_key() {
+        let result = parser::parse("message Foo { map<double, string> foo = 1; }", "foo.proto");
+        assert_eq!(
+            result.err().expect("expected error").to_string(),
+            "line 1: invalid map key type"
+        );
+    }
+
+    #[test]
+    fn test_parse_unresolved_type() {
+        let result = parser::parse("message Foo { optional Bar bar = 1; }", "foo.proto");
+        assert_eq!(
+            result.err().expect("expected error").description(),
+            "unresolved type"
+        );
+    }
+
+    #[test]
+    fn test_parse_error_line() {
+        let result = parser::parse("message Foo {\n  optional string bar = ;\n}", "foo.proto");
+        assert_eq!(
+            result.err().expect("expected error").to_string(),
+            "line 2: expected integer"
+        );
+    }
+}
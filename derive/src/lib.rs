@@ -0,0 +1,914 @@
+//! Derives pblite message and enum implementations for hand-written types, as an alternative to
+//! generating them from .proto files with `hangups_codegen`. The derive is re-exported by
+//! hangups.
+//!
+//! ```ignore
+//! use hangups::{pblite, PbliteMessage};
+//!
+//! #[derive(Debug, Default, PartialEq, PbliteMessage)]
+//! struct Participant {
+//!     #[pblite(field = 1, required)]
+//!     name: Option<String>,
+//!     #[pblite(field = 2, enumeration)]
+//!     status: Option<Status>,
+//!     #[pblite(field = 3)]
+//!     phone_numbers: Option<Vec<PhoneNumber>>,
+//!     #[pblite(unknown_fields)]
+//!     unknown_fields: pblite::UnknownFields,
+//! }
+//!
+//! #[derive(Debug, PartialEq, PbliteMessage)]
+//! enum Status {
+//!     #[pblite(value = 1)]
+//!     Active,
+//!     #[pblite(value = 2)]
+//!     Away,
+//!     #[pblite(unknown)]
+//!     Unknown(u32),
+//! }
+//! ```
+//!
+//! Fields have the same types as generated fields: `Option<T>`, `Option<Vec<T>>` for repeated
+//! fields and `Option<BTreeMap<K, V>>` for maps. Scalars are `String`, `Vec<u8>` for bytes,
+//! `bool`, `u32`, `u64`, `i32`, `i64`, `f64` and `f32`, and other types are messages unless the
+//! field is marked `enumeration`. Enum values are int32 numbers, which may be zero or negative,
+//! and an `unknown` variant keeps values that aren't listed.
+//!
+//! The types also implement serde's `Deserialize` and `Serialize` as pblite, so they can be used
+//! with `pblite_serde` and as fields of types deriving those, and shouldn't derive them too. The
+//! derived code refers to `pblite`, `pblite_serde`, `serde` and `serde_json` through
+//! `::hangups`, so crates using it only need to depend on hangups. Types within hangups itself
+//! are given `#[pblite(crate = "crate")]` to change that path.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+#[macro_use]
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Literal, Span};
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    Attribute, Data, DeriveInput, Fields, GenericArgument, LitInt, LitStr, PathArguments, Type,
+};
+
+#[proc_macro_derive(PbliteMessage, attributes(pblite))]
+pub fn derive_pblite_message(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = match syn::parse(input) {
+        Ok(input) => input,
+        Err(error) => return error.to_compile_error().into(),
+    };
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+/// Returns the implementation of `pblite::Message` for a struct or `pblite::Enum` for an enum,
+/// along with `Deserialize` and `Serialize`.
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "pblite messages can't have generic parameters",
+        ));
+    }
+    let paths = Paths::new(&parse_type_options(&input.attrs)?);
+    match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => {
+                expand_message(&input.ident, fields.named.iter(), &paths)
+            }
+            _ => Err(syn::Error::new(
+                input.ident.span(),
+                "pblite messages must have named fields",
+            )),
+        },
+        Data::Enum(ref data) => expand_enum(&input.ident, data.variants.iter(), &paths),
+        Data::Union(_) => Err(syn::Error::new(
+            input.ident.span(),
+            "pblite messages can't be unions",
+        )),
+    }
+}
+
+/// Options given in `#[pblite(...)]` attributes.
+#[derive(Default)]
+struct Options {
+    /// Field number of a field.
+    field: Option<u32>,
+    /// Value of an enum variant.
+    value: Option<i32>,
+    required: bool,
+    enumeration: bool,
+    unknown: bool,
+}
+
+/// An option in a `#[pblite(...)]` attribute, which is either a word or a name with an integer
+/// or a string. Meta items can't contain negative numbers, so these are parsed separately.
+struct OptionItem {
+    name: Ident,
+    number: Option<(i64, Span)>,
+    string: Option<LitStr>,
+}
+impl Parse for OptionItem {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        // The name may be a keyword, as in `crate = "..."`.
+        let name = input.call(Ident::parse_any)?;
+        if !input.peek(Token![=]) {
+            return Ok(OptionItem {
+                name: name,
+                number: None,
+                string: None,
+            });
+        }
+        input.parse::<Token![=]>()?;
+        if input.peek(LitStr) {
+            return Ok(OptionItem {
+                name: name,
+                number: None,
+                string: Some(input.parse()?),
+            });
+        }
+        let negative = input.peek(Token![-]);
+        if negative {
+            input.parse::<Token![-]>()?;
+        }
+        let literal: LitInt = input.parse()?;
+        if literal.value() > i64::max_value() as u64 {
+            return Err(syn::Error::new(literal.span(), "number is out of range"));
+        }
+        let number = literal.value() as i64;
+        Ok(OptionItem {
+            name: name,
+            number: Some((if negative { -number } else { number }, literal.span())),
+            string: None,
+        })
+    }
+}
+
+/// The parenthesized options of a `#[pblite(...)]` attribute.
+struct OptionList(Punctuated<OptionItem, Token![,]>);
+impl Parse for OptionList {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let content;
+        parenthesized!(content in input);
+        Ok(OptionList(content.parse_terminated(OptionItem::parse)?))
+    }
+}
+
+fn parse_option_items(attrs: &[Attribute]) -> syn::Result<Vec<OptionItem>> {
+    let mut items = Vec::new();
+    for attr in attrs {
+        if !attr.path.is_ident("pblite") {
+            continue;
+        }
+        if attr.tts.is_empty() {
+            return Err(syn::Error::new(attr.span(), "expected #[pblite(...)]"));
+        }
+        let list: OptionList = syn::parse2(attr.tts.clone())?;
+        items.extend(list.0);
+    }
+    Ok(items)
+}
+
+/// Returns the path of the hangups crate, which is `::hangups` unless it's given by
+/// `#[pblite(crate = "...")]` on the type, eg. `crate` within hangups itself.
+fn parse_type_options(attrs: &[Attribute]) -> syn::Result<syn::Path> {
+    let mut krate = parse_quote!(::hangups);
+    for item in parse_option_items(attrs)? {
+        match (item.name.to_string().as_str(), item.number, item.string) {
+            ("crate", None, Some(path)) => krate = path.parse()?,
+            _ => return Err(syn::Error::new(item.name.span(), "unknown pblite option")),
+        }
+    }
+    Ok(krate)
+}
+
+fn parse_options(attrs: &[Attribute]) -> syn::Result<Options> {
+    let mut options = Options::default();
+    for item in parse_option_items(attrs)? {
+        match (item.name.to_string().as_str(), item.number, item.string) {
+            ("field", Some((number, span)), None) => {
+                if number <= 0 || number > u32::max_value() as i64 {
+                    return Err(syn::Error::new(span, "expected a positive number"));
+                }
+                options.field = Some(number as u32);
+            }
+            ("value", Some((number, span)), None) => {
+                if number < i32::min_value() as i64 || number > i32::max_value() as i64 {
+                    return Err(syn::Error::new(span, "expected an i32 number"));
+                }
+                options.value = Some(number as i32);
+            }
+            ("required", None, None) => options.required = true,
+            ("enumeration", None, None) => options.enumeration = true,
+            ("unknown_fields", None, None) | ("unknown", None, None) => options.unknown = true,
+            _ => return Err(syn::Error::new(item.name.span(), "unknown pblite option")),
+        }
+    }
+    Ok(options)
+}
+
+/// Paths of the modules and crates used by derived code, which are all reached through the
+/// hangups crate so that crates deriving types don't need to depend on serde themselves.
+struct Paths {
+    pblite: proc_macro2::TokenStream,
+    pblite_serde: proc_macro2::TokenStream,
+    serde: proc_macro2::TokenStream,
+    serde_json: proc_macro2::TokenStream,
+}
+impl Paths {
+    fn new(krate: &syn::Path) -> Self {
+        Paths {
+            pblite: quote!(#krate::pblite),
+            pblite_serde: quote!(#krate::pblite_serde),
+            serde: quote!(#krate::serde),
+            serde_json: quote!(#krate::serde_json),
+        }
+    }
+}
+
+/// Type of a field's values, with the names of the `pblite` functions that read and write it.
+struct ValueType {
+    method_type_name: &'static str,
+    field_type: &'static str,
+    ty: Type,
+}
+impl ValueType {
+    fn new(ty: &Type, enumeration: bool) -> Self {
+        let (method_type_name, field_type) = match get_type_name(ty).as_ref().map(String::as_str) {
+            Some("String") => ("string", "String"),
+            Some("bool") => ("bool", "Bool"),
+            Some("u32") => ("uint32", "Uint32"),
+            Some("u64") => ("uint64", "Uint64"),
+            Some("i32") => ("int32", "Int32"),
+            Some("i64") => ("int64", "Int64"),
+            Some("f64") => ("double", "Double"),
+            Some("f32") => ("float", "Float"),
+            Some("Vec") if get_type_arguments(ty).len() == 1
+                && get_type_name(get_type_arguments(ty)[0]) == Some("u8".to_owned()) =>
+            {
+                ("bytes", "Bytes")
+            }
+            _ if enumeration => ("enum", "Enum"),
+            _ => ("message", "Message"),
+        };
+        ValueType {
+            method_type_name: method_type_name,
+            field_type: field_type,
+            ty: ty.clone(),
+        }
+    }
+
+    fn is_message(&self) -> bool {
+        self.method_type_name == "message"
+    }
+
+    fn get_read_method(&self, paths: &Paths) -> proc_macro2::TokenStream {
+        let pblite = &paths.pblite;
+        let method = Ident::new(&format!("read_{}", self.method_type_name), Span::call_site());
+        quote!(#pblite::#method)
+    }
+
+    fn get_reader_method(&self, paths: &Paths) -> proc_macro2::TokenStream {
+        let pblite = &paths.pblite;
+        let method = Ident::new(&format!("read_{}", self.method_type_name), Span::call_site());
+        quote!(#pblite::Reader::#method)
+    }
+
+    fn get_write_method(&self, paths: &Paths) -> proc_macro2::TokenStream {
+        let pblite = &paths.pblite;
+        let method = Ident::new(&format!("write_{}", self.method_type_name), Span::call_site());
+        quote!(#pblite::#method)
+    }
+
+    fn get_field_type(&self, paths: &Paths) -> proc_macro2::TokenStream {
+        let pblite = &paths.pblite;
+        let field_type = Ident::new(self.field_type, Span::call_site());
+        quote!(#pblite::FieldType::#field_type)
+    }
+}
+
+enum Shape {
+    Optional(ValueType),
+    Repeated(ValueType),
+    Map(ValueType, ValueType),
+}
+
+/// A field with a `#[pblite(field = N)]` attribute.
+struct Field {
+    ident: Ident,
+    number: u32,
+    required: bool,
+    shape: Shape,
+}
+
+impl Field {
+    /// Whether the field's values are messages, which are read with a warning sink.
+    fn has_message_values(&self) -> bool {
+        match self.shape {
+            Shape::Optional(ref value) | Shape::Repeated(ref value) | Shape::Map(_, ref value) => {
+                value.is_message()
+            }
+        }
+    }
+}
+
+/// Returns the last segment of a type's path, eg. "Vec" for `::std::vec::Vec<u8>`.
+fn get_type_name(ty: &Type) -> Option<String> {
+    match *ty {
+        Type::Path(ref path) if path.qself.is_none() => path.path
+            .segments
+            .iter()
+            .last()
+            .map(|segment| segment.ident.to_string()),
+        _ => None,
+    }
+}
+
+fn get_type_arguments(ty: &Type) -> Vec<&Type> {
+    let segment = match *ty {
+        Type::Path(ref path) => path.path.segments.iter().last(),
+        _ => None,
+    };
+    match segment.map(|segment| &segment.arguments) {
+        Some(&PathArguments::AngleBracketed(ref arguments)) => arguments
+            .args
+            .iter()
+            .filter_map(|argument| match *argument {
+                GenericArgument::Type(ref ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn get_shape(ty: &Type, enumeration: bool) -> Option<Shape> {
+    if get_type_name(ty) != Some("Option".to_owned()) {
+        return None;
+    }
+    let inner = match get_type_arguments(ty).as_slice() {
+        &[inner] => inner,
+        _ => return None,
+    };
+    let arguments = get_type_arguments(inner);
+    Some(match (get_type_name(inner).as_ref().map(String::as_str), arguments.as_slice()) {
+        (Some("Vec"), &[elem]) if get_type_name(elem) != Some("u8".to_owned()) => {
+            Shape::Repeated(ValueType::new(elem, enumeration))
+        }
+        (Some("BTreeMap"), &[key, value]) => Shape::Map(
+            ValueType::new(key, false),
+            ValueType::new(value, enumeration),
+        ),
+        _ => Shape::Optional(ValueType::new(inner, enumeration)),
+    })
+}
+
+fn expand_message<'a, I>(
+    name: &Ident,
+    input_fields: I,
+    paths: &Paths,
+) -> syn::Result<proc_macro2::TokenStream>
+where
+    I: Iterator<Item = &'a syn::Field>,
+{
+    let mut fields: Vec<Field> = Vec::new();
+    let mut unknown_fields = None;
+    for input_field in input_fields {
+        let ident = input_field.ident.clone().unwrap();
+        let options = parse_options(&input_field.attrs)?;
+        if options.unknown {
+            unknown_fields = Some(ident);
+            continue;
+        }
+        let number = match options {
+            Options {
+                field: Some(number),
+                value: None,
+                ..
+            } => number,
+            _ => {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "expected #[pblite(field = N)] or #[pblite(unknown_fields)]",
+                ))
+            }
+        };
+        if fields.iter().any(|field| field.number == number) {
+            return Err(syn::Error::new(
+                ident.span(),
+                format!("field number {} is used more than once", number),
+            ));
+        }
+        let shape = get_shape(&input_field.ty, options.enumeration).ok_or_else(|| {
+            syn::Error::new(
+                input_field.ty.span(),
+                "expected Option<T>, Option<Vec<T>> or Option<BTreeMap<K, V>>",
+            )
+        })?;
+        fields.push(Field {
+            ident: ident,
+            number: number,
+            required: options.required,
+            shape: shape,
+        });
+    }
+
+    let (pblite, pblite_serde, serde, serde_json) = (
+        &paths.pblite,
+        &paths.pblite_serde,
+        &paths.serde,
+        &paths.serde_json,
+    );
+    let name_str = name.to_string();
+    let descriptors = fields.iter().map(|field| gen_field_descriptor(field, paths));
+    let set_arms = fields.iter().map(|field| {
+        let (ident, index) = (&field.ident, Literal::u32_unsuffixed(field.number - 1));
+        let read = match field.shape {
+            Shape::Optional(ref value) if value.is_message() => {
+                quote!(#pblite::read_optional_with_sink(
+                    field_value,
+                    sink,
+                    &#pblite::read_message_with_sink
+                ))
+            }
+            Shape::Optional(ref value) => {
+                let method = value.get_read_method(paths);
+                quote!(#pblite::read_optional(field_value, &#method))
+            }
+            Shape::Repeated(ref value) if value.is_message() => {
+                quote!(#pblite::read_array_with_sink(
+                    field_value,
+                    sink,
+                    &#pblite::read_message_with_sink
+                ))
+            }
+            Shape::Repeated(ref value) => {
+                let method = value.get_read_method(paths);
+                quote!(#pblite::read_array(field_value, &#method))
+            }
+            Shape::Map(ref key, ref value) if value.is_message() => {
+                let key_method = key.get_read_method(paths);
+                quote!(#pblite::read_map_with_sink(
+                    field_value,
+                    sink,
+                    &#key_method,
+                    &#pblite::read_message_with_sink
+                ))
+            }
+            Shape::Map(ref key, ref value) => {
+                let (key_method, value_method) =
+                    (key.get_read_method(paths), value.get_read_method(paths));
+                quote!(#pblite::read_map(field_value, &#key_method, &#value_method))
+            }
+        };
+        quote!(#index => self.#ident = #read?,)
+    });
+    let read_arms = fields.iter().map(|field| {
+        let (ident, index) = (&field.ident, Literal::u32_unsuffixed(field.number - 1));
+        let read = match field.shape {
+            Shape::Optional(ref value) => {
+                let method = value.get_reader_method(paths);
+                quote!(reader.read_optional(&#method))
+            }
+            Shape::Repeated(ref value) => {
+                let method = value.get_reader_method(paths);
+                quote!(reader.read_array(&#method))
+            }
+            Shape::Map(ref key, ref value) => {
+                let (key_method, value_method) =
+                    (key.get_reader_method(paths), value.get_reader_method(paths));
+                quote!(reader.read_map(&#key_method, &#value_method))
+            }
+        };
+        quote!(#index => self.#ident = #read?,)
+    });
+    let writes = fields.iter().map(|field| {
+        let (ident, index) = (&field.ident, Literal::u32_unsuffixed(field.number - 1));
+        let write = match field.shape {
+            Shape::Optional(ref value) => {
+                let method = value.get_write_method(paths);
+                quote!(#pblite::write_optional(&self.#ident, &#method))
+            }
+            Shape::Repeated(ref value) => {
+                let method = value.get_write_method(paths);
+                quote!(#pblite::write_array(&self.#ident, &#method))
+            }
+            Shape::Map(ref key, ref value) => {
+                let (key_method, value_method) =
+                    (key.get_write_method(paths), value.get_write_method(paths));
+                quote!(#pblite::write_map(&self.#ident, &#key_method, &#value_method))
+            }
+        };
+        quote!((#index, #write),)
+    });
+    let required_checks = fields.iter().filter(|field| field.required).map(|field| {
+        let (ident, ident_str) = (&field.ident, field.ident.to_string());
+        quote!(#pblite::check_required(&self.#ident, #ident_str)?;)
+    });
+    // The sink is only passed on to the functions reading messages.
+    let sink = if fields.iter().any(|field| field.has_message_values()) {
+        quote!(sink)
+    } else {
+        quote!(_sink)
+    };
+    let (set_unknown, read_unknown, write_unknown) = match unknown_fields {
+        Some(ref ident) => (
+            quote!(#pblite::read_unknown_field(&mut self.#ident, number, field_value)),
+            quote!(reader.read_unknown_field(&mut self.#ident, number)?),
+            quote!(fields.extend(#pblite::write_unknown_fields(&self.#ident));),
+        ),
+        None => (quote!({}), quote!({ reader.skip_value()?; }), quote!()),
+    };
+
+    Ok(quote! {
+        impl #pblite::Message for #name {
+            fn descriptor() -> &'static #pblite::MessageDescriptor {
+                static DESCRIPTOR: #pblite::MessageDescriptor = #pblite::MessageDescriptor {
+                    name: #name_str,
+                    full_name: #name_str,
+                    fields: &[#(#descriptors),*],
+                    nested_messages: &[],
+                    nested_enums: &[],
+                };
+                &DESCRIPTOR
+            }
+            fn get_name(&self) -> &str {
+                #name_str
+            }
+            fn set_field(
+                &mut self,
+                number: usize,
+                field_value: &#serde_json::Value,
+                #sink: &mut #pblite::WarningSink,
+            ) -> #pblite::Result<()> {
+                match number {
+                    #(#set_arms)*
+                    _ => #set_unknown,
+                };
+                Ok(())
+            }
+            fn read_field(
+                &mut self,
+                number: usize,
+                reader: &mut #pblite::Reader,
+            ) -> #pblite::Result<()> {
+                match number {
+                    #(#read_arms)*
+                    _ => #read_unknown,
+                };
+                Ok(())
+            }
+            #[allow(unused_mut)]
+            fn get_fields(&self) -> Vec<(usize, #serde_json::Value)> {
+                let mut fields = vec![#(#writes)*];
+                #write_unknown
+                fields
+            }
+            fn check_required(&self) -> #pblite::Result<()> {
+                #(#required_checks)*
+                Ok(())
+            }
+        }
+        impl<'de> #serde::Deserialize<'de> for #name {
+            fn deserialize<D: #serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> ::std::result::Result<Self, D::Error> {
+                #pblite_serde::deserialize_message(deserializer)
+            }
+        }
+        impl #serde::Serialize for #name {
+            fn serialize<S: #serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> ::std::result::Result<S::Ok, S::Error> {
+                #pblite_serde::serialize_message(self, serializer)
+            }
+        }
+    })
+}
+
+fn gen_field_descriptor(field: &Field, paths: &Paths) -> proc_macro2::TokenStream {
+    let pblite = &paths.pblite;
+    let (label, key, value) = match field.shape {
+        Shape::Optional(ref value) if field.required => (quote!(Required), None, value),
+        Shape::Optional(ref value) => (quote!(Optional), None, value),
+        Shape::Repeated(ref value) => (quote!(Repeated), None, value),
+        Shape::Map(ref key, ref value) => (quote!(Repeated), Some(key), value),
+    };
+    let ty = &value.ty;
+    let message_type = match value.method_type_name {
+        "message" => quote!(Some(<#ty as #pblite::Message>::descriptor)),
+        _ => quote!(None),
+    };
+    let enum_type = match value.method_type_name {
+        "enum" => quote!(Some(<#ty as #pblite::Enum>::descriptor)),
+        _ => quote!(None),
+    };
+    let map_key_type = match key {
+        Some(key) => {
+            let field_type = key.get_field_type(paths);
+            quote!(Some(#field_type))
+        }
+        None => quote!(None),
+    };
+    let (name, number, field_type) = (
+        field.ident.to_string(),
+        Literal::usize_unsuffixed(field.number as usize),
+        value.get_field_type(paths),
+    );
+    quote! {
+        #pblite::FieldDescriptor {
+            name: #name,
+            number: #number,
+            label: #pblite::Label::#label,
+            field_type: #field_type,
+            message_type: #message_type,
+            enum_type: #enum_type,
+            oneof: None,
+            map_key_type: #map_key_type,
+        }
+    }
+}
+
+fn expand_enum<'a, I>(
+    name: &Ident,
+    variants: I,
+    paths: &Paths,
+) -> syn::Result<proc_macro2::TokenStream>
+where
+    I: Iterator<Item = &'a syn::Variant>,
+{
+    let mut values: Vec<(&Ident, i32)> = Vec::new();
+    let mut unknown = None;
+    for variant in variants {
+        let options = parse_options(&variant.attrs)?;
+        match variant.fields {
+            Fields::Unnamed(ref fields) if options.unknown && fields.unnamed.len() == 1 => {
+                unknown = Some(&variant.ident);
+            }
+            Fields::Unit if !options.unknown => {
+                let number = match options {
+                    Options {
+                        field: None,
+                        value: Some(number),
+                        ..
+                    } => number,
+                    _ => {
+                        return Err(syn::Error::new(
+                            variant.ident.span(),
+                            "expected #[pblite(value = N)]",
+                        ))
+                    }
+                };
+                if values.iter().any(|&(_, value)| value == number) {
+                    return Err(syn::Error::new(
+                        variant.ident.span(),
+                        format!("enum value {} is used more than once", number),
+                    ));
+                }
+                values.push((&variant.ident, number));
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    variant.ident.span(),
+                    "expected a unit variant, or a #[pblite(unknown)] variant containing a u32",
+                ))
+            }
+        }
+    }
+
+    let (pblite, pblite_serde, serde, serde_json) = (
+        &paths.pblite,
+        &paths.pblite_serde,
+        &paths.serde,
+        &paths.serde_json,
+    );
+    let name_str = name.to_string();
+    let descriptors = values.iter().map(|&(ident, number)| {
+        let (ident_str, number) = (ident.to_string(), Literal::i32_unsuffixed(number));
+        quote!(#pblite::EnumValueDescriptor { name: #ident_str, number: #number })
+    });
+    // Negative values are represented by their two's complement, like in generated enums.
+    let from_arms = values.iter().map(|&(ident, number)| {
+        let number = Literal::u32_unsuffixed(number as u32);
+        quote!(#number => Ok(#name::#ident),)
+    });
+    let to_arms = values.iter().map(|&(ident, number)| {
+        let number = Literal::u32_unsuffixed(number as u32);
+        quote!(&#name::#ident => #number,)
+    });
+    let (from_unknown, to_unknown) = match unknown {
+        Some(ident) => (
+            quote!(Ok(#name::#ident(value))),
+            quote!(&#name::#ident(value) => value,),
+        ),
+        None => (
+            quote!(Err(#pblite::expected_value(#name_str, &#serde_json::Value::from(value)))),
+            quote!(),
+        ),
+    };
+
+    Ok(quote! {
+        impl #pblite::Enum for #name {
+            fn descriptor() -> &'static #pblite::EnumDescriptor {
+                static DESCRIPTOR: #pblite::EnumDescriptor = #pblite::EnumDescriptor {
+                    name: #name_str,
+                    full_name: #name_str,
+                    values: &[#(#descriptors),*],
+                };
+                &DESCRIPTOR
+            }
+            fn from_u32(value: u32) -> #pblite::Result<Self> {
+                match value {
+                    #(#from_arms)*
+                    _ => #from_unknown,
+                }
+            }
+            fn to_u32(&self) -> u32 {
+                match self {
+                    #(#to_arms)*
+                    #to_unknown
+                }
+            }
+        }
+        impl<'de> #serde::Deserialize<'de> for #name {
+            fn deserialize<D: #serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> ::std::result::Result<Self, D::Error> {
+                #pblite_serde::deserialize_enum(deserializer)
+            }
+        }
+        impl #serde::Serialize for #name {
+            fn serialize<S: #serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> ::std::result::Result<S::Ok, S::Error> {
+                #pblite_serde::serialize_enum(self, serializer)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use expand;
+    use syn;
+
+    fn expand_error(input: &str) -> String {
+        let input: syn::DeriveInput = syn::parse_str(input).unwrap();
+        expand(&input).err().expect("expected error").to_string()
+    }
+
+    #[test]
+    fn test_expand_message() {
+        let input: syn::DeriveInput = syn::parse_str(
+            "struct Foo {
+                #[pblite(field = 1, required)]
+                name: Option<String>,
+                #[pblite(field = 3)]
+                ids: Option<Vec<u64>>,
+                #[pblite(field = 4, enumeration)]
+                statuses: Option<BTreeMap<String, Status>>,
+                #[pblite(field = 5)]
+                data: Option<Vec<u8>>,
+            }",
+        ).unwrap();
+        let tokens = expand(&input).unwrap().to_string();
+        assert!(tokens.contains(
+            "0 => self . name = :: hangups :: pblite :: read_optional ( field_value , & :: \
+             hangups :: pblite :: read_string ) ?"
+        ));
+        assert!(tokens.contains(
+            "2 => self . ids = reader . read_array ( & :: hangups :: pblite :: Reader :: \
+             read_uint64 ) ?"
+        ));
+        assert!(tokens.contains(
+            "( 3 , :: hangups :: pblite :: write_map ( & self . statuses , & :: hangups :: \
+             pblite :: write_string , & :: hangups :: pblite :: write_enum ) )"
+        ));
+        assert!(tokens.contains(
+            "( 4 , :: hangups :: pblite :: write_optional ( & self . data , & :: hangups :: \
+             pblite :: write_bytes ) )"
+        ));
+        assert!(tokens.contains(
+            ":: hangups :: pblite :: check_required ( & self . name , \"name\" ) ?"
+        ));
+        assert!(tokens.contains(
+            "enum_type : Some ( < Status as :: hangups :: pblite :: Enum > :: descriptor )"
+        ));
+        assert!(tokens.contains(
+            ":: hangups :: pblite_serde :: deserialize_message ( deserializer )"
+        ));
+        assert!(tokens.contains(
+            ":: hangups :: pblite_serde :: serialize_message ( self , serializer )"
+        ));
+    }
+
+    #[test]
+    fn test_expand_enum() {
+        let input: syn::DeriveInput = syn::parse_str(
+            "enum Status {
+                #[pblite(value = 0)]
+                Unspecified,
+                #[pblite(value = 1)]
+                Active,
+                #[pblite(value = -1)]
+                Deleted,
+                #[pblite(unknown)]
+                Unknown(u32),
+            }",
+        ).unwrap();
+        let tokens = expand(&input).unwrap().to_string();
+        assert!(tokens.contains("0 => Ok ( Status :: Unspecified )"));
+        assert!(tokens.contains("1 => Ok ( Status :: Active )"));
+        assert!(tokens.contains("4294967295 => Ok ( Status :: Deleted )"));
+        assert!(tokens.contains("name : \"Deleted\" , number : -1"));
+        assert!(tokens.contains("_ => Ok ( Status :: Unknown ( value ) )"));
+        assert!(tokens.contains("& Status :: Unknown ( value ) => value"));
+        assert!(tokens.contains(
+            ":: hangups :: pblite_serde :: deserialize_enum ( deserializer )"
+        ));
+    }
+
+    #[test]
+    fn test_expand_crate() {
+        let input: syn::DeriveInput = syn::parse_str(
+            "#[pblite(crate = \"crate\")]
+            struct Foo {
+                #[pblite(field = 1)]
+                name: Option<String>,
+            }",
+        ).unwrap();
+        let tokens = expand(&input).unwrap().to_string();
+        assert!(tokens.contains("impl crate :: pblite :: Message for Foo"));
+        assert!(tokens.contains("field_value : & crate :: serde_json :: Value"));
+        assert!(tokens.contains("impl < 'de > crate :: serde :: Deserialize < 'de > for Foo"));
+        assert!(!tokens.contains("hangups"));
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        assert_eq!(
+            expand_error("struct Foo { name: Option<String> }"),
+            "expected #[pblite(field = N)] or #[pblite(unknown_fields)]"
+        );
+        assert_eq!(
+            expand_error("struct Foo { #[pblite(field = 1)] name: String }"),
+            "expected Option<T>, Option<Vec<T>> or Option<BTreeMap<K, V>>"
+        );
+        assert_eq!(
+            expand_error(
+                "struct Foo { #[pblite(field = 1)] a: Option<u32>, \
+                 #[pblite(field = 1)] b: Option<u32> }"
+            ),
+            "field number 1 is used more than once"
+        );
+        assert_eq!(
+            expand_error("struct Foo { #[pblite(field = 0)] a: Option<u32> }"),
+            "expected a positive number"
+        );
+        assert_eq!(
+            expand_error("struct Foo { #[pblite(feild = 1)] a: Option<u32> }"),
+            "unknown pblite option"
+        );
+        assert_eq!(
+            expand_error(
+                "struct Foo { #[pblite(field = 1, crate = \"crate\")] a: Option<u32> }"
+            ),
+            "unknown pblite option"
+        );
+        assert_eq!(
+            expand_error(
+                "#[pblite(field = 1)] struct Foo { #[pblite(field = 1)] a: Option<u32> }"
+            ),
+            "unknown pblite option"
+        );
+        assert_eq!(
+            expand_error("enum Status { Active }"),
+            "expected #[pblite(value = N)]"
+        );
+        assert_eq!(
+            expand_error("enum Status { #[pblite(field = 1)] Active }"),
+            "expected #[pblite(value = N)]"
+        );
+        assert_eq!(
+            expand_error(
+                "enum Status { #[pblite(value = 1)] Active, #[pblite(value = 1)] Away }"
+            ),
+            "enum value 1 is used more than once"
+        );
+        assert_eq!(
+            expand_error("enum Status { #[pblite(value = 2147483648)] Active }"),
+            "expected an i32 number"
+        );
+    }
+}
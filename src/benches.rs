@@ -0,0 +1,105 @@
+//! Benchmarks for decoding channel payloads, run with `cargo +nightly bench --features nightly`.
+//!
+//! Only `CAPTURED_CHUNK` was captured from the channel. The larger chunks are synthetic, written
+//! to follow its format, so their numbers show the relative cost of the decoders rather than
+//! what real traffic costs. `hangouts::*` messages own their strings, so the reader still
+//! allocates one for each string field.
+
+use channel_parser::ContainerArray;
+use hangouts;
+use pblite;
+use pblite::Message;
+use serde_json;
+use test::Bencher;
+
+/// A `BatchUpdate` payload captured from the channel, containing one state update.
+const CAPTURED_CHUNK: &str = "[[5,[{\"p\":\"{\\\"1\\\":{\\\"1\\\":{\\\"1\\\":{\\\"1\\\":1,\\\"2\\\":1}},\\\"4\\\":\\\"1521002546965\\\",\\\"5\\\":\\\"S4\\\"},\\\"2\\\":{\\\"1\\\":{\\\"1\\\":\\\"babel\\\",\\\"2\\\":\\\"conserver.google.com\\\"},\\\"2\\\":\\\"[\\\\\\\"cbu\\\\\\\",[[[0,null,\\\\\\\"173955572810212329\\\\\\\",null,1521002546845000]\\\\n,null,null,null,null,null,null,null,null,null,null,null,null,[[[\\\\\\\"lcsw_hangouts_E5EC3DFB\\\\\\\",\\\\\\\"2DA6A88554072FCA\\\\\\\"]\\\\n,30]\\\\n]\\\\n]\\\\n]\\\\n]\\\\n\\\"}}\"}]]\n]\n";
+
+/// A 33 KB chunk in the same format as `CAPTURED_CHUNK`, with a backlog of 40 chat messages in
+/// one `BatchUpdate` like the one delivered after reconnecting. Like the server, it escapes
+/// non-ASCII and HTML characters and breaks lines after closing brackets. Identifiers and
+/// messages are made up, and it wasn't captured.
+const EVENT_BACKLOG_CHUNK: &str = include_str!("bench_data/event_backlog.txt");
+
+/// A 12 KB chunk in the same format, with six arrays mixing chat messages with typing, focus
+/// and watermark notifications. It is synthetic like `EVENT_BACKLOG_CHUNK`.
+const MIXED_UPDATES_CHUNK: &str = include_str!("bench_data/mixed_updates.txt");
+
+/// Returns the pblite strings of the `BatchUpdate`s in `chunk`, including their headers.
+fn get_pblite_strings(chunk: &str) -> Vec<String> {
+    let container = serde_json::from_str::<serde_json::Value>(chunk).unwrap();
+    container
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|array| {
+            let wrapper = serde_json::from_str::<serde_json::Value>(
+                array[1][0]["p"].as_str().unwrap(),
+            ).unwrap();
+            wrapper["2"]["2"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+/// Decodes a `BatchUpdate` by building a `serde_json::Value` first, for comparison.
+fn decode_with_value(pblite: &str) -> hangouts::BatchUpdate {
+    let value = serde_json::from_str::<serde_json::Value>(pblite).unwrap();
+    hangouts::BatchUpdate::from_vec(&value.as_array().unwrap()[1..].to_vec()).unwrap()
+}
+
+fn decode_with_reader(pblite: &str) -> hangouts::BatchUpdate {
+    pblite::Reader::new(pblite)
+        .read_message_skipping(1)
+        .unwrap()
+}
+
+fn bench_decode_with(b: &mut Bencher, chunk: &str, decode: fn(&str) -> hangouts::BatchUpdate) {
+    let pblite_strings = get_pblite_strings(chunk);
+    for pblite in &pblite_strings {
+        assert_eq!(decode_with_reader(pblite), decode_with_value(pblite));
+    }
+    b.bytes = pblite_strings.iter().map(|pblite| pblite.len() as u64).sum();
+    b.iter(|| {
+        pblite_strings
+            .iter()
+            .map(|pblite| decode(pblite))
+            .collect::<Vec<_>>()
+    });
+}
+
+#[bench]
+fn bench_parse_captured_chunk(b: &mut Bencher) {
+    b.iter(|| ContainerArray::parse(CAPTURED_CHUNK).unwrap());
+}
+
+#[bench]
+fn bench_parse_event_backlog_chunk(b: &mut Bencher) {
+    b.bytes = EVENT_BACKLOG_CHUNK.len() as u64;
+    b.iter(|| ContainerArray::parse(EVENT_BACKLOG_CHUNK).unwrap());
+}
+
+#[bench]
+fn bench_parse_mixed_updates_chunk(b: &mut Bencher) {
+    b.bytes = MIXED_UPDATES_CHUNK.len() as u64;
+    b.iter(|| ContainerArray::parse(MIXED_UPDATES_CHUNK).unwrap());
+}
+
+#[bench]
+fn bench_decode_event_backlog_with_value(b: &mut Bencher) {
+    bench_decode_with(b, EVENT_BACKLOG_CHUNK, decode_with_value);
+}
+
+#[bench]
+fn bench_decode_event_backlog_with_reader(b: &mut Bencher) {
+    bench_decode_with(b, EVENT_BACKLOG_CHUNK, decode_with_reader);
+}
+
+#[bench]
+fn bench_decode_mixed_updates_with_value(b: &mut Bencher) {
+    bench_decode_with(b, MIXED_UPDATES_CHUNK, decode_with_value);
+}
+
+#[bench]
+fn bench_decode_mixed_updates_with_reader(b: &mut Bencher) {
+    bench_decode_with(b, MIXED_UPDATES_CHUNK, decode_with_reader);
+}
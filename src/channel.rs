@@ -1,10 +1,19 @@
-use futures::{Future, Stream};
+use futures::{future, Async, Future, Poll, Stream};
+use futures::future::Either;
+use rand;
+use rand::Rng;
 use hyper;
-use hyper::{header, Chunk, Client, Method, Request};
+use hyper::{header, Chunk, Client, Method, Request, Uri};
+use hyper::client::{FutureResponse, HttpConnector};
 use hyper_tls::HttpsConnector;
-use tokio_core::reactor::Core;
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_service::Service;
 use serde_json;
+use std::cmp;
+use std::io;
 use std::str;
+use std::thread;
+use std::time::Duration;
 use decoder;
 use time;
 use sha1::Sha1;
@@ -14,10 +23,15 @@ use std::result;
 use native_tls;
 use channel_parser::{ChannelPayload, ContainerArray, ChannelArray};
 
-const CHANNEL_URL: &str = "https://0.client-channel.google.com/client-channel/channel/bind";
+const BASE_URL: &str = "https://0.client-channel.google.com/client-channel";
 const ORIGIN: &str = "https://hangouts.google.com";
 const SERVICE_NAMES: &[&str] = &["babel", "babel_presence_last_seen"];
 const DNS_WORKER_THREADS: usize = 1;
+const INITIAL_RETRY_DELAY_MILLIS: u64 = 1000;
+const MAX_RETRY_DELAY_MILLIS: u64 = 60 * 1000;
+const CONNECT_TIMEOUT_SECS: u64 = 30;
+const RESPONSE_TIMEOUT_SECS: u64 = 60;
+const CHUNK_TIMEOUT_SECS: u64 = 60;
 
 error_chain! {
     errors {
@@ -29,6 +43,10 @@ error_chain! {
             description("bad status"),
             display("bad status: {}", status),
         }
+        TimedOut(waiting_for: &'static str) {
+            description("timed out"),
+            display("timed out waiting for {}", waiting_for),
+        }
     }
     foreign_links {
         Http(hyper::Error);
@@ -44,6 +62,20 @@ struct Session {
     session_id: String,
     g_session_id: String,
 }
+impl Error {
+    /// Returns the status of the bad response that caused this error, if any.
+    fn get_bad_status(&self) -> Option<hyper::StatusCode> {
+        match self.0 {
+            ErrorKind::BadStatus(status) => Some(status),
+            _ => self.1
+                .next_error
+                .as_ref()
+                .and_then(|error| error.downcast_ref::<Error>())
+                .and_then(|error| error.get_bad_status()),
+        }
+    }
+}
+
 impl Session {
     fn from_response(value: serde_json::Value) -> Result<Self> {
         let sid: Result<_> = value
@@ -62,32 +94,205 @@ impl Session {
 }
 
 type Callback<'a> = &'a Fn(hangouts::StateUpdate);
+type ConnectionCallback<'a> = &'a Fn(ConnectionState);
+
+/// State of the connection to the channel, which `listen` reports whenever it changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionState {
+    /// Not connected, either because `listen` hasn't connected yet or because it gave up.
+    Disconnected,
+    /// A long polling request is open.
+    Connected,
+    /// After the given number of consecutive failed requests, waiting for the delay before
+    /// trying again.
+    Reconnecting { failures: u32, delay: Duration },
+}
+
+/// How `listen` retries failed requests. The delay doubles after each consecutive failure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Delay after the first failure.
+    pub initial_delay: Duration,
+    /// Upper bound of the delay.
+    pub max_delay: Duration,
+    /// Number of consecutive failures to retry before giving up, or `None` to retry forever.
+    pub max_retries: Option<u32>,
+}
+impl ReconnectPolicy {
+    /// Returns the delay after the given number of consecutive failures. Each delay is randomly
+    /// shortened by up to half, so that clients disconnected together don't retry together.
+    fn get_delay(&self, failures: u32) -> Duration {
+        let exponent = cmp::min(failures.saturating_sub(1), 31);
+        let delay = self.initial_delay
+            .checked_mul(1 << exponent)
+            .map_or(self.max_delay, |delay| cmp::min(delay, self.max_delay));
+        let millis = delay.as_secs() * 1000 + u64::from(delay.subsec_nanos()) / 1_000_000;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2, millis + 1))
+    }
+}
+/// How long requests wait before failing, so that a lost connection is detected even if the
+/// socket stays open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeouts {
+    /// For connecting to the server.
+    pub connect: Duration,
+    /// For the response headers of a long polling request and for the whole response of other
+    /// requests, including connecting.
+    pub response: Duration,
+    /// Between chunks of a long polling response. The server sends a noop about every 30
+    /// seconds, so missing them means the connection was lost.
+    pub chunk: Duration,
+}
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(CONNECT_TIMEOUT_SECS),
+            response: Duration::from_secs(RESPONSE_TIMEOUT_SECS),
+            chunk: Duration::from_secs(CHUNK_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(INITIAL_RETRY_DELAY_MILLIS),
+            max_delay: Duration::from_millis(MAX_RETRY_DELAY_MILLIS),
+            max_retries: None,
+        }
+    }
+}
+
+/// Where a `Channel` connects to and how it identifies itself. The default connects to Hangouts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelConfig {
+    /// URL that the channel's endpoints are relative to, without a trailing slash.
+    pub base_url: String,
+    /// Origin sent with requests, which is also part of the authorization hash.
+    pub origin: String,
+    /// Index of the signed in Google account to use.
+    pub auth_user: u32,
+    /// Services to subscribe to once the channel is open.
+    pub service_names: Vec<String>,
+    /// Whether to support HTTPS. Disabling this only allows HTTP, eg. for a local test server.
+    pub use_tls: bool,
+    pub reconnect_policy: ReconnectPolicy,
+    pub timeouts: Timeouts,
+}
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        ChannelConfig {
+            base_url: BASE_URL.to_owned(),
+            origin: ORIGIN.to_owned(),
+            auth_user: 0,
+            service_names: SERVICE_NAMES.iter().map(|name| name.to_string()).collect(),
+            use_tls: true,
+            reconnect_policy: ReconnectPolicy::default(),
+            timeouts: Timeouts::default(),
+        }
+    }
+}
 
 struct ClientID(String);
 
 pub struct Channel {
+    config: ChannelConfig,
+    /// Reactor that all requests run on, which is only `None` while running one.
+    core: Option<Core>,
+    handle: Handle,
+    /// Client shared by all requests, so that connections are reused.
+    client: HttpClient,
     cookies: header::Cookie,
     client_id: Option<ClientID>, // eventually needed for sending requests
+    connection_state: ConnectionState,
+    /// ID of the last array received in the current session, which the server uses to resend
+    /// arrays the client missed.
+    last_array_id: Option<u64>,
     unicode_decoder: decoder::UnicodeDecoder,
     chunk_decoder: decoder::ChunkDecoder,
 }
 impl Channel {
-    pub fn new(cookies: header::Cookie) -> Self {
-        Channel {
+    /// Creates a channel with its own reactor, which can't be sent to another thread.
+    pub fn new(cookies: header::Cookie, config: ChannelConfig) -> Result<Self> {
+        let core = Core::new()?;
+        let handle = core.handle();
+        let client = get_client(&config, &handle)?;
+        Ok(Channel {
+            config: config,
+            core: Some(core),
+            handle: handle,
+            client: client,
             cookies: cookies,
             client_id: None,
+            connection_state: ConnectionState::Disconnected,
+            last_array_id: None,
             unicode_decoder: decoder::UnicodeDecoder::new(),
             chunk_decoder: decoder::ChunkDecoder::new(),
-        }
+        })
     }
 
-    pub fn listen(&mut self, on_state_update: Callback) -> Result<()> {
-        let session = self.fetch_session().chain_err(|| ErrorKind::Disconnected)?;
-        info!("Got new session: {:?}", session);
+    /// Receives state updates until the reconnect policy gives up, retrying failed requests and
+    /// creating a new session when the server no longer knows the current one.
+    pub fn listen(
+        &mut self,
+        on_state_update: Callback,
+        on_connection_state: ConnectionCallback,
+    ) -> Result<()> {
+        let mut session = None;
+        let mut failures = 0;
         loop {
-            // TODO: Verify that this doesn't any lose data.
-            self.open_long_polling_request(&session, on_state_update)
-                .chain_err(|| ErrorKind::Disconnected)?;
+            let result = match session {
+                Some(ref session) => {
+                    self.open_long_polling_request(session, on_state_update, on_connection_state)
+                }
+                None => self.fetch_session().map(|new_session| {
+                    info!("Got new session: {:?}", new_session);
+                    session = Some(new_session);
+                }),
+            };
+            if self.connection_state == ConnectionState::Connected {
+                failures = 0;
+            }
+            let error = match result {
+                Ok(()) => continue,
+                Err(error) => error,
+            };
+            failures += 1;
+            if error.get_bad_status() == Some(hyper::StatusCode::BadRequest) {
+                // The server responds with "Unknown SID" once the session has expired.
+                info!("Session is no longer valid");
+                session = None;
+            }
+            let reconnect_policy = self.config.reconnect_policy.clone();
+            if reconnect_policy
+                .max_retries
+                .map_or(false, |max_retries| failures > max_retries)
+            {
+                self.set_connection_state(ConnectionState::Disconnected, on_connection_state);
+                return Err(error).chain_err(|| ErrorKind::Disconnected);
+            }
+            let delay = reconnect_policy.get_delay(failures);
+            warn!("Request failed, retrying in {:?}: {}", delay, error);
+            self.set_connection_state(
+                ConnectionState::Reconnecting {
+                    failures: failures,
+                    delay: delay,
+                },
+                on_connection_state,
+            );
+            thread::sleep(delay);
+        }
+    }
+
+    fn set_connection_state(
+        &mut self,
+        state: ConnectionState,
+        on_connection_state: ConnectionCallback,
+    ) {
+        if state != self.connection_state {
+            info!("Connection state changed to {:?}", state);
+            self.connection_state = state;
+            on_connection_state(state);
         }
     }
 
@@ -95,30 +300,57 @@ impl Channel {
         &mut self,
         session: &Session,
         on_state_update: Callback,
+        on_connection_state: ConnectionCallback,
     ) -> Result<()> {
         info!("Opening new long polling request");
-        let (mut core, client) = get_client()?; // TODO: Error chaining?
+        // Discard any incomplete chunk left by a previous request.
+        self.unicode_decoder = decoder::UnicodeDecoder::new();
+        self.chunk_decoder = decoder::ChunkDecoder::new();
         let request = self.get_request(Some(session), None)?; // TODO: Error chaining?
+        // Take the reactor because the request borrows the channel while running on it.
+        let mut core = self.core.take().expect("reactor is already running");
+        let handle = core.handle();
+        let chunk_timeout = self.config.timeouts.chunk;
 
-        // TODO: Add timeout for entire request.
-        // TODO: Add timeout for each chunk.
-        let work = client
-            .request(request)
-            .from_err::<Error>()
+        let response = with_timeout(
+            self.client.request(request).from_err::<Error>(),
+            self.config.timeouts.response,
+            &handle,
+            "response",
+        );
+        let work = response
             .and_then(|res| {
                 trace!("Response: {}", res.status());
                 expect_ok(&res.status())?;
                 Ok(res.body())
             })
             .and_then(|body| {
-                body.from_err::<Error>().for_each(|c| {
-                    // TODO: Remove unwrap.
-                    for channel_array in self.handle_pushed_bytes(c).unwrap() {
+                self.set_connection_state(ConnectionState::Connected, on_connection_state);
+                let body =
+                    TimeoutStream::new(body.from_err::<Error>(), chunk_timeout, &handle, "chunk");
+                body.for_each(|c| {
+                    // Requests made while handling the chunk, which must finish before reading
+                    // the next one.
+                    let mut requests: Vec<Box<Future<Item = (), Error = Error>>> = Vec::new();
+                    let channel_arrays = match self.handle_pushed_bytes(c) {
+                        Ok(channel_arrays) => channel_arrays,
+                        Err(e) => return future::Either::A(future::err(e)),
+                    };
+                    for channel_array in channel_arrays {
+                        // The server may send arrays again if it didn't receive the AID of the
+                        // request, so skip arrays that were already handled.
+                        if self.last_array_id
+                            .map_or(false, |last_array_id| channel_array.array_id <= last_array_id)
+                        {
+                            debug!("Skipping already received array {}", channel_array.array_id);
+                            continue;
+                        }
+                        self.last_array_id = Some(channel_array.array_id);
                         match channel_array.payload {
                             ChannelPayload::NewClientID(new_client_id) => {
                                 // TODO: Make parser return ClientID?
                                 self.client_id = Some(ClientID(new_client_id));
-                                self.add_services(session)?;
+                                requests.push(self.add_services(session));
                             }
                             ChannelPayload::BatchUpdate(batch_update) => {
                                 batch_update.state_update.and_then(|state_updates| {
@@ -131,11 +363,13 @@ impl Channel {
                             _ => {}
                         };
                     }
-                    Ok(())
+                    future::Either::B(future::join_all(requests).map(|_| ()))
                 })
             });
 
-        core.run(work)
+        let result = core.run(work);
+        self.core = Some(core);
+        result
     }
 
     // TODO: Add some logging to this.
@@ -155,18 +389,26 @@ impl Channel {
 
     fn fetch_session(&mut self) -> Result<Session> {
         info!("Creating new session");
-        self.send_maps(None, vec![])
+        // Array IDs start again in a new session.
+        self.last_array_id = None;
+        let work = self.send_maps(None, vec![]);
+        let mut core = self.core.take().expect("reactor is already running");
+        let result = core.run(work);
+        self.core = Some(core);
+        result
             .and_then(|response| Session::from_response(response))
             .map_err(|e| Error::with_chain(e, "failed to create session"))
     }
 
-    fn add_services(&mut self, session: &Session) -> Result<()> {
+    /// Returns a future that adds services, which runs alongside the long polling request.
+    fn add_services(&mut self, session: &Session) -> Box<Future<Item = (), Error = Error>> {
         info!("Adding services");
-        let maps = SERVICE_NAMES
+        let maps = self.config
+            .service_names
             .iter()
             .map(|service_name| json!({"p": {"3": {"1": {"1": service_name}}}}))
             .collect();
-        self.send_maps(Some(session), maps)
+        Box::new(self.send_maps(Some(session), maps)
             .and_then(|response| {
                 response
                     .as_array()
@@ -178,7 +420,7 @@ impl Channel {
                 1 => Ok(()),
                 _ => Err("request failed".into()),
             })
-            .chain_err(|| "failed to add services")
+            .then(|result| result.chain_err(|| "failed to add services")))
     }
 
     fn get_request(
@@ -190,6 +432,7 @@ impl Channel {
             Some(_) => "0",
             None => "rpc",
         };
+        let last_array_id = self.last_array_id.map(|array_id| array_id.to_string());
         let uri = {
             let mut query = vec![
                 ("VER", "8"),
@@ -207,11 +450,17 @@ impl Channel {
                 query.push(("TYPE", "xmlhttp"));
                 query.push(("t", "1"));
                 query.push(("CI", "0"));
+                if let (Some(_), Some(last_array_id)) = (session, last_array_id.as_ref()) {
+                    query.push(("AID", last_array_id));
+                }
             }
-            // TODO: Remove unwrap.
-            format!("{}{}", CHANNEL_URL, get_query_string(query))
-                .parse()
-                .unwrap()
+            let url = format!(
+                "{}/channel/bind{}",
+                self.config.base_url,
+                get_query_string(query)
+            );
+            url.parse::<hyper::Uri>()
+                .chain_err(|| format!("invalid channel url: {}", url))?
         };
         let method = match maps {
             Some(_) => Method::Post,
@@ -243,8 +492,9 @@ impl Channel {
         req.headers_mut()
             .set(header::ContentType::form_url_encoded());
         // Add authorization headers. Will get "Bad SID" error if these are incorrect.
-        req.headers_mut().set(XGoogAuthUser("0".to_owned()));
-        req.headers_mut().set(XOrigin(ORIGIN.to_owned()));
+        req.headers_mut()
+            .set(XGoogAuthUser(self.config.auth_user.to_string()));
+        req.headers_mut().set(XOrigin(self.config.origin.clone()));
         req.headers_mut().set(self.get_authorization_header());
         trace!("Request: {:?}", req);
         Ok(req)
@@ -254,19 +504,23 @@ impl Channel {
         &mut self,
         session: Option<&Session>,
         maps: Vec<serde_json::Value>,
-    ) -> Result<serde_json::Value> {
+    ) -> Box<Future<Item = serde_json::Value, Error = Error>> {
         trace!("Sending maps: {:?}", maps);
-        let (mut core, client) = get_client()?; // TODO: Error chaining?
-
-        self.get_request(session, Some(maps))
-            .and_then(|req| Ok(client.request(req)))
-            .and_then(|future| {
-                Ok(future.and_then(|response| {
-                    let status = response.status();
-                    response.body().concat2().join(Ok(status))
-                }))
+        let request = match self.get_request(session, Some(maps)) {
+            Ok(request) => request,
+            Err(e) => return Box::new(future::err(Error::with_chain(e, "failed to send maps"))),
+        };
+        let response = self.client
+            .request(request)
+            .and_then(|response| {
+                let status = response.status();
+                response.body().concat2().join(Ok(status))
             })
-            .and_then(|future| core.run(future).chain_err(|| "request error"))
+            .from_err::<Error>();
+        // The whole response is covered by the timeout, because adding services blocks reading
+        // the long polling response, which would otherwise hang on a lost connection.
+        let work = with_timeout(response, self.config.timeouts.response, &self.handle, "response")
+            .then(|result| result.chain_err(|| "request error"))
             .and_then(move |(body, status)| {
                 let body_str =
                     String::from_utf8(body.to_vec()).chain_err(|| "response is not utf8")?;
@@ -280,7 +534,8 @@ impl Channel {
                     .ok_or::<Error>("failed to decode chunk from response".into())?;
                 serde_json::from_str(&first_chunk).chain_err(|| "failed to parse chunk as json")
             })
-            .chain_err(|| "failed to send maps")
+            .then(|result| result.chain_err(|| "failed to send maps"));
+        Box::new(work)
     }
 
     fn get_authorization_header(&self) -> header::Authorization<ApiHash> {
@@ -288,21 +543,159 @@ impl Channel {
             time: time::get_time(),
             // TODO: Remove unwrap.
             sapisid_cookie: self.cookies.get("SAPISID").unwrap().to_owned(),
+            origin: self.config.origin.clone(),
         })
     }
 }
 
-fn get_client() -> Result<
-    (
-        Core,
-        Client<HttpsConnector<hyper::client::HttpConnector>, hyper::Body>,
-    ),
-> {
-    let core = Core::new()?;
-    let client = Client::configure()
-        .connector(HttpsConnector::new(DNS_WORKER_THREADS, &core.handle())?)
-        .build(&core.handle());
-    Ok((core, client))
+/// HTTP client, which supports HTTPS unless TLS is disabled.
+enum HttpClient {
+    Https(Client<TimeoutConnector<HttpsConnector<HttpConnector>>, hyper::Body>),
+    Http(Client<TimeoutConnector<HttpConnector>, hyper::Body>),
+}
+impl HttpClient {
+    fn request(&self, request: Request) -> FutureResponse {
+        match self {
+            &HttpClient::Https(ref client) => client.request(request),
+            &HttpClient::Http(ref client) => client.request(request),
+        }
+    }
+}
+
+/// Returns a client for running requests on the reactor of `handle`, which keeps connections
+/// alive to reuse them.
+fn get_client(config: &ChannelConfig, handle: &Handle) -> Result<HttpClient> {
+    let connect_timeout = config.timeouts.connect;
+    let client = if config.use_tls {
+        let connector = HttpsConnector::new(DNS_WORKER_THREADS, handle)?;
+        HttpClient::Https(Client::configure()
+            .connector(TimeoutConnector::new(connector, connect_timeout, handle))
+            .keep_alive(true)
+            .build(handle))
+    } else {
+        let connector = HttpConnector::new(DNS_WORKER_THREADS, handle);
+        HttpClient::Http(Client::configure()
+            .connector(TimeoutConnector::new(connector, connect_timeout, handle))
+            .keep_alive(true)
+            .build(handle))
+    };
+    Ok(client)
+}
+
+/// Connector that fails connections that aren't established within the timeout.
+struct TimeoutConnector<C> {
+    connector: C,
+    timeout: Duration,
+    handle: Handle,
+}
+impl<C> TimeoutConnector<C> {
+    fn new(connector: C, timeout: Duration, handle: &Handle) -> Self {
+        TimeoutConnector {
+            connector: connector,
+            timeout: timeout,
+            handle: handle.clone(),
+        }
+    }
+}
+impl<C> Service for TimeoutConnector<C>
+where
+    C: Service<Request = Uri, Error = io::Error>,
+    C::Response: 'static,
+    C::Future: 'static,
+{
+    type Request = Uri;
+    type Response = C::Response;
+    type Error = io::Error;
+    type Future = Box<Future<Item = C::Response, Error = io::Error>>;
+
+    fn call(&self, uri: Uri) -> Self::Future {
+        let timeout = match Timeout::new(self.timeout, &self.handle) {
+            Ok(timeout) => timeout,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        Box::new(
+            self.connector
+                .call(uri)
+                .select2(timeout)
+                .then(|result| match result {
+                    Ok(Either::A((connection, _))) => Ok(connection),
+                    Ok(Either::B(_)) => {
+                        Err(io::Error::new(io::ErrorKind::TimedOut, "timed out connecting"))
+                    }
+                    Err(Either::A((e, _))) | Err(Either::B((e, _))) => Err(e),
+                }),
+        )
+    }
+}
+
+/// Fails with `ErrorKind::TimedOut` unless `future` completes within `duration`.
+fn with_timeout<F>(
+    future: F,
+    duration: Duration,
+    handle: &Handle,
+    waiting_for: &'static str,
+) -> Box<Future<Item = F::Item, Error = Error>>
+where
+    F: Future<Error = Error> + 'static,
+{
+    let timeout = match Timeout::new(duration, handle) {
+        Ok(timeout) => timeout,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+    Box::new(future.select2(timeout).then(move |result| match result {
+        Ok(Either::A((item, _))) => Ok(item),
+        Ok(Either::B(_)) => Err(ErrorKind::TimedOut(waiting_for).into()),
+        Err(Either::A((e, _))) => Err(e),
+        Err(Either::B((e, _))) => Err(e.into()),
+    }))
+}
+
+/// Stream that fails with `ErrorKind::TimedOut` when it waits for an item for longer than the
+/// timeout.
+struct TimeoutStream<S> {
+    stream: S,
+    duration: Duration,
+    handle: Handle,
+    waiting_for: &'static str,
+    /// Timeout of the current wait, which is started when the stream is first not ready.
+    timeout: Option<Timeout>,
+}
+impl<S> TimeoutStream<S> {
+    fn new(stream: S, duration: Duration, handle: &Handle, waiting_for: &'static str) -> Self {
+        TimeoutStream {
+            stream: stream,
+            duration: duration,
+            handle: handle.clone(),
+            waiting_for: waiting_for,
+            timeout: None,
+        }
+    }
+}
+impl<S: Stream<Error = Error>> Stream for TimeoutStream<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, Error> {
+        match self.stream.poll()? {
+            Async::NotReady => {
+                let mut timeout = match self.timeout.take() {
+                    Some(timeout) => timeout,
+                    None => Timeout::new(self.duration, &self.handle)?,
+                };
+                match timeout.poll()? {
+                    Async::Ready(()) => Err(ErrorKind::TimedOut(self.waiting_for).into()),
+                    Async::NotReady => {
+                        self.timeout = Some(timeout);
+                        Ok(Async::NotReady)
+                    }
+                }
+            }
+            ready => {
+                self.timeout = None;
+                Ok(ready)
+            }
+        }
+    }
 }
 
 struct PercentEncodedString {
@@ -330,13 +723,14 @@ fn get_query_string(params: Vec<(&str, &str)>) -> String {
 struct ApiHash {
     time: time::Timespec,
     sapisid_cookie: String,
+    origin: String,
 }
 impl header::Scheme for ApiHash {
     fn scheme() -> Option<&'static str> {
         Some("SAPISIDHASH")
     }
     fn fmt_scheme(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let auth_string = format!("{} {} {}", self.time.sec, self.sapisid_cookie, ORIGIN);
+        let auth_string = format!("{} {} {}", self.time.sec, self.sapisid_cookie, self.origin);
         let auth_hash = Sha1::from(auth_string).hexdigest();
         write!(f, "{}_{}", self.time.sec, auth_hash)
     }
@@ -363,9 +757,14 @@ header! { (XGoogAuthUser, "X-Goog-AuthUser") => [String] }
 mod tests {
 
     use serde_json;
-    use hyper::header;
+    use futures::future;
+    use hyper::{header, Uri};
     use channel;
+    use std::io;
+    use std::time::Duration;
     use time;
+    use tokio_core::reactor::Core;
+    use tokio_service::Service;
 
     #[test]
     fn test_api_hash() {
@@ -376,6 +775,7 @@ mod tests {
         let header = header::Authorization(channel::ApiHash {
             time: now,
             sapisid_cookie: String::from("jBoR10LFQqxvjDQy/Azg6q-5kgeQ-MiaKF"),
+            origin: String::from("https://hangouts.google.com"),
         });
         let expected = "SAPISIDHASH 1519452159_a5813881ad9a05006c22d2e1e28347b4fa4c4205";
         assert_eq!(format!("{}", header), expected);
@@ -410,4 +810,118 @@ mod tests {
         assert_eq!(channel::get_query_string(params), "?foo=bar&fizz=buzz");
     }
 
+    #[test]
+    fn test_get_request_uses_config() {
+        let mut cookies = header::Cookie::new();
+        cookies.append("SAPISID", "jBoR10LFQqxvjDQy/Azg6q-5kgeQ-MiaKF");
+        let mut channel = channel::Channel::new(
+            cookies,
+            channel::ChannelConfig {
+                base_url: "http://127.0.0.1:8080/client-channel".to_owned(),
+                origin: "http://localhost".to_owned(),
+                auth_user: 2,
+                service_names: vec!["babel".to_owned()],
+                use_tls: false,
+                ..Default::default()
+            },
+        ).unwrap();
+        let request = channel.get_request(None, None).unwrap();
+        assert_eq!(
+            request.uri().to_string(),
+            "http://127.0.0.1:8080/client-channel/channel/bind\
+             ?VER=8&ctype=hangouts&RID=rpc&TYPE=xmlhttp&t=1&CI=0"
+        );
+        assert_eq!(
+            request.headers().get::<channel::XOrigin>(),
+            Some(&channel::XOrigin("http://localhost".to_owned()))
+        );
+        assert_eq!(
+            request.headers().get::<channel::XGoogAuthUser>(),
+            Some(&channel::XGoogAuthUser("2".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_get_request_array_id() {
+        let mut cookies = header::Cookie::new();
+        cookies.append("SAPISID", "jBoR10LFQqxvjDQy/Azg6q-5kgeQ-MiaKF");
+        let mut channel = channel::Channel::new(cookies, Default::default()).unwrap();
+        channel.last_array_id = Some(5);
+        let session = channel::Session {
+            session_id: "EXAMPLE_SID".to_owned(),
+            g_session_id: "EXAMPLE_GSID".to_owned(),
+        };
+        let request = channel.get_request(Some(&session), None).unwrap();
+        assert!(request.uri().to_string().ends_with("&TYPE=xmlhttp&t=1&CI=0&AID=5"));
+        let request = channel.get_request(Some(&session), Some(vec![])).unwrap();
+        assert!(!request.uri().to_string().contains("AID"));
+    }
+
+    #[test]
+    fn test_get_request_invalid_url() {
+        let mut cookies = header::Cookie::new();
+        cookies.append("SAPISID", "jBoR10LFQqxvjDQy/Azg6q-5kgeQ-MiaKF");
+        let mut channel = channel::Channel::new(
+            cookies,
+            channel::ChannelConfig {
+                base_url: "not a url".to_owned(),
+                ..Default::default()
+            },
+        ).unwrap();
+        let error = channel.get_request(None, None).err().expect("expected error");
+        assert!(error.to_string().starts_with("invalid channel url: not a url/channel/bind?"));
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        let reconnect_policy = channel::ReconnectPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            max_retries: None,
+        };
+        let bounds = vec![
+            (1, 50, 100),
+            (2, 100, 200),
+            (4, 400, 800),
+            (5, 500, 1000),
+            (40, 500, 1000),
+        ];
+        for (failures, min, max) in bounds {
+            let delay = reconnect_policy.get_delay(failures);
+            assert!(
+                delay >= Duration::from_millis(min) && delay <= Duration::from_millis(max),
+                "delay after {} failures is {:?}",
+                failures,
+                delay
+            );
+        }
+    }
+
+    /// Connector that never connects.
+    struct StalledConnector;
+    impl Service for StalledConnector {
+        type Request = Uri;
+        type Response = ();
+        type Error = io::Error;
+        type Future = future::Empty<(), io::Error>;
+
+        fn call(&self, _uri: Uri) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    #[test]
+    fn test_timeout_connector() {
+        let mut core = Core::new().unwrap();
+        let connector = channel::TimeoutConnector::new(
+            StalledConnector,
+            Duration::from_millis(10),
+            &core.handle(),
+        );
+        let uri = "http://127.0.0.1:8080/".parse().unwrap();
+        let error = core.run(connector.call(uri)).err().expect("expected error");
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
 }
+
@@ -1,6 +1,10 @@
+use serde::de;
+use serde::de::{Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
 use serde_json;
 use hangouts;
-use pblite::Message;
+use pblite;
+use std::borrow::Cow;
+use std::fmt;
 
 // TODO: Come up with better names for everything here.
 
@@ -12,12 +16,17 @@ pub struct ContainerArray {
 }
 impl ContainerArray {
     pub fn parse(string: &str) -> Result<Self> {
-        let mut value =
-            serde_json::from_str::<serde_json::Value>(string).chain_err(|| "failed to parse json")?;
-        let array = value.as_array_mut().chain_err(|| "container is not array")?;
-        let channel_arrays = array
-            .drain(..)
-            .map(|val| ChannelArray::parse(val))
+        let raw_arrays = serde_json::from_str::<Vec<(u64, Vec<RawPayload>)>>(string)
+            .chain_err(|| "failed to parse json")?;
+        let channel_arrays = raw_arrays
+            .into_iter()
+            .map(|(array_id, mut raw_payloads)| {
+                let raw_payload = match raw_payloads.len() {
+                    0 => RawPayload::Unknown,
+                    _ => raw_payloads.swap_remove(0),
+                };
+                ChannelArray::parse(array_id, raw_payload)
+            })
             .collect::<Result<Vec<ChannelArray>>>()?;
         Ok(Self {
             channel_arrays: channel_arrays,
@@ -31,51 +40,30 @@ pub struct ChannelArray {
     pub payload: ChannelPayload,
 }
 impl ChannelArray {
-    pub fn parse(value: serde_json::Value) -> Result<Self> {
-        let inner_array = value.as_array().chain_err(|| "expected array")?;
-        let array_id = inner_array
-            .get(0)
-            .and_then(|val| val.as_u64())
-            .chain_err(|| "expected array id")?;
-        let raw_data_array = inner_array.get(1).chain_err(|| "expected data array")?;
-        let payload = match &raw_data_array[0] {
-            &serde_json::Value::String(ref s) if s == "noop" => ChannelPayload::Noop,
-            &serde_json::Value::Object(ref m) => {
-                let wrapper_str = m.get("p")
-                    .and_then(|val| val.as_str())
-                    .chain_err(|| "expected string")?;
-                let wrapper_val = serde_json::from_str::<serde_json::Value>(wrapper_str)
+    fn parse(array_id: u64, raw_payload: RawPayload) -> Result<Self> {
+        let payload = match raw_payload {
+            RawPayload::Noop => ChannelPayload::Noop,
+            RawPayload::Wrapped(wrapper_str) => {
+                let wrapper = serde_json::from_str::<Wrapper>(&wrapper_str)
                     .chain_err(|| "failed to parse json")?;
-                let new_client_id_array = wrapper_val.get("3");
-                let new_proto_array = wrapper_val.get("2");
-
-                if new_client_id_array.is_some() {
-                    let client_id = new_client_id_array
-                        .and_then(|value| value.get("2"))
-                        .and_then(|value| value.as_str())
-                        .chain_err(|| "failed to parse client id")?;
-                    ChannelPayload::NewClientID(client_id.to_owned())
-                } else if new_proto_array.is_some() {
-                    let pblite_str = new_proto_array
-                        .and_then(|obj| obj.get("2"))
-                        .and_then(|val| val.as_str())
-                        .chain_err(|| "failed to extract pblite string")?;
-                    let mut pblite_val = serde_json::from_str::<serde_json::Value>(pblite_str)
-                        .chain_err(|| "failed to parse pblite string as json")?;
-                    let pblite_vec = pblite_val
-                        .as_array_mut()
-                        .chain_err(|| "pblite string is not array")?;
-                    // Remove the pblite "header"
-                    // TODO: This can panic, consider moving it to pblite instead.
-                    pblite_vec.remove(0);
-                    let batch_update = hangouts::BatchUpdate::from_vec(&pblite_vec)
+                if let Some(new_client_id) = wrapper.new_client_id {
+                    ChannelPayload::NewClientID(new_client_id.client_id.into_owned())
+                } else if let Some(new_proto) = wrapper.new_proto {
+                    // Decode leniently so that one invalid field doesn't lose the whole batch.
+                    let mut reader = pblite::Reader::new_lenient(&new_proto.pblite);
+                    // Skip the pblite "header".
+                    let batch_update = reader
+                        .read_message_skipping::<hangouts::BatchUpdate>(1)
                         .chain_err(|| "failed to parse BatchUpdate")?;
+                    for warning in reader.take_warnings() {
+                        warn!("Skipped invalid field in BatchUpdate: {}", warning);
+                    }
                     ChannelPayload::BatchUpdate(batch_update)
                 } else {
                     ChannelPayload::Unknown
                 }
             }
-            _ => ChannelPayload::Unknown,
+            RawPayload::Unknown => ChannelPayload::Unknown,
         };
         Ok(ChannelArray {
             array_id: array_id,
@@ -84,6 +72,98 @@ impl ChannelArray {
     }
 }
 
+/// The first item of an array's data, read without building a `serde_json::Value` for the
+/// data.
+enum RawPayload {
+    Noop,
+    /// The "p" string of an object, which is JSON itself.
+    Wrapped(String),
+    Unknown,
+}
+impl<'de> Deserialize<'de> for RawPayload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(RawPayloadVisitor)
+    }
+}
+
+struct RawPayloadVisitor;
+impl<'de> Visitor<'de> for RawPayloadVisitor {
+    type Value = RawPayload;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a channel payload")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> ::std::result::Result<RawPayload, E> {
+        match value {
+            "noop" => Ok(RawPayload::Noop),
+            _ => Ok(RawPayload::Unknown),
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> ::std::result::Result<RawPayload, A::Error> {
+        let mut wrapper_str = None;
+        while let Some(key) = map.next_key::<Cow<str>>()? {
+            if key == "p" {
+                wrapper_str = Some(map.next_value()?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        wrapper_str
+            .map(RawPayload::Wrapped)
+            .ok_or_else(|| de::Error::missing_field("p"))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> ::std::result::Result<RawPayload, A::Error> {
+        while let Some(IgnoredAny) = seq.next_element()? {}
+        Ok(RawPayload::Unknown)
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> ::std::result::Result<RawPayload, E> {
+        Ok(RawPayload::Unknown)
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> ::std::result::Result<RawPayload, E> {
+        Ok(RawPayload::Unknown)
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> ::std::result::Result<RawPayload, E> {
+        Ok(RawPayload::Unknown)
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> ::std::result::Result<RawPayload, E> {
+        Ok(RawPayload::Unknown)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> ::std::result::Result<RawPayload, E> {
+        Ok(RawPayload::Unknown)
+    }
+}
+
+/// The JSON object encoded in the "p" string of a payload. Strings without escape sequences are
+/// borrowed from the "p" string, but the pblite string always contains escaped quotes, so it is
+/// copied once while unescaping it. The `pblite::Reader` then borrows from that copy.
+#[derive(Deserialize)]
+struct Wrapper<'a> {
+    #[serde(rename = "2", borrow, default)]
+    new_proto: Option<NewProto<'a>>,
+    #[serde(rename = "3", borrow, default)]
+    new_client_id: Option<NewClientID<'a>>,
+}
+
+#[derive(Deserialize)]
+struct NewProto<'a> {
+    #[serde(rename = "2", borrow)]
+    pblite: Cow<'a, str>,
+}
+
+#[derive(Deserialize)]
+struct NewClientID<'a> {
+    #[serde(rename = "2", borrow)]
+    client_id: Cow<'a, str>,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ChannelPayload {
     Noop,
@@ -135,6 +215,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_unknown() {
+        let unknown = "[[1,[\"c\",\"SID\",\"\",8]\n]\n,[2,[{\"p\":\"{}\",\"q\":[1]}]]\n,[3,[]]\n,[4,[[\"x\"],1]]]\n";
+        let container_array = ContainerArray::parse(unknown).unwrap();
+        let payloads = container_array
+            .channel_arrays
+            .iter()
+            .map(|channel_array| (channel_array.array_id, &channel_array.payload))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            payloads,
+            vec![
+                (1, &ChannelPayload::Unknown),
+                (2, &ChannelPayload::Unknown),
+                (3, &ChannelPayload::Unknown),
+                (4, &ChannelPayload::Unknown),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for invalid in &["{}", "[[\"1\",[\"noop\"]]]", "[[1,[{\"q\":1}]]]", "[[1,[{\"p\":\"[\"}]]]"] {
+            assert!(ContainerArray::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_bench_chunks() {
+        let container_array = ContainerArray::parse(include_str!("bench_data/event_backlog.txt")).unwrap();
+        assert_eq!(container_array.channel_arrays.len(), 1);
+        match container_array.channel_arrays[0].payload {
+            ChannelPayload::BatchUpdate(ref batch_update) => {
+                let state_updates = batch_update.state_update.as_ref().unwrap();
+                assert_eq!(state_updates.len(), 40);
+                assert!(state_updates.iter().all(|state_update| state_update.unknown_3.is_some()));
+            }
+            ref payload => panic!("unexpected payload {:?}", payload),
+        }
+        let container_array = ContainerArray::parse(include_str!("bench_data/mixed_updates.txt")).unwrap();
+        assert_eq!(container_array.channel_arrays.len(), 6);
+        for channel_array in &container_array.channel_arrays {
+            assert_matches!(channel_array.payload, ChannelPayload::BatchUpdate(_));
+        }
+    }
+
     #[test]
     fn test_parse_noop() {
         let noop = "[[6,[\"noop\"]\n]\n]\n";
@@ -0,0 +1,477 @@
+//! Decoding pblite messages with a schema loaded at runtime, for inspecting traffic without
+//! generating code.
+
+extern crate serde_json;
+
+use hangups_codegen;
+use hangups_codegen::descriptor::{EnumDescriptor, FieldDescriptor, FieldType, FileDescriptor,
+                                  Label, MessageDescriptor};
+use pblite;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+error_chain! {
+    errors {
+        UnknownType(name: String) {
+            description("unknown type"),
+            display("type '{}' is not defined", name),
+        }
+    }
+    links {
+        Pblite(pblite::Error, pblite::ErrorKind);
+        Codegen(hangups_codegen::Error, hangups_codegen::ErrorKind);
+    }
+}
+
+/// Message and enum definitions from one or more .proto files, by fully-qualified name.
+#[derive(Debug, Default)]
+pub struct DescriptorPool {
+    messages: HashMap<String, MessageDescriptor>,
+    enums: HashMap<String, EnumDescriptor>,
+}
+impl DescriptorPool {
+    /// Loads a serialized FileDescriptorSet, as written by `protoc --descriptor_set_out`.
+    pub fn from_descriptor_set(bytes: &[u8]) -> Result<Self> {
+        let mut pool = DescriptorPool::default();
+        for file in hangups_codegen::descriptor_set::parse(bytes)? {
+            pool.add_file(file);
+        }
+        pool.check_types()?;
+        Ok(pool)
+    }
+
+    /// Loads the source of a .proto file.
+    pub fn from_proto(source: &str, name: &str) -> Result<Self> {
+        let mut pool = DescriptorPool::default();
+        pool.add_file(hangups_codegen::parser::parse(source, name)?);
+        pool.check_types()?;
+        Ok(pool)
+    }
+
+    fn add_file(&mut self, file: FileDescriptor) {
+        let scope = match file.package {
+            Some(ref package) => format!(".{}.", package),
+            None => ".".to_owned(),
+        };
+        self.add_types(&scope, file.message_types, file.enum_types);
+    }
+
+    fn add_types(
+        &mut self,
+        scope: &str,
+        messages: Vec<MessageDescriptor>,
+        enums: Vec<EnumDescriptor>,
+    ) {
+        for mut message in messages {
+            let full_name = format!("{}{}", scope, message.name);
+            let nested_types = message.nested_types.drain(..).collect();
+            let enum_types = message.enum_types.drain(..).collect();
+            self.add_types(&format!("{}.", full_name), nested_types, enum_types);
+            self.messages.insert(full_name, message);
+        }
+        for enum_descriptor in enums {
+            self.enums
+                .insert(format!("{}{}", scope, enum_descriptor.name), enum_descriptor);
+        }
+    }
+
+    /// Checks that the message and enum types of all fields are defined, so they can be looked up
+    /// while decoding.
+    fn check_types(&self) -> Result<()> {
+        for field in self.messages.values().flat_map(|message| &message.fields) {
+            match field.field_type {
+                FieldType::Message => self.get_type(&self.messages, field).map(|_| ())?,
+                FieldType::Enum => self.get_type(&self.enums, field).map(|_| ())?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes a pblite array as the message type with the given fully-qualified name, eg.
+    /// "hangouts.BatchUpdate".
+    pub fn decode(&self, message_name: &str, array: &Vec<Value>) -> Result<DynamicMessage> {
+        let full_name = if message_name.starts_with('.') {
+            message_name.to_owned()
+        } else {
+            format!(".{}", message_name)
+        };
+        let descriptor = self.messages
+            .get(&full_name)
+            .ok_or(ErrorKind::UnknownType(message_name.to_owned()))?;
+        Ok(self.read_message(&full_name, descriptor, array)
+            .chain_err(|| pblite::ErrorKind::InvalidMessage(full_name[1..].to_owned()))?)
+    }
+
+    /// Decodes pblite text as the message type with the given fully-qualified name.
+    pub fn decode_pblite(&self, message_name: &str, text: &str) -> Result<DynamicMessage> {
+        let value = serde_json::from_str::<Value>(text).chain_err(|| "invalid json")?;
+        let array = value
+            .as_array()
+            .ok_or(pblite::expected_value("array", &value))?;
+        self.decode(message_name, array)
+    }
+
+    fn read_message(
+        &self,
+        full_name: &str,
+        descriptor: &MessageDescriptor,
+        array: &Vec<Value>,
+    ) -> pblite::Result<DynamicMessage> {
+        let name = &full_name[1..];
+        let mut message = DynamicMessage {
+            name: name.to_owned(),
+            fields: Vec::new(),
+        };
+        for (number, field_value) in pblite::get_field_values(array)? {
+            if field_value.is_null() {
+                continue;
+            }
+            let field = descriptor
+                .fields
+                .iter()
+                .find(|field| field.number as usize == number + 1);
+            let value = match field {
+                Some(field) => self.read_field(field, field_value).map_err(|error| {
+                    pblite::map_decode_error(error, |decode_error| {
+                        decode_error.in_field(name, Some(field.name.as_str()), number + 1)
+                    })
+                })?,
+                None => DynamicValue::Unknown(field_value.clone()),
+            };
+            message.fields.push(DynamicField {
+                name: field.map(|field| field.name.to_owned()),
+                number: number + 1,
+                value: value,
+            });
+        }
+        Ok(message)
+    }
+
+    fn read_field(&self, field: &FieldDescriptor, value: &Value) -> pblite::Result<DynamicValue> {
+        match field.label {
+            // Map fields are read as their repeated entry messages, with key and value fields.
+            Label::Repeated => {
+                let values = pblite::read_array(value, &|elem| self.read_value(field, elem))?;
+                Ok(DynamicValue::Repeated(values.unwrap_or_default()))
+            }
+            Label::Optional | Label::Required => self.read_value(field, value),
+        }
+    }
+
+    fn read_value(&self, field: &FieldDescriptor, value: &Value) -> pblite::Result<DynamicValue> {
+        Ok(match field.field_type {
+            FieldType::Double | FieldType::Float => DynamicValue::Double(pblite::read_double(value)?),
+            FieldType::Int32
+            | FieldType::Int64
+            | FieldType::Sint32
+            | FieldType::Sint64
+            | FieldType::Sfixed32
+            | FieldType::Sfixed64 => DynamicValue::Int(pblite::read_int64(value)?),
+            FieldType::Uint32 | FieldType::Uint64 | FieldType::Fixed32 | FieldType::Fixed64 => {
+                DynamicValue::Uint(pblite::read_uint64(value)?)
+            }
+            FieldType::Bool => DynamicValue::Bool(pblite::read_bool(value)?),
+            FieldType::String => DynamicValue::String(pblite::read_string(value)?),
+            FieldType::Bytes => DynamicValue::Bytes(pblite::read_bytes(value)?),
+            FieldType::Enum => {
+                let number = pblite::read_int32(value)?;
+                let name = self.get_checked_type(&self.enums, field)
+                    .values
+                    .iter()
+                    .find(|enum_value| enum_value.number == number)
+                    .map(|enum_value| enum_value.name.to_owned());
+                DynamicValue::Enum(number, name)
+            }
+            FieldType::Message => {
+                let full_name = field.type_name.as_ref().map_or("", |name| name.as_str());
+                let descriptor = self.get_checked_type(&self.messages, field);
+                let array = value
+                    .as_array()
+                    .ok_or(pblite::expected_value("array", value))?;
+                DynamicValue::Message(self.read_message(full_name, descriptor, array)?)
+            }
+            FieldType::Group => DynamicValue::Unknown(value.clone()),
+        })
+    }
+
+    fn get_type<'a, T>(
+        &self,
+        types: &'a HashMap<String, T>,
+        field: &FieldDescriptor,
+    ) -> Result<&'a T> {
+        let type_name = field.type_name.as_ref().map_or("", |name| name.as_str());
+        types.get(type_name).ok_or_else(|| {
+            // Type names are fully-qualified, with a leading dot.
+            ErrorKind::UnknownType(type_name.get(1..).unwrap_or("").to_owned()).into()
+        })
+    }
+
+    /// Returns the type of a field, which `check_types` has checked is defined.
+    fn get_checked_type<'a, T>(
+        &self,
+        types: &'a HashMap<String, T>,
+        field: &FieldDescriptor,
+    ) -> &'a T {
+        self.get_type(types, field)
+            .expect("field types are checked when the pool is loaded")
+    }
+}
+
+/// A message decoded with a `DescriptorPool`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DynamicMessage {
+    /// Fully-qualified name of the message type, eg. "hangouts.BatchUpdate".
+    pub name: String,
+    /// Fields that are set, in the order they appear in the pblite array.
+    pub fields: Vec<DynamicField>,
+}
+impl DynamicMessage {
+    /// Returns the value of the field with the given name, if it is set.
+    pub fn get(&self, name: &str) -> Option<&DynamicValue> {
+        self.fields
+            .iter()
+            .find(|field| field.name.as_ref().map_or(false, |field_name| field_name == name))
+            .map(|field| &field.value)
+    }
+
+    fn fmt_fields(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        for field in &self.fields {
+            let name = field
+                .name
+                .to_owned()
+                .unwrap_or_else(|| field.number.to_string());
+            match field.value {
+                DynamicValue::Repeated(ref values) => for value in values {
+                    value.fmt_field(f, &name, indent)?;
+                },
+                ref value => value.fmt_field(f, &name, indent)?,
+            }
+        }
+        Ok(())
+    }
+}
+/// Formats the message like the protobuf text format, with field numbers in place of the names
+/// of unknown fields.
+impl fmt::Display for DynamicMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_fields(f, 0)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DynamicField {
+    /// Name of the field, or `None` if it is not defined in the message type.
+    pub name: Option<String>,
+    pub number: usize,
+    pub value: DynamicValue,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum DynamicValue {
+    Double(f64),
+    Int(i64),
+    Uint(u64),
+    Bool(bool),
+    String(String),
+    Bytes(Vec<u8>),
+    /// An enum value's number, and its name if the number is defined.
+    Enum(i32, Option<String>),
+    Message(DynamicMessage),
+    Repeated(Vec<DynamicValue>),
+    /// The raw value of a field that is not defined in the message type.
+    Unknown(Value),
+}
+impl DynamicValue {
+    fn fmt_field(&self, f: &mut fmt::Formatter, name: &str, indent: usize) -> fmt::Result {
+        write!(f, "{:indent$}{}", "", name, indent = indent)?;
+        match self {
+            &DynamicValue::Double(value) => writeln!(f, ": {}", value),
+            &DynamicValue::Int(value) => writeln!(f, ": {}", value),
+            &DynamicValue::Uint(value) => writeln!(f, ": {}", value),
+            &DynamicValue::Bool(value) => writeln!(f, ": {}", value),
+            &DynamicValue::String(ref value) => writeln!(f, ": {:?}", value),
+            &DynamicValue::Bytes(ref value) => writeln!(
+                f,
+                ": \"{}\"",
+                value
+                    .iter()
+                    .flat_map(|&byte| ::std::ascii::escape_default(byte))
+                    .map(|byte| byte as char)
+                    .collect::<String>()
+            ),
+            &DynamicValue::Enum(number, ref name) => match name {
+                &Some(ref name) => writeln!(f, ": {}", name),
+                &None => writeln!(f, ": {}", number),
+            },
+            &DynamicValue::Message(ref message) => {
+                writeln!(f, " {{")?;
+                message.fmt_fields(f, indent + 2)?;
+                writeln!(f, "{:indent$}}}", "", indent = indent)
+            }
+            &DynamicValue::Repeated(ref values) => writeln!(f, ": [{} values]", values.len()),
+            &DynamicValue::Unknown(ref value) => writeln!(f, ": {}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use dynamic::{DescriptorPool, DynamicField, DynamicMessage, DynamicValue, ErrorKind};
+    use std::borrow::Cow;
+    use wire;
+
+    fn get_example_pool() -> DescriptorPool {
+        DescriptorPool::from_proto(include_str!("example.proto"), "example.proto").unwrap()
+    }
+
+    fn length_delimited(number: u32, bytes: Vec<u8>, writer: &mut wire::Writer) {
+        writer.write_field(number, wire::FieldValue::LengthDelimited(Cow::Owned(bytes)));
+    }
+
+    #[test]
+    fn test_decode() {
+        let message = get_example_pool()
+            .decode_pblite("example.PhoneNumber", "[\"+15551234567\",[\"CA\",1,null,5]]")
+            .unwrap();
+        assert_eq!(
+            message,
+            DynamicMessage {
+                name: "example.PhoneNumber".to_owned(),
+                fields: vec![
+                    DynamicField {
+                        name: Some("e164".to_owned()),
+                        number: 1,
+                        value: DynamicValue::String("+15551234567".to_owned()),
+                    },
+                    DynamicField {
+                        name: Some("i18n_data".to_owned()),
+                        number: 2,
+                        value: DynamicValue::Message(DynamicMessage {
+                            name: "example.I18nData".to_owned(),
+                            fields: vec![
+                                DynamicField {
+                                    name: Some("region_code".to_owned()),
+                                    number: 1,
+                                    value: DynamicValue::String("CA".to_owned()),
+                                },
+                                DynamicField {
+                                    name: Some("is_valid".to_owned()),
+                                    number: 2,
+                                    value: DynamicValue::Bool(true),
+                                },
+                                DynamicField {
+                                    name: None,
+                                    number: 4,
+                                    value: DynamicValue::Unknown(json!(5)),
+                                },
+                            ],
+                        }),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_nested() {
+        let message = get_example_pool()
+            .decode_pblite(".example.NestedExample", "[[\"foo\",2],[1,3]]")
+            .unwrap();
+        assert_eq!(
+            message.get("test_repeated_enum"),
+            Some(&DynamicValue::Repeated(vec![
+                DynamicValue::Enum(1, Some("INNER_ENUM_VALUE_1".to_owned())),
+                DynamicValue::Enum(3, None),
+            ]))
+        );
+        assert_eq!(
+            message.to_string(),
+            "test_inner {\n  test_string: \"foo\"\n  test_enum: INNER_ENUM_VALUE_2\n}\n\
+             test_repeated_enum: INNER_ENUM_VALUE_1\ntest_repeated_enum: 3\n"
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_value() {
+        let error = get_example_pool()
+            .decode_pblite("example.PhoneNumber", "[null,[\"CA\",\"foo\"]]")
+            .err()
+            .expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        let decode_error = error
+            .1
+            .next_error
+            .as_ref()
+            .and_then(|error| error.downcast_ref::<::pblite::Error>())
+            .and_then(|error| error.get_decode_error())
+            .map(|decode_error| decode_error.to_string());
+        assert_eq!(
+            decode_error,
+            Some(
+                "example.PhoneNumber.i18n_data.is_valid (field 2): expected u64 value but got: \
+                 '\"foo\"'"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn test_decode_unknown_type() {
+        let error = get_example_pool()
+            .decode_pblite("example.Missing", "[]")
+            .err()
+            .expect("expected error");
+        assert_eq!(error.to_string(), "type 'example.Missing' is not defined");
+    }
+
+    /// Returns a FileDescriptorSet with "message Foo" in "package foo", with one field.
+    fn get_descriptor_set(field: wire::Writer) -> Vec<u8> {
+        let mut message = wire::Writer::new();
+        length_delimited(1, b"Foo".to_vec(), &mut message);
+        length_delimited(2, field.into_bytes(), &mut message);
+        let mut file = wire::Writer::new();
+        length_delimited(1, b"foo.proto".to_vec(), &mut file);
+        length_delimited(2, b"foo".to_vec(), &mut file);
+        length_delimited(4, message.into_bytes(), &mut file);
+        let mut descriptor_set = wire::Writer::new();
+        length_delimited(1, file.into_bytes(), &mut descriptor_set);
+        descriptor_set.into_bytes()
+    }
+
+    #[test]
+    fn test_from_descriptor_set() {
+        // optional string bar = 1;
+        let mut field = wire::Writer::new();
+        length_delimited(1, b"bar".to_vec(), &mut field);
+        field.write_field(3, wire::FieldValue::Varint(1));
+        field.write_field(4, wire::FieldValue::Varint(1));
+        field.write_field(5, wire::FieldValue::Varint(9));
+
+        let pool = DescriptorPool::from_descriptor_set(&get_descriptor_set(field)).unwrap();
+        let message = pool.decode_pblite("foo.Foo", "[\"baz\"]").unwrap();
+        assert_eq!(
+            message.get("bar"),
+            Some(&DynamicValue::String("baz".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_from_descriptor_set_undefined_type() {
+        // optional Bar bar = 1;
+        let mut field = wire::Writer::new();
+        length_delimited(1, b"bar".to_vec(), &mut field);
+        field.write_field(3, wire::FieldValue::Varint(1));
+        field.write_field(4, wire::FieldValue::Varint(1));
+        field.write_field(5, wire::FieldValue::Varint(11));
+        length_delimited(6, b".foo.Bar".to_vec(), &mut field);
+
+        let error = DescriptorPool::from_descriptor_set(&get_descriptor_set(field))
+            .err()
+            .expect("expected error");
+        assert_matches!(error.kind(), &ErrorKind::UnknownType(ref name) if name == "foo.Bar");
+        assert_eq!(error.to_string(), "type 'foo.Bar' is not defined");
+    }
+}
@@ -0,0 +1,691 @@
+//! A local server that fakes the BrowserChannel `bind` endpoint of the Hangouts channel, so that
+//! `Channel` can be tested against scripted responses without network access.
+
+use channel::{ChannelConfig, ReconnectPolicy};
+use futures::{future, Future, Sink, Stream};
+use futures::future::Either;
+use futures::stream;
+use futures::sync::oneshot;
+use hangouts;
+use hyper;
+use hyper::{Chunk, Method, StatusCode};
+use hyper::header::ContentLength;
+use hyper::server::{Http, Request, Response, Service};
+use pblite::Message;
+use serde_json;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle};
+
+pub const SESSION_ID: &str = "FAKE_SID";
+pub const G_SESSION_ID: &str = "FAKE_GSID";
+const BIND_PATH: &str = "/client-channel/channel/bind";
+
+/// Data of a channel array pushed to the client.
+#[derive(Debug, Clone)]
+pub enum Payload {
+    Noop,
+    NewClientID(String),
+    BatchUpdate(hangouts::BatchUpdate),
+    /// Data that is sent as is, eg. for payloads that the client doesn't know about.
+    Raw(serde_json::Value),
+}
+impl Payload {
+    fn to_value(&self) -> serde_json::Value {
+        match self {
+            &Payload::Noop => json!(["noop"]),
+            &Payload::NewClientID(ref client_id) => {
+                json!([{"p": json!({"3": {"2": client_id}}).to_string()}])
+            }
+            &Payload::BatchUpdate(ref batch_update) => {
+                // The "cbu" header is the first item of the message's array.
+                let pblite = format!("[\"cbu\",{}", &batch_update.to_pblite()[1..]);
+                json!([{"p": json!({"2": {"2": pblite}}).to_string()}])
+            }
+            &Payload::Raw(ref value) => value.clone(),
+        }
+    }
+}
+
+/// Response to a long polling request.
+#[derive(Debug, Clone)]
+pub enum Poll {
+    /// Streams each payload as a separate chunk, then ends the response.
+    Payloads(Vec<Payload>),
+    /// Like `Payloads`, but with the given array IDs instead of the next ones, eg. to send an
+    /// array again.
+    Arrays(Vec<(u64, Payload)>),
+    /// Responds with an error status instead.
+    Status(StatusCode),
+    /// Streams the payloads like `Payloads`, but then stops sending without ending the response.
+    Stall(Vec<Payload>),
+    /// Never responds.
+    NoResponse,
+}
+
+/// Request sending maps, which the server can be told to leave unanswered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapsRequest {
+    CreateSession,
+    AddServices,
+}
+
+/// A request received by the server.
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    pub method: Method,
+    pub query: HashMap<String, String>,
+    /// Fields of the form in the body, with their values percent-decoded.
+    pub form: HashMap<String, String>,
+    pub headers: hyper::Headers,
+}
+impl ReceivedRequest {
+    /// Returns the service names of an `add_services` request.
+    pub fn get_service_names(&self) -> Vec<String> {
+        let count = self.form
+            .get("count")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0);
+        (0..count)
+            .filter_map(|num| self.form.get(&format!("req{}_p", num)))
+            .filter_map(|map| serde_json::from_str::<serde_json::Value>(map).ok())
+            .filter_map(|map| {
+                map.pointer("/3/1/1")
+                    .and_then(|name| name.as_str())
+                    .map(|name| name.to_owned())
+            })
+            .collect()
+    }
+}
+
+struct State {
+    polls: VecDeque<Poll>,
+    requests: Vec<ReceivedRequest>,
+    /// Number of connections accepted.
+    connections: usize,
+    unanswered: HashSet<MapsRequest>,
+    /// ID of the next array sent by `Poll::Payloads`, which starts again in a new session.
+    next_array_id: u64,
+}
+impl State {
+    /// Assigns the next array IDs to the payloads.
+    fn get_next_arrays(&mut self, payloads: Vec<Payload>) -> Vec<(u64, Payload)> {
+        let first_array_id = self.next_array_id;
+        self.next_array_id += payloads.len() as u64;
+        (first_array_id..).zip(payloads).collect()
+    }
+}
+
+/// A fake channel server listening on a local port, which stops when dropped.
+///
+/// Creating a session and adding services succeed unless left unanswered. Each long polling request is answered
+/// with the next `Poll` of the script, and once the script is exhausted with status 400, which is
+/// what the real server does for an expired session.
+pub struct FakeServer {
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+impl FakeServer {
+    pub fn start(polls: Vec<Poll>) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            polls: polls.into_iter().collect(),
+            requests: Vec::new(),
+            connections: 0,
+            unanswered: HashSet::new(),
+            next_array_id: 1,
+        }));
+        let (address_tx, address_rx) = mpsc::channel();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let server_state = state.clone();
+        let thread = thread::spawn(move || {
+            let mut core = Core::new().unwrap();
+            let handle = core.handle();
+            let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), &handle).unwrap();
+            address_tx.send(listener.local_addr().unwrap()).unwrap();
+            let http = Http::<Chunk>::new();
+            let server = listener.incoming().for_each(|(socket, _)| {
+                server_state.lock().unwrap().connections += 1;
+                let service = FakeService {
+                    state: server_state.clone(),
+                    handle: handle.clone(),
+                };
+                handle.spawn(
+                    http.serve_connection(socket, service)
+                        .map(|_| ())
+                        .map_err(|e| warn!("Fake server connection failed: {}", e)),
+                );
+                Ok(())
+            });
+            // Stop when either the listener fails or the server is dropped.
+            let _ = core.run(server.map_err(|_| ()).select(shutdown_rx.map_err(|_| ())));
+        });
+        FakeServer {
+            address: address_rx.recv().unwrap(),
+            state: state,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns a configuration for connecting a `Channel` to this server, which gives up after
+    /// the first failed request.
+    pub fn get_config(&self) -> ChannelConfig {
+        ChannelConfig {
+            base_url: format!("http://{}/client-channel", self.address),
+            use_tls: false,
+            reconnect_policy: ReconnectPolicy {
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(10),
+                max_retries: Some(0),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Stops responding to requests of the given kind.
+    pub fn leave_unanswered(&self, request: MapsRequest) {
+        self.state.lock().unwrap().unanswered.insert(request);
+    }
+
+    /// Returns the number of connections accepted so far.
+    pub fn get_connection_count(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    /// Returns the requests received so far, in the order they were received.
+    pub fn get_requests(&self) -> Vec<ReceivedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct FakeService {
+    state: Arc<Mutex<State>>,
+    handle: Handle,
+}
+impl Service for FakeService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, request: Request) -> Self::Future {
+        let (method, uri, _, headers, body) = request.deconstruct();
+        let state = self.state.clone();
+        let handle = self.handle.clone();
+        Box::new(body.concat2().and_then(move |body| {
+            let request = ReceivedRequest {
+                method: method,
+                query: parse_form(uri.query().unwrap_or("")),
+                form: parse_form(&String::from_utf8_lossy(&body)),
+                headers: headers,
+            };
+            let mut state = state.lock().unwrap();
+            state.requests.push(request.clone());
+            match respond(&mut state, &request, &handle, uri.path()) {
+                Some(response) => Either::A(future::ok(response)),
+                None => Either::B(future::empty()),
+            }
+        }))
+    }
+}
+
+/// Returns the response to a request, or `None` to never respond.
+fn respond(
+    state: &mut State,
+    request: &ReceivedRequest,
+    handle: &Handle,
+    path: &str,
+) -> Option<Response> {
+    if path != BIND_PATH {
+        return Some(Response::new().with_status(StatusCode::NotFound));
+    }
+    let session_id = request.query.get("SID").map(|sid| sid.as_str());
+    let response = match (&request.method, session_id) {
+        (&Method::Post, None) if state.unanswered.contains(&MapsRequest::CreateSession) => {
+            return None
+        }
+        (&Method::Post, None) => {
+            state.next_array_id = 1;
+            let session = json!([
+                [0, ["c", SESSION_ID, "", 8]],
+                [1, [{"gsid": G_SESSION_ID}]],
+            ]);
+            get_chunked_response(&session.to_string())
+        }
+        (&Method::Post, Some(SESSION_ID))
+            if state.unanswered.contains(&MapsRequest::AddServices) =>
+        {
+            return None
+        }
+        (&Method::Post, Some(SESSION_ID)) => get_chunked_response("[1,0,0]"),
+        (&Method::Get, Some(SESSION_ID)) => match state.polls.pop_front() {
+            Some(Poll::Payloads(payloads)) => {
+                let arrays = state.get_next_arrays(payloads);
+                get_streaming_response(handle, arrays, true)
+            }
+            Some(Poll::Arrays(arrays)) => {
+                let array_ids = arrays.iter().map(|&(array_id, _)| array_id + 1);
+                state.next_array_id = array_ids.fold(state.next_array_id, cmp::max);
+                get_streaming_response(handle, arrays, true)
+            }
+            Some(Poll::Status(status)) => Response::new().with_status(status),
+            Some(Poll::Stall(payloads)) => {
+                let arrays = state.get_next_arrays(payloads);
+                get_streaming_response(handle, arrays, false)
+            }
+            Some(Poll::NoResponse) => return None,
+            None => Response::new().with_status(StatusCode::BadRequest),
+        },
+        _ => Response::new().with_status(StatusCode::BadRequest),
+    };
+    Some(response)
+}
+
+/// Returns a response that streams each array as a separate chunk, and then ends unless `end` is
+/// false.
+fn get_streaming_response(handle: &Handle, arrays: Vec<(u64, Payload)>, end: bool) -> Response {
+    let mut chunks = Vec::new();
+    for (array_id, payload) in arrays {
+        let container = json!([[array_id, payload.to_value()]]);
+        let bytes = get_chunk(&container.to_string()).into_bytes();
+        // Split every chunk to exercise decoding across reads.
+        let (first, second) = bytes.split_at(bytes.len() / 2);
+        chunks.push(Ok(Chunk::from(first.to_vec())));
+        chunks.push(Ok(Chunk::from(second.to_vec())));
+    }
+    let (sender, body) = hyper::Body::pair();
+    handle.spawn(
+        sender
+            .send_all(stream::iter_ok(chunks))
+            .map_err(|_| ())
+            .and_then(move |(sender, _)| {
+                if end {
+                    Either::A(future::ok(()))
+                } else {
+                    // Keep the response open by keeping the sender until the server stops.
+                    Either::B(future::empty().map(move |()| drop(sender)))
+                }
+            }),
+    );
+    Response::new().with_body(body)
+}
+
+/// Returns a response containing `data` as a single chunk.
+fn get_chunked_response(data: &str) -> Response {
+    let chunk = get_chunk(data);
+    Response::new()
+        .with_header(ContentLength(chunk.len() as u64))
+        .with_body(chunk)
+}
+
+/// Prefixes `data` with its length in UTF-16 code units, like the channel does.
+fn get_chunk(data: &str) -> String {
+    format!("{}\n{}", data.encode_utf16().count(), data)
+}
+
+/// Parses URL encoded `key=value` pairs.
+fn parse_form(form: &str) -> HashMap<String, String> {
+    form.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match (bytes[i], s.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) => u8::from_str_radix(hex, 16).ok(),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use channel;
+    use fake_server::{FakeServer, MapsRequest, Payload, Poll};
+    use hangouts;
+    use hyper::{header, Method, StatusCode};
+    use std::cell::RefCell;
+    use std::time::Duration;
+
+    fn get_channel(config: channel::ChannelConfig) -> channel::Channel {
+        let mut cookies = header::Cookie::new();
+        cookies.append("SAPISID", "jBoR10LFQqxvjDQy/Azg6q-5kgeQ-MiaKF");
+        channel::Channel::new(cookies, config).unwrap()
+    }
+
+    fn get_batch_update(conversation_id: &str) -> hangouts::BatchUpdate {
+        hangouts::BatchUpdate {
+            state_update: Some(vec![hangouts::StateUpdate {
+                conversation: Some(hangouts::Conversation {
+                    conversation_id: Some(hangouts::ConversationId {
+                        id: Some(conversation_id.to_owned()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }
+    }
+
+    fn get_conversation_ids(state_updates: &[hangouts::StateUpdate]) -> Vec<String> {
+        state_updates
+            .iter()
+            .filter_map(|state_update| state_update.conversation.as_ref())
+            .filter_map(|conversation| conversation.conversation_id.as_ref())
+            .filter_map(|conversation_id| conversation_id.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(super::percent_decode("%7B%22a%22%3A1%7D+b%2"), "{\"a\":1} b%2");
+    }
+
+    #[test]
+    fn test_listen_receives_state_updates() {
+        let server = FakeServer::start(vec![
+            Poll::Payloads(vec![
+                Payload::NewClientID("CLIENT_ID".to_owned()),
+                Payload::Noop,
+                Payload::BatchUpdate(get_batch_update("conversation1")),
+            ]),
+            Poll::Payloads(vec![
+                Payload::Raw(json!([{"p": "{\"4\":{}}"}])),
+                Payload::BatchUpdate(get_batch_update("conversation2")),
+            ]),
+        ]);
+        let state_updates = RefCell::new(Vec::new());
+        let error = get_channel(server.get_config())
+            .listen(
+                &|state_update| state_updates.borrow_mut().push(state_update),
+                &|_| {},
+            )
+            .err()
+            .expect("expected error");
+
+        assert_matches!(error.kind(), &channel::ErrorKind::Disconnected);
+        assert_eq!(
+            get_conversation_ids(&state_updates.borrow()),
+            vec!["conversation1", "conversation2"]
+        );
+        let requests = server.get_requests();
+        let methods: Vec<_> = requests.iter().map(|request| request.method.clone()).collect();
+        assert_eq!(
+            methods,
+            vec![
+                Method::Post,
+                Method::Get,
+                Method::Post,
+                Method::Get,
+                Method::Get,
+            ]
+        );
+        assert_eq!(
+            requests[2].get_service_names(),
+            vec!["babel", "babel_presence_last_seen"]
+        );
+        for request in &requests[1..] {
+            assert_eq!(request.query["SID"], super::SESSION_ID);
+            assert_eq!(request.query["gsessionid"], super::G_SESSION_ID);
+        }
+    }
+
+    #[test]
+    fn test_listen_bad_status() {
+        let server = FakeServer::start(vec![
+            Poll::Payloads(vec![Payload::Noop]),
+            Poll::Status(StatusCode::InternalServerError),
+        ]);
+        let error = get_channel(server.get_config())
+            .listen(&|_| panic!("unexpected state update"), &|_| {})
+            .err()
+            .expect("expected error");
+        assert!(
+            error
+                .iter()
+                .any(|e| e.to_string() == "bad status: 500 Internal Server Error")
+        );
+        assert_eq!(server.get_requests().len(), 3);
+    }
+
+    #[test]
+    fn test_listen_reconnects() {
+        let server = FakeServer::start(vec![
+            Poll::Payloads(vec![
+                Payload::NewClientID("CLIENT_ID".to_owned()),
+                Payload::BatchUpdate(get_batch_update("conversation1")),
+            ]),
+            Poll::Status(StatusCode::BadRequest),
+            Poll::Status(StatusCode::InternalServerError),
+            Poll::Payloads(vec![Payload::BatchUpdate(get_batch_update("conversation2"))]),
+        ]);
+        let mut config = server.get_config();
+        config.reconnect_policy.max_retries = Some(2);
+        let state_updates = RefCell::new(Vec::new());
+        let connection_states = RefCell::new(Vec::new());
+        get_channel(config)
+            .listen(
+                &|state_update| state_updates.borrow_mut().push(state_update),
+                &|connection_state| {
+                    connection_states.borrow_mut().push(match connection_state {
+                        channel::ConnectionState::Reconnecting { failures, .. } => {
+                            format!("reconnecting {}", failures)
+                        }
+                        connection_state => format!("{:?}", connection_state),
+                    })
+                },
+            )
+            .err()
+            .expect("expected error");
+
+        assert_eq!(
+            get_conversation_ids(&state_updates.borrow()),
+            vec!["conversation1", "conversation2"]
+        );
+        assert_eq!(
+            *connection_states.borrow(),
+            vec![
+                "Connected",
+                "reconnecting 1",
+                "reconnecting 2",
+                "Connected",
+                "reconnecting 1",
+                "reconnecting 2",
+                "Disconnected",
+            ]
+        );
+        // A new session is created after each 400 response, but not after the 500 response.
+        let requests = server.get_requests();
+        assert_eq!(requests.len(), 12);
+        let new_sessions = requests
+            .iter()
+            .filter(|request| !request.query.contains_key("SID"))
+            .count();
+        assert_eq!(new_sessions, 4);
+    }
+
+    #[test]
+    fn test_listen_skips_repeated_arrays() {
+        let server = FakeServer::start(vec![
+            Poll::Payloads(vec![
+                Payload::NewClientID("CLIENT_ID".to_owned()),
+                Payload::BatchUpdate(get_batch_update("conversation1")),
+            ]),
+            Poll::Arrays(vec![
+                (2, Payload::BatchUpdate(get_batch_update("conversation1"))),
+                (3, Payload::BatchUpdate(get_batch_update("conversation2"))),
+            ]),
+        ]);
+        let state_updates = RefCell::new(Vec::new());
+        get_channel(server.get_config())
+            .listen(
+                &|state_update| state_updates.borrow_mut().push(state_update),
+                &|_| {},
+            )
+            .err()
+            .expect("expected error");
+
+        assert_eq!(
+            get_conversation_ids(&state_updates.borrow()),
+            vec!["conversation1", "conversation2"]
+        );
+        let array_ids: Vec<_> = server
+            .get_requests()
+            .iter()
+            .filter(|request| request.method == Method::Get)
+            .map(|request| request.query.get("AID").cloned())
+            .collect();
+        assert_eq!(
+            array_ids,
+            vec![None, Some("2".to_owned()), Some("3".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_listen_response_timeout() {
+        let server = FakeServer::start(vec![Poll::NoResponse]);
+        let mut config = server.get_config();
+        config.timeouts.response = Duration::from_millis(100);
+        let error = get_channel(config)
+            .listen(&|_| panic!("unexpected state update"), &|_| {})
+            .err()
+            .expect("expected error");
+        assert!(
+            error
+                .iter()
+                .any(|e| e.to_string() == "timed out waiting for response")
+        );
+    }
+
+    #[test]
+    fn test_listen_create_session_timeout() {
+        let server = FakeServer::start(vec![]);
+        server.leave_unanswered(MapsRequest::CreateSession);
+        let mut config = server.get_config();
+        config.timeouts.response = Duration::from_millis(100);
+        let error = get_channel(config)
+            .listen(&|_| panic!("unexpected state update"), &|_| {})
+            .err()
+            .expect("expected error");
+        assert!(
+            error
+                .iter()
+                .any(|e| e.to_string() == "timed out waiting for response")
+        );
+        assert_eq!(server.get_requests().len(), 1);
+    }
+
+    #[test]
+    fn test_listen_add_services_timeout() {
+        let server = FakeServer::start(vec![
+            Poll::Stall(vec![Payload::NewClientID("CLIENT_ID".to_owned())]),
+        ]);
+        server.leave_unanswered(MapsRequest::AddServices);
+        let mut config = server.get_config();
+        config.timeouts.response = Duration::from_millis(100);
+        let error = get_channel(config)
+            .listen(&|_| panic!("unexpected state update"), &|_| {})
+            .err()
+            .expect("expected error");
+        assert!(
+            error
+                .iter()
+                .any(|e| e.to_string() == "timed out waiting for response")
+        );
+        assert_eq!(server.get_requests().len(), 3);
+    }
+
+    #[test]
+    fn test_listen_chunk_timeout() {
+        let server = FakeServer::start(vec![
+            Poll::Stall(vec![
+                Payload::NewClientID("CLIENT_ID".to_owned()),
+                Payload::BatchUpdate(get_batch_update("conversation1")),
+            ]),
+            Poll::Payloads(vec![Payload::BatchUpdate(get_batch_update("conversation2"))]),
+        ]);
+        let mut config = server.get_config();
+        config.reconnect_policy.max_retries = Some(1);
+        config.timeouts.chunk = Duration::from_millis(100);
+        let state_updates = RefCell::new(Vec::new());
+        get_channel(config)
+            .listen(
+                &|state_update| state_updates.borrow_mut().push(state_update),
+                &|_| {},
+            )
+            .err()
+            .expect("expected error");
+
+        // The stalled request is retried in the same session.
+        assert_eq!(
+            get_conversation_ids(&state_updates.borrow()),
+            vec!["conversation1", "conversation2"]
+        );
+        let array_ids: Vec<_> = server
+            .get_requests()
+            .iter()
+            .filter(|request| request.method == Method::Get)
+            .map(|request| request.query.get("AID").cloned())
+            .collect();
+        assert_eq!(array_ids[..2], [None, Some("2".to_owned())]);
+    }
+
+    #[test]
+    fn test_listen_reuses_connections() {
+        let server = FakeServer::start(vec![
+            Poll::Payloads(vec![Payload::NewClientID("CLIENT_ID".to_owned())]),
+            Poll::Payloads(vec![Payload::Noop]),
+            Poll::Payloads(vec![Payload::Noop]),
+        ]);
+        get_channel(server.get_config())
+            .listen(&|_| panic!("unexpected state update"), &|_| {})
+            .err()
+            .expect("expected error");
+        assert_eq!(server.get_requests().len(), 6);
+        // Adding services needs a second connection because the long polling request is open.
+        assert_eq!(server.get_connection_count(), 2);
+    }
+
+}
+
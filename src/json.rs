@@ -0,0 +1,609 @@
+//! Canonical protobuf JSON mapping, for the same generated message types as `pblite`.
+//!
+//! Fields are named in lowerCamelCase, 64-bit integers are strings, bytes are base64 and enums
+//! are value names, so messages can be exchanged with standard protobuf tooling.
+
+extern crate base64;
+extern crate serde_json;
+
+use pblite;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+error_chain!{
+    links {
+        Pblite(pblite::Error, pblite::ErrorKind);
+    }
+    errors {
+        InvalidMessage(name: String) {
+            description("invalid message"),
+            display("message '{}' is invalid", name),
+        }
+        InvalidField(name: String) {
+            description("invalid field"),
+            display("field '{}' is invalid", name),
+        }
+        UnknownField(name: String) {
+            description("unknown field"),
+            display("field '{}' is not defined", name),
+        }
+        ExpectedValue(expected: &'static str, actual: Value) {
+            description("expected value"),
+            display("expected {} value but got: '{}'", expected, actual),
+        }
+    }
+}
+
+fn expected_value(expected: &'static str, actual: &Value) -> Error {
+    ErrorKind::ExpectedValue(expected, actual.clone()).into()
+}
+
+pub trait Enum: pblite::Enum {
+    fn from_name(name: &str) -> Option<Self>;
+
+    /// Returns the name of the value, or `None` if it is unrecognised.
+    fn to_name(&self) -> Option<&'static str>;
+}
+
+/// A message that can also be encoded in the canonical JSON mapping.
+///
+/// Unknown fields are rejected when decoding, and `pblite::UnknownFields` are not encoded.
+pub trait Message: pblite::Message {
+    /// Sets a field by its JSON name or original name, returning false if there is no such
+    /// field.
+    fn set_json_field(&mut self, name: &str, field_value: &Value) -> Result<bool>;
+
+    /// Returns the JSON names and values of the fields.
+    fn get_json_fields(&self) -> Vec<(&'static str, Value)>;
+
+    fn from_json_value(value: &Value) -> Result<Self> {
+        let mut message = Self::default();
+        || -> Result<_> {
+            let object = value.as_object().ok_or(expected_value("object", value))?;
+            for (name, field_value) in object {
+                if !message
+                    .set_json_field(name, field_value)
+                    .chain_err(|| ErrorKind::InvalidField(name.to_owned()))?
+                {
+                    return Err(ErrorKind::UnknownField(name.to_owned()).into());
+                }
+            }
+            Ok(message.check_required()?)
+        }()
+            .chain_err(|| ErrorKind::InvalidMessage(message.get_name().to_owned()))?;
+        Ok(message)
+    }
+
+    fn from_json(text: &str) -> Result<Self> {
+        serde_json::from_str(text)
+            .chain_err(|| "invalid json")
+            .and_then(|value| Self::from_json_value(&value))
+    }
+
+    fn to_json_value(&self) -> Value {
+        Value::Object(
+            self.get_json_fields()
+                .into_iter()
+                .filter(|&(_, ref field_value)| !field_value.is_null())
+                .map(|(name, field_value)| (name.to_owned(), field_value))
+                .collect(),
+        )
+    }
+
+    fn to_json(&self) -> String {
+        self.to_json_value().to_string()
+    }
+}
+
+/// Reads an integer from either a number or a string, which may be written with a fraction or
+/// exponent if it is integral, eg. "1.0" or "1e2".
+fn read_integer<T: FromStr>(value: &Value, expected: &'static str) -> Result<T> {
+    let text = match value {
+        &Value::Number(ref number) => number.to_string(),
+        &Value::String(ref string) => string.to_owned(),
+        _ => return Err(expected_value(expected, value)),
+    };
+    text.parse()
+        .ok()
+        .or_else(|| to_plain_integer(&text).and_then(|text| text.parse().ok()))
+        .ok_or(expected_value(expected, value))
+}
+
+/// Rewrites a decimal number with a fraction or exponent as a plain integer, eg. "1.5e1" as "15",
+/// or returns `None` if it isn't integral. The digits are shifted rather than converted to a
+/// float, so large integers keep their precision.
+fn to_plain_integer(text: &str) -> Option<String> {
+    let (sign, unsigned) = match text.starts_with('-') {
+        true => ("-", &text[1..]),
+        false => ("", text),
+    };
+    let (mantissa, exponent) = match unsigned.find(&['e', 'E'][..]) {
+        Some(index) => (&unsigned[..index], unsigned[index + 1..].parse::<i64>().ok()?),
+        None => (unsigned, 0),
+    };
+    let (integer, fraction) = match mantissa.find('.') {
+        Some(index) => (&mantissa[..index], Some(&mantissa[index + 1..])),
+        None => (mantissa, None),
+    };
+    let is_digits = |text: &str| !text.is_empty() && text.bytes().all(|c| c.is_ascii_digit());
+    if !is_digits(integer) || fraction.iter().any(|fraction| !is_digits(fraction)) {
+        return None;
+    }
+    let fraction = fraction.unwrap_or("");
+    let digits = format!("{}{}", integer, fraction);
+    let significant = digits.trim_start_matches('0');
+    if significant.is_empty() {
+        return Some("0".to_owned());
+    }
+    // The position of the decimal point within the significant digits.
+    let point = integer.len() as i64 - (digits.len() - significant.len()) as i64 + exponent;
+    if point < significant.len() as i64 {
+        let (integer, fraction) = significant.split_at(point.max(0) as usize);
+        if point <= 0 || fraction.bytes().any(|c| c != b'0') {
+            return None;
+        }
+        return Some(format!("{}{}", sign, integer));
+    }
+    // Larger numbers are out of range for every integer type.
+    if point > 20 {
+        return None;
+    }
+    let zeros = "0".repeat(point as usize - significant.len());
+    Some(format!("{}{}{}", sign, significant, zeros))
+}
+
+/// Reads a floating point number with `read`, which reads the special values "NaN", "Infinity"
+/// and "-Infinity" like pblite. Other numbers may also be strings.
+fn read_floating_point<A>(
+    value: &Value,
+    expected: &'static str,
+    read: fn(&Value) -> pblite::Result<A>,
+) -> Result<A> {
+    let number = match value {
+        &Value::String(ref string) => serde_json::from_str(string).ok().map(Value::Number),
+        _ => None,
+    };
+    read(number.as_ref().unwrap_or(value)).or(Err(expected_value(expected, value)))
+}
+
+pub fn read_string(value: &Value) -> Result<String> {
+    value
+        .as_str()
+        .map(|s| s.to_owned())
+        .ok_or(expected_value("string", value))
+}
+
+pub fn read_bool(value: &Value) -> Result<bool> {
+    match value {
+        &Value::Bool(value) => Ok(value),
+        // Map keys are always strings.
+        &Value::String(ref string) if string == "true" => Ok(true),
+        &Value::String(ref string) if string == "false" => Ok(false),
+        _ => Err(expected_value("bool", value)),
+    }
+}
+
+pub fn read_enum<E: Enum>(value: &Value) -> Result<E> {
+    match value {
+        &Value::String(ref name) => E::from_name(name).ok_or(expected_value("enum name", value)),
+        _ => Ok(E::from_u32(read_integer::<i32>(value, "enum")? as u32)?),
+    }
+}
+
+pub fn read_int32(value: &Value) -> Result<i32> {
+    read_integer(value, "int32")
+}
+
+pub fn read_int64(value: &Value) -> Result<i64> {
+    read_integer(value, "int64")
+}
+
+pub fn read_uint32(value: &Value) -> Result<u32> {
+    read_integer(value, "uint32")
+}
+
+pub fn read_uint64(value: &Value) -> Result<u64> {
+    read_integer(value, "uint64")
+}
+
+pub fn read_double(value: &Value) -> Result<f64> {
+    read_floating_point(value, "number", pblite::read_double)
+}
+
+pub fn read_float(value: &Value) -> Result<f32> {
+    read_floating_point(value, "float", pblite::read_float)
+}
+
+pub fn read_bytes(value: &Value) -> Result<Vec<u8>> {
+    // Both the standard and URL-safe base64 alphabets are accepted.
+    value
+        .as_str()
+        .ok_or(expected_value("string", value))
+        .and_then(|s| {
+            base64::decode(s)
+                .or(base64::decode_config(s, base64::URL_SAFE))
+                .or(Err(expected_value("base64", value)))
+        })
+}
+
+pub fn read_message<M: Message>(value: &Value) -> Result<M> {
+    M::from_json_value(value)
+}
+
+pub fn read_array<A>(value: &Value, read_elem: &Fn(&Value) -> Result<A>) -> Result<Option<Vec<A>>> {
+    match value {
+        &Value::Array(ref vec) => Ok(Some(vec.iter()
+            .map(|val| read_elem(val))
+            .collect::<Result<_>>()?)),
+        &Value::Null => Ok(None),
+        _ => Err(expected_value("array", value)),
+    }
+}
+
+pub fn read_optional<A>(value: &Value, read_inner: &Fn(&Value) -> Result<A>) -> Result<Option<A>> {
+    match value {
+        &Value::Null => Ok(None),
+        value => Ok(Some(read_inner(value)?)),
+    }
+}
+
+/// Reads a map from an object, whose keys are read as strings.
+pub fn read_map<K: Ord, V>(
+    value: &Value,
+    read_key: &Fn(&Value) -> Result<K>,
+    read_value: &Fn(&Value) -> Result<V>,
+) -> Result<Option<BTreeMap<K, V>>> {
+    match value {
+        &Value::Object(ref object) => Ok(Some(object
+            .iter()
+            .map(|(key, value)| Ok((read_key(&Value::from(key.as_str()))?, read_value(value)?)))
+            .collect::<Result<_>>()?)),
+        &Value::Null => Ok(None),
+        _ => Err(expected_value("object", value)),
+    }
+}
+
+/// Sets a oneof to a member's value, unless the member is null.
+pub fn read_oneof<A, O>(
+    oneof: &mut Option<O>,
+    value: &Value,
+    read_inner: &Fn(&Value) -> Result<A>,
+    variant: fn(A) -> O,
+) -> Result<()> {
+    if let Some(inner) = read_optional(value, read_inner)? {
+        *oneof = Some(variant(inner));
+    }
+    Ok(())
+}
+
+pub fn write_string(value: &String) -> Value {
+    Value::String(value.to_owned())
+}
+
+pub fn write_bool(value: &bool) -> Value {
+    Value::Bool(*value)
+}
+
+pub fn write_enum<E: Enum>(value: &E) -> Value {
+    match value.to_name() {
+        Some(name) => Value::from(name),
+        None => Value::from(value.to_u32() as i32),
+    }
+}
+
+pub fn write_int32(value: &i32) -> Value {
+    Value::from(*value)
+}
+
+pub fn write_int64(value: &i64) -> Value {
+    Value::String(value.to_string())
+}
+
+pub fn write_uint32(value: &u32) -> Value {
+    Value::from(*value)
+}
+
+pub fn write_uint64(value: &u64) -> Value {
+    Value::String(value.to_string())
+}
+
+pub fn write_double(value: &f64) -> Value {
+    pblite::write_double(value)
+}
+
+pub fn write_float(value: &f32) -> Value {
+    pblite::write_float(value)
+}
+
+pub fn write_bytes(value: &Vec<u8>) -> Value {
+    Value::String(base64::encode(value))
+}
+
+pub fn write_message<M: Message>(value: &M) -> Value {
+    value.to_json_value()
+}
+
+pub fn write_array<A>(value: &Option<Vec<A>>, write_elem: &Fn(&A) -> Value) -> Value {
+    match value {
+        &Some(ref vec) => Value::Array(vec.iter().map(|val| write_elem(val)).collect()),
+        &None => Value::Null,
+    }
+}
+
+pub fn write_optional<A>(value: &Option<A>, write_inner: &Fn(&A) -> Value) -> Value {
+    match value {
+        &Some(ref value) => write_inner(value),
+        &None => Value::Null,
+    }
+}
+
+pub fn write_map<K, V>(
+    value: &Option<BTreeMap<K, V>>,
+    write_key: &Fn(&K) -> Value,
+    write_value: &Fn(&V) -> Value,
+) -> Value {
+    match value {
+        &Some(ref map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    let key = match write_key(key) {
+                        Value::String(key) => key,
+                        key => key.to_string(),
+                    };
+                    (key, write_value(value))
+                })
+                .collect(),
+        ),
+        &None => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use example;
+    use json::Message;
+    use pblite::Message as PbliteMessage;
+
+    #[test]
+    fn test_i18n_data() {
+        let i18n_data = example::I18nData::from_json(
+            "{\"regionCode\":\"CA\",\"isValid\":true,\"country_code\":1}",
+        ).unwrap();
+        assert_eq!(
+            i18n_data,
+            example::I18nData {
+                region_code: Some("CA".to_owned()),
+                is_valid: Some(true),
+                country_code: Some(1),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            i18n_data.to_json_value(),
+            json!({"regionCode": "CA", "isValid": true, "countryCode": 1})
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!(example::Empty::from_json("{}").unwrap(), example::Empty::default());
+        assert_eq!(example::Empty::default().to_json(), "{}");
+    }
+
+    #[test]
+    fn test_null_fields() {
+        let i18n_data = example::I18nData::from_json("{\"regionCode\":null}").unwrap();
+        assert_eq!(i18n_data, example::I18nData::default());
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        let result = example::Empty::from_json("{\"foo\":1}");
+        let error = result.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        assert_eq!(error.iter().last().unwrap().to_string(), "field 'foo' is not defined");
+    }
+
+    #[test]
+    fn test_expected_object() {
+        let result = example::Empty::from_json("[]");
+        assert_eq!(
+            result.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_example() {
+        let example = example::Example::from_pblite(
+            "[1.5,\"64\",32,1,\"foo\",\"AAEC\",2,[],[],[\"18446744073709551615\"],[],[],[],[],[2,100]]",
+        ).unwrap();
+        assert_eq!(
+            example.to_json_value(),
+            json!({
+                "testDouble": 1.5,
+                "testUint64": "64",
+                "testUint32": 32,
+                "testBool": true,
+                "testString": "foo",
+                "testBytes": "AAEC",
+                "testEnum": "EXAMPLE_ENUM_VALUE_2",
+                "testMessage": {},
+                "testRepeatedDouble": [],
+                "testRepeatedUint64": ["18446744073709551615"],
+                "testRepeatedUint32": [],
+                "testRepeatedBool": [],
+                "testRepeatedString": [],
+                "testRepeatedBytes": [],
+                "testRepeatedEnum": ["EXAMPLE_ENUM_VALUE_2", 100],
+            })
+        );
+        assert_eq!(example::Example::from_json(&example.to_json()).unwrap(), example);
+    }
+
+    #[test]
+    fn test_numbers() {
+        let numbers = example::ExampleNumbers::from_json(
+            "{\"testInt32\":\"-1\",\"testInt64\":-2,\"testFloat\":\"NaN\",\
+             \"testRepeatedFloat\":[0.1,\"-Infinity\"]}",
+        ).unwrap();
+        assert_eq!(numbers.test_int32, Some(-1));
+        assert_eq!(numbers.test_int64, Some(-2));
+        assert!(numbers.test_float.unwrap().is_nan());
+        assert_eq!(
+            numbers.to_json_value(),
+            json!({
+                "testInt32": -1,
+                "testInt64": "-2",
+                "testFloat": "NaN",
+                "testRepeatedFloat": [0.1, "-Infinity"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_integral_numbers() {
+        let numbers = example::ExampleNumbers::from_json(
+            "{\"testInt32\":1.0,\"testInt64\":\"-1.5e1\",\"testSint32\":1e2,\
+             \"testFixed64\":\"18446744073709551615.0\",\"testSfixed32\":\"-0.0\",\
+             \"testRepeatedInt32\":[0.1e1,\"2E0\",300e-2]}",
+        ).unwrap();
+        assert_eq!(numbers.test_int32, Some(1));
+        assert_eq!(numbers.test_int64, Some(-15));
+        assert_eq!(numbers.test_sint32, Some(100));
+        assert_eq!(numbers.test_fixed64, Some(::std::u64::MAX));
+        assert_eq!(numbers.test_sfixed32, Some(0));
+        assert_eq!(numbers.test_repeated_int32, Some(vec![1, 2, 3]));
+        for json in &[
+            "{\"testInt32\":1.5}",
+            "{\"testInt32\":\"1e-1\"}",
+            "{\"testInt32\":\"1.\"}",
+            "{\"testInt32\":\".5\"}",
+            "{\"testInt32\":\"1e\"}",
+            "{\"testInt32\":3e9}",
+            "{\"testInt64\":\"1e100000000000\"}",
+            "{\"testFixed64\":\"18446744073709551616.0\"}",
+            "{\"testFixed32\":-1.0}",
+        ] {
+            let result = example::ExampleNumbers::from_json(json);
+            assert_eq!(
+                result.err().expect("expected error").description(),
+                "invalid message",
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn test_float_out_of_range() {
+        let numbers =
+            example::ExampleNumbers::from_json("{\"testFloat\":3.4028235e38}").unwrap();
+        assert_eq!(numbers.test_float, Some(::std::f32::MAX));
+        let numbers = example::ExampleNumbers::from_json("{\"testFloat\":\"-Infinity\"}").unwrap();
+        assert_eq!(numbers.test_float, Some(::std::f32::NEG_INFINITY));
+        let result = example::ExampleNumbers::from_json("{\"testFloat\":3.5e38}");
+        let error = result.err().expect("expected error");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "expected float value but got: '3.5e+38'"
+        );
+    }
+
+    #[test]
+    fn test_quoted_floats() {
+        let example = example::Example::from_json("{\"testDouble\":\"-1.5e2\"}").unwrap();
+        assert_eq!(example.test_double, Some(-150.0));
+        let numbers = example::ExampleNumbers::from_json("{\"testFloat\":\"0.1\"}").unwrap();
+        assert_eq!(numbers.test_float, Some(0.1));
+        for json in &["{\"testDouble\":\"1e400\"}", "{\"testDouble\":\"nan\"}"] {
+            let error = example::Example::from_json(json)
+                .err()
+                .expect("expected error");
+            assert_eq!(
+                error.iter().last().unwrap().to_string(),
+                format!("expected number value but got: '{}'", &json[14..json.len() - 1])
+            );
+        }
+    }
+
+    #[test]
+    fn test_int32_out_of_range() {
+        let result = example::ExampleNumbers::from_json("{\"testInt32\":2147483648}");
+        assert_eq!(
+            result.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_enum_unknown_name() {
+        let result = example::Example::from_json("{\"testEnum\":\"FOO\"}");
+        assert_eq!(
+            result.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_bytes_url_safe() {
+        let example = example::Example::from_json("{\"testBytes\":\"-_8\"}").unwrap();
+        assert_eq!(example.test_bytes, Some(vec![0xfb, 0xff]));
+        assert_eq!(example.to_json_value(), json!({"testBytes": "+/8="}));
+    }
+
+    #[test]
+    fn test_map() {
+        let map = example::MapExample::from_json(
+            "{\"testMap\":{\"foo\":1},\"testMessageMap\":{\"2\":{\"regionCode\":\"CA\"}}}",
+        ).unwrap();
+        assert_eq!(map.test_map.as_ref().unwrap()["foo"], 1);
+        assert_eq!(
+            map.test_message_map.as_ref().unwrap()[&2].region_code,
+            Some("CA".to_owned())
+        );
+        assert_eq!(
+            map.to_json_value(),
+            json!({"testMap": {"foo": 1}, "testMessageMap": {"2": {"regionCode": "CA"}}})
+        );
+    }
+
+    #[test]
+    fn test_oneof() {
+        let oneof = example::OneofExample::from_json("{\"testOneofString\":\"foo\"}").unwrap();
+        assert_eq!(
+            oneof.test_oneof,
+            Some(example::OneofExample_TestOneof::TestOneofString(
+                "foo".to_owned(),
+            ))
+        );
+        assert_eq!(oneof.to_json_value(), json!({"testOneofString": "foo"}));
+    }
+
+    #[test]
+    fn test_required_field_missing() {
+        let result = example::RequiredExample::from_json("{}");
+        let error = result.err().expect("expected error");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "required field 'test_required_string' is missing"
+        );
+    }
+
+    fn assert_round_trip<M: Message + PartialEq + ::std::fmt::Debug>(pblite: &str) {
+        let message = M::from_pblite(pblite).unwrap();
+        assert_eq!(M::from_json(&message.to_json()).unwrap(), message);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_round_trip::<example::PhoneNumber>("[\"16067624137\",[\"CA\", 1, 123]]");
+        assert_round_trip::<example::Example>("[3.14159,\"64\",32,1,\"foo\",\"AAEC\",2,[],[3.14159,1.1],[64,65],[32,33],[1,0],[\"foo\",\"bar\"],[\"AAEC\",\"AgEA\"],[2,3],[[],[]]]");
+        assert_round_trip::<example::ExampleNumbers>("[-1,\"-2\",-3,\"-4\",5,\"6\",-7,\"-8\",9.5,[-1,2],[\"-3\",4],[1.5]]");
+        assert_round_trip::<example::NestedExample>("[[\"foo\",2],[1,2],[\"1\"]]");
+        assert_round_trip::<example::SparseExample>("[\"foo\",{\"101\":5,\"250\":[\"CA\"]}]");
+        assert_round_trip::<example::MapExample>("[[[\"foo\",1]],[[1,[\"CA\"]],[2,[]]]]");
+    }
+}
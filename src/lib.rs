@@ -1,3 +1,5 @@
+#![cfg_attr(all(test, feature = "nightly"), feature(test))]
+
 #[cfg(test)]
 #[macro_use]
 extern crate assert_matches;
@@ -5,19 +7,28 @@ extern crate assert_matches;
 extern crate error_chain;
 extern crate fern;
 extern crate futures;
+extern crate hangups_codegen;
+#[cfg_attr(test, macro_use)]
+extern crate hangups_derive;
 #[macro_use]
 extern crate hyper;
 extern crate hyper_tls;
 #[macro_use]
 extern crate log;
 extern crate native_tls;
+extern crate rand;
+// Code derived by `PbliteMessage` refers to serde through this crate.
+pub extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
-extern crate serde_json;
+pub extern crate serde_json;
 extern crate sha1;
+#[cfg(all(test, feature = "nightly"))]
+extern crate test;
 extern crate time;
 extern crate tokio_core;
+extern crate tokio_service;
 
 use std::thread;
 use std::sync::mpsc;
@@ -25,13 +36,30 @@ use std::os::raw::c_char;
 use std::ffi::CString;
 use error_chain::ChainedError;
 
-mod pblite;
-mod example;
-mod hangouts;
+pub use hangups_derive::PbliteMessage;
+
+pub mod pblite;
+pub mod pblite_serde;
+pub mod json;
+pub mod wire;
+pub mod dynamic;
+// Generated from the test proto, whose accessors aren't all exercised.
+#[cfg(test)]
+#[allow(dead_code)]
+mod example {
+    include!(concat!(env!("OUT_DIR"), "/example.rs"));
+}
+pub mod hangouts {
+    include!(concat!(env!("OUT_DIR"), "/hangouts.rs"));
+}
 mod decoder;
 mod channel;
 mod auth;
 mod channel_parser;
+#[cfg(test)]
+mod fake_server;
+#[cfg(all(test, feature = "nightly"))]
+mod benches;
 
 #[no_mangle]
 pub extern "C" fn say_hello() {
@@ -98,14 +126,20 @@ impl Client {
         setup_logger().unwrap();
 
         let cookies = auth::get_cookies()?;
-        let mut channel = channel::Channel::new(cookies);
 
         let (tx, rx) = mpsc::channel();
         thread::spawn(move || {
-            match channel.listen(&|state_update| {
-                let state_update_json = serde_json::to_string(&state_update).unwrap();
-                tx.send(state_update_json).unwrap();
-            }) {
+            // The channel's reactor can't be sent between threads, so create it on this one.
+            let result = channel::Channel::new(cookies, channel::ChannelConfig::default())
+                .and_then(|mut channel| {
+                    channel.listen(
+                        &|state_update| {
+                            tx.send(encode_state_update(&state_update)).unwrap();
+                        },
+                        &|connection_state| info!("Channel is {:?}", connection_state),
+                    )
+                });
+            match result {
                 Ok(_) => {}
                 Err(e) => {
                     error!("{}", e.display_chain());
@@ -124,6 +158,12 @@ impl Client {
     }
 }
 
+/// Encodes a state update for `libhangups_client_receive` as JSON, with the field names from the
+/// .proto file and any unknown fields.
+fn encode_state_update(state_update: &hangouts::StateUpdate) -> String {
+    serde_json::to_string(state_update).unwrap()
+}
+
 // TODO: Replace this with a public function to configure a logging callback.
 fn setup_logger() -> Result<(), fern::InitError> {
     println!("setup logging");
@@ -143,3 +183,22 @@ fn setup_logger() -> Result<(), fern::InitError> {
         .apply()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+
+    use hangouts;
+    use pblite::Message;
+
+    #[test]
+    fn test_encode_state_update() {
+        let state_update = hangouts::StateUpdate::from_pblite(
+            "[[null,null,null,null,\"1521002546845000\"],{\"150\":[\"foo\",1]}]",
+        ).unwrap();
+        assert_eq!(
+            ::encode_state_update(&state_update),
+            "{\"state_update_header\":{\"current_server_time\":1521002546845000},\
+             \"unknown_fields\":{\"150\":[\"foo\",1]}}"
+        );
+    }
+}
@@ -2,6 +2,9 @@ extern crate base64;
 extern crate serde_json;
 
 use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
 
 error_chain!{
     errors {
@@ -13,43 +16,519 @@ error_chain!{
             description("invalid field"),
             display("field {} is invalid", number),
         }
-        ExpectedValue(expected: &'static str, actual: Value) {
-            description("expected value"),
-            display("expected {} value but got: '{}'", expected, actual),
+        Decode(error: DecodeError) {
+            description("invalid value"),
+            display("{}", error),
+        }
+        MissingField(name: &'static str) {
+            description("missing field"),
+            display("required field '{}' is missing", name),
+        }
+        UnknownField(name: String) {
+            description("unknown field"),
+            display("field '{}' is not defined", name),
+        }
+        Syntax(message: &'static str, position: usize) {
+            description("invalid json"),
+            display("invalid json at position {}: {}", position, message),
+        }
+    }
+}
+
+impl Error {
+    /// Returns the decode error that caused this error, if any.
+    pub fn get_decode_error(&self) -> Option<&DecodeError> {
+        match self.0 {
+            ErrorKind::Decode(ref decode_error) => Some(decode_error),
+            _ => self.1
+                .next_error
+                .as_ref()
+                .and_then(|error| error.downcast_ref::<Error>())
+                .and_then(|error| error.get_decode_error()),
+        }
+    }
+}
+
+/// Maximum number of characters of the invalid value included in a `DecodeError`.
+const SNIPPET_LENGTH: usize = 80;
+
+/// Position of a value within a message.
+#[derive(Debug, PartialEq, Clone)]
+pub enum PathSegment {
+    /// A field, by name if it is known and otherwise by number.
+    Field(String),
+    /// An element of a repeated field.
+    Index(usize),
+}
+
+/// An invalid value, with its position from the outermost message being decoded.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DecodeError {
+    /// Name of the outermost message, unless the value is not within a message.
+    pub message_name: Option<String>,
+    pub path: Vec<PathSegment>,
+    /// Field number of the innermost field in the path.
+    pub field_number: Option<usize>,
+    /// Type of value that was expected, eg. "string".
+    pub expected: &'static str,
+    /// The invalid value as JSON, truncated to `SNIPPET_LENGTH` characters.
+    pub snippet: String,
+}
+impl DecodeError {
+    fn new(expected: &'static str, actual: &Value) -> Self {
+        let mut snippet = actual.to_string();
+        if let Some((index, _)) = snippet.char_indices().nth(SNIPPET_LENGTH) {
+            snippet.truncate(index);
+            snippet.push_str("...");
+        }
+        DecodeError {
+            message_name: None,
+            path: Vec::new(),
+            field_number: None,
+            expected: expected,
+            snippet: snippet,
+        }
+    }
+
+    /// Records that the invalid value is within a field of a message.
+    pub fn in_field(mut self, message_name: &str, field_name: Option<&str>, number: usize) -> Self {
+        let field_name = field_name.map_or_else(|| number.to_string(), |name| name.to_owned());
+        self.path.insert(0, PathSegment::Field(field_name));
+        self.field_number = self.field_number.or(Some(number));
+        self.message_name = Some(message_name.to_owned());
+        self
+    }
+
+    /// Returns the path to the invalid value, eg. "BatchUpdate.state_update[3].event_notification".
+    pub fn get_path(&self) -> String {
+        let mut path = self.message_name.clone().unwrap_or_default();
+        for segment in &self.path {
+            match segment {
+                &PathSegment::Field(ref name) => {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(name);
+                }
+                &PathSegment::Index(index) => path.push_str(&format!("[{}]", index)),
+            }
+        }
+        path
+    }
+}
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.get_path();
+        if !path.is_empty() {
+            write!(f, "{}", path)?;
+            if let Some(field_number) = self.field_number {
+                write!(f, " (field {})", field_number)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "expected {} value but got: '{}'", self.expected, self.snippet)
+    }
+}
+
+pub fn expected_value(expected: &'static str, actual: &Value) -> Error {
+    ErrorKind::Decode(DecodeError::new(expected, actual)).into()
+}
+
+/// Applies a change to the decode error in `error`, if it is one.
+pub fn map_decode_error<F: FnOnce(DecodeError) -> DecodeError>(error: Error, update: F) -> Error {
+    match error {
+        Error(ErrorKind::Decode(decode_error), state) => {
+            Error(ErrorKind::Decode(update(decode_error)), state)
+        }
+        error => error,
+    }
+}
+
+/// Where errors which are skipped while decoding leniently are recorded.
+///
+/// A `Reader` is its own sink. When decoding a `serde_json::Value`, the sink is passed to
+/// `Message::set_field`: `Strict` fails on the first error, and a `Vec<Error>` collects them.
+pub trait WarningSink {
+    /// Returns the skipped errors, or `None` while decoding strictly.
+    fn get_warnings(&mut self) -> Option<&mut Vec<Error>>;
+}
+
+/// Strict decoding, which records no warnings.
+pub struct Strict;
+impl WarningSink for Strict {
+    fn get_warnings(&mut self) -> Option<&mut Vec<Error>> {
+        None
+    }
+}
+
+/// Lenient decoding, which collects the skipped errors.
+impl WarningSink for Vec<Error> {
+    fn get_warnings(&mut self) -> Option<&mut Vec<Error>> {
+        Some(self)
+    }
+}
+
+/// Records `error` as a warning when decoding leniently, and otherwise returns it.
+fn skip_if_lenient<S: WarningSink + ?Sized>(sink: &mut S, error: Error) -> Result<()> {
+    match sink.get_warnings() {
+        Some(warnings) => {
+            warnings.push(error);
+            Ok(())
+        }
+        None => Err(error),
+    }
+}
+
+fn get_warning_count<S: WarningSink + ?Sized>(sink: &mut S) -> usize {
+    sink.get_warnings().map_or(0, |warnings| warnings.len())
+}
+
+/// Applies a change to the decode errors among the warnings recorded since `start`.
+fn map_warnings_since<S, F>(sink: &mut S, start: usize, update: F)
+where
+    S: WarningSink + ?Sized,
+    F: Fn(DecodeError) -> DecodeError,
+{
+    if let Some(warnings) = sink.get_warnings() {
+        let new_warnings = warnings.split_off(start);
+        warnings.extend(
+            new_warnings
+                .into_iter()
+                .map(|warning| map_decode_error(warning, |decode_error| update(decode_error))),
+        );
+    }
+}
+
+/// Decodes field `number` of `message` with `decode`, adding the field to the path of any decode
+/// errors. Returns whether the field was skipped because its value is invalid and decoding is
+/// lenient.
+fn decode_field<M, S, F>(message: &mut M, number: usize, sink: &mut S, decode: F) -> Result<bool>
+where
+    M: Message,
+    S: WarningSink + ?Sized,
+    F: FnOnce(&mut M, &mut S) -> Result<()>,
+{
+    let warning_count = get_warning_count(sink);
+    let result = decode(message, sink);
+    let name = message.get_name();
+    let field_name = message.get_field_name(number);
+    map_warnings_since(sink, warning_count, |decode_error| {
+        decode_error.in_field(name, field_name, number + 1)
+    });
+    let error = match result {
+        Ok(()) => return Ok(false),
+        // Invalid JSON can't be skipped, since the end of the value is unknown.
+        Err(error @ Error(ErrorKind::Syntax(..), _)) => return Err(error),
+        Err(Error(ErrorKind::Decode(decode_error), state)) => Error(
+            ErrorKind::Decode(decode_error.in_field(name, field_name, number + 1)),
+            state,
+        ),
+        Err(error) => Error::with_chain(
+            Error::with_chain(error, ErrorKind::InvalidField(number + 1)),
+            ErrorKind::InvalidMessage(name.to_owned()),
+        ),
+    };
+    skip_if_lenient(sink, error)?;
+    Ok(true)
+}
+
+/// Decodes element `index` of a repeated field with `decode`, adding the index to the path of
+/// any decode errors.
+fn decode_element<A, S, F>(index: usize, sink: &mut S, decode: F) -> Result<A>
+where
+    S: WarningSink + ?Sized,
+    F: FnOnce(&mut S) -> Result<A>,
+{
+    let add_index = |mut decode_error: DecodeError| {
+        decode_error.path.insert(0, PathSegment::Index(index));
+        decode_error
+    };
+    let warning_count = get_warning_count(sink);
+    let result = decode(sink);
+    map_warnings_since(sink, warning_count, &add_index);
+    result.map_err(|error| map_decode_error(error, &add_index))
+}
+
+/// Returns an error if a required field of a decoded message is not set, unless decoding is
+/// lenient.
+fn check_message<M: Message, S: WarningSink + ?Sized>(message: &M, sink: &mut S) -> Result<()> {
+    if let Err(error) = message.check_required() {
+        skip_if_lenient(sink, Error::with_chain(
+            error,
+            ErrorKind::InvalidMessage(message.get_name().to_owned()),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Fields with numbers above this are encoded in a trailing object instead of by position.
+const SPARSE_FIELD_THRESHOLD: usize = 100;
+
+/// Returns the zero-based field numbers and values of a pblite array, including those from the
+/// optional trailing object of sparse fields.
+pub fn get_field_values(array: &Vec<Value>) -> Result<Vec<(usize, &Value)>> {
+    let mut field_values = Vec::new();
+    let (last, positional) = match array.split_last() {
+        Some((&Value::Object(ref sparse_fields), positional)) => (Some(sparse_fields), positional),
+        _ => (None, array.as_slice()),
+    };
+    for (number, field_value) in positional.iter().enumerate() {
+        field_values.push((number, field_value));
+    }
+    if let Some(sparse_fields) = last {
+        for (key, field_value) in sparse_fields {
+            let number = key.parse::<usize>()
+                .ok()
+                .and_then(|number| number.checked_sub(1))
+                .ok_or(expected_value("field number", &Value::String(key.to_owned())))?;
+            field_values.push((number, field_value));
         }
     }
+    Ok(field_values)
+}
+
+/// Returns the pblite array for zero-based field numbers and values, skipping null values.
+pub fn write_fields<I: IntoIterator<Item = (usize, Value)>>(fields: I) -> Vec<Value> {
+    let mut array = Vec::new();
+    let mut sparse_fields = serde_json::Map::new();
+    for (number, field_value) in fields {
+        if field_value.is_null() {
+            continue;
+        }
+        if number >= SPARSE_FIELD_THRESHOLD {
+            sparse_fields.insert((number + 1).to_string(), field_value);
+            continue;
+        }
+        if array.len() <= number {
+            array.resize(number + 1, Value::Null);
+        }
+        array[number] = field_value;
+    }
+    if !sparse_fields.is_empty() {
+        array.push(Value::Object(sparse_fields));
+    }
+    array
+}
+
+/// Raw values of fields missing from the message definition, keyed by field number.
+pub type UnknownFields = BTreeMap<usize, Value>;
+
+/// Type of a field's values, as declared in the .proto file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FieldType {
+    Double,
+    Float,
+    Int64,
+    Uint64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Message,
+    Bytes,
+    Uint32,
+    Enum,
+    Sfixed32,
+    Sfixed64,
+    Sint32,
+    Sint64,
 }
 
-fn expected_value(expected: &'static str, actual: &Value) -> Error {
-    ErrorKind::ExpectedValue(expected, actual.clone()).into()
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Label {
+    Optional,
+    Required,
+    Repeated,
+}
+
+/// Static description of a field of a generated message.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldDescriptor {
+    pub name: &'static str,
+    pub number: usize,
+    pub label: Label,
+    /// Type of the field's values. For map fields, this is the type of the map's values.
+    pub field_type: FieldType,
+    /// Descriptor of the message type, for message fields.
+    pub message_type: Option<fn() -> &'static MessageDescriptor>,
+    /// Descriptor of the enum type, for enum fields.
+    pub enum_type: Option<fn() -> &'static EnumDescriptor>,
+    /// Name of the oneof the field is a member of, if any.
+    pub oneof: Option<&'static str>,
+    /// Type of the map's keys, for map fields. Map fields are labelled as repeated.
+    pub map_key_type: Option<FieldType>,
+}
+
+/// Static description of a generated message, as returned by `Message::descriptor`.
+#[derive(Debug)]
+pub struct MessageDescriptor {
+    /// Name relative to the package, eg. "Outer.Inner".
+    pub name: &'static str,
+    /// Fully-qualified name, eg. "package.Outer.Inner".
+    pub full_name: &'static str,
+    pub fields: &'static [FieldDescriptor],
+    pub nested_messages: &'static [fn() -> &'static MessageDescriptor],
+    pub nested_enums: &'static [fn() -> &'static EnumDescriptor],
+}
+impl MessageDescriptor {
+    pub fn get_field(&self, name: &str) -> Option<&'static FieldDescriptor> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    pub fn get_field_by_number(&self, number: usize) -> Option<&'static FieldDescriptor> {
+        self.fields.iter().find(|field| field.number == number)
+    }
+}
+
+/// Static description of a generated enum, as returned by `Enum::descriptor`.
+#[derive(Debug)]
+pub struct EnumDescriptor {
+    /// Name relative to the package, eg. "Outer.Inner".
+    pub name: &'static str,
+    /// Fully-qualified name, eg. "package.Outer.Inner".
+    pub full_name: &'static str,
+    pub values: &'static [EnumValueDescriptor],
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct EnumValueDescriptor {
+    pub name: &'static str,
+    pub number: i32,
 }
 
 pub trait Message: Sized + Default {
+    fn descriptor() -> &'static MessageDescriptor;
+
     fn get_name(&self) -> &str;
 
-    fn set_field(&mut self, number: usize, field_value: &Value) -> Result<()>;
+    /// Sets a field from its pblite value, recording errors that are skipped within nested
+    /// messages in `sink`.
+    fn set_field(&mut self, number: usize, field_value: &Value, sink: &mut WarningSink) -> Result<()>;
+
+    /// Like `set_field`, but reads the value from a `Reader`.
+    fn read_field(&mut self, number: usize, reader: &mut Reader) -> Result<()>;
 
+    fn get_field_name(&self, number: usize) -> Option<&'static str> {
+        Self::descriptor()
+            .get_field_by_number(number + 1)
+            .map(|field| field.name)
+    }
+
+    fn get_fields(&self) -> Vec<(usize, Value)>;
+
+    /// Returns the pblite value of a field, which is null if the field is not set, or `None` if
+    /// the message has no such field.
+    fn get_field_by_name(&self, name: &str) -> Option<Value> {
+        let number = Self::descriptor().get_field(name)?.number - 1;
+        Some(self.get_fields()
+            .into_iter()
+            .find(|&(field_number, _)| field_number == number)
+            .map_or(Value::Null, |(_, field_value)| field_value))
+    }
+
+    /// Sets a field from its pblite value, or clears it if the value is null.
+    fn set_field_by_name(&mut self, name: &str, field_value: &Value) -> Result<()> {
+        let descriptor = Self::descriptor();
+        let field = descriptor
+            .get_field(name)
+            .ok_or(ErrorKind::UnknownField(name.to_owned()))?;
+        self.set_field(field.number - 1, field_value, &mut Strict).map_err(|error| {
+            map_decode_error(error, |decode_error| {
+                decode_error.in_field(descriptor.name, Some(field.name), field.number)
+            })
+        })
+    }
+
+    /// Returns an error if a required field is not set.
+    fn check_required(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Decodes a message from a pblite array.
+    ///
+    /// Invalid values are reported as `ErrorKind::Decode` errors, whose path leads from this
+    /// message to the value.
     fn from_vec(array: &Vec<Value>) -> Result<Self> {
+        Self::from_vec_with_sink(array, &mut Strict)
+    }
+
+    /// Decodes a message from a pblite array, recording skipped errors in `sink`. See
+    /// `from_vec_lenient`.
+    fn from_vec_with_sink(array: &Vec<Value>, sink: &mut WarningSink) -> Result<Self> {
         let mut message = Self::default();
-        for (number, field_value) in array.iter().enumerate() {
-            message
-                .set_field(number, field_value)
-                .chain_err(|| ErrorKind::InvalidField(number))
-                .chain_err(|| ErrorKind::InvalidMessage(message.get_name().to_owned()))?;
+        let field_values = get_field_values(array).map_err(|error| {
+            map_decode_error(error, |decode_error| DecodeError {
+                message_name: Some(message.get_name().to_owned()),
+                ..decode_error
+            })
+        })?;
+        for (number, field_value) in field_values {
+            decode_field(&mut message, number, sink, |message, sink| {
+                message.set_field(number, field_value, sink)
+            })?;
         }
+        check_message(&message, sink)?;
         Ok(message)
     }
 
+    /// Decodes a message from a pblite array, leaving fields with invalid values unset instead of
+    /// failing.
+    ///
+    /// Returns the message along with the errors for the skipped fields. Nested messages are also
+    /// decoded leniently, so an error only discards the innermost field containing it. An error is
+    /// still returned if `array` is not a valid message at all.
+    fn from_vec_lenient(array: &Vec<Value>) -> Result<(Self, Vec<Error>)> {
+        let mut warnings = Vec::new();
+        let message = Self::from_vec_with_sink(array, &mut warnings)?;
+        Ok((message, warnings))
+    }
+
+    /// Decodes a message from pblite text, with the same results as `from_vec`.
+    ///
+    /// The text is decoded with a `Reader`, so no `serde_json::Value` is built for it.
     fn from_pblite(text: &str) -> Result<Self> {
-        serde_json::from_str(text)
-            .chain_err(|| "invalid json")
-            .and_then(|v| read_message(&v))
-            .chain_err(|| ErrorKind::InvalidMessage(Self::default().get_name().to_owned()))
+        read_whole_message(&mut Reader::new(text))
     }
+
+    /// Decodes a message from pblite text, leaving fields with invalid values unset instead of
+    /// failing. See `from_vec_lenient`.
+    fn from_pblite_lenient(text: &str) -> Result<(Self, Vec<Error>)> {
+        let mut reader = Reader::new_lenient(text);
+        let message = read_whole_message(&mut reader)?;
+        Ok((message, reader.take_warnings()))
+    }
+
+    fn to_vec(&self) -> Vec<Value> {
+        write_fields(self.get_fields())
+    }
+
+    fn to_value(&self) -> Value {
+        Value::Array(self.to_vec())
+    }
+
+    fn to_pblite(&self) -> String {
+        self.to_value().to_string()
+    }
+}
+
+/// Reads a message which must be all of the reader's text.
+fn read_whole_message<M: Message>(reader: &mut Reader) -> Result<M> {
+    reader
+        .read_message()
+        .and_then(|message| {
+            reader.finish()?;
+            Ok(message)
+        })
+        .chain_err(|| ErrorKind::InvalidMessage(M::default().get_name().to_owned()))
 }
 
 pub trait Enum: Sized {
+    fn descriptor() -> &'static EnumDescriptor;
+
     fn from_u32(value: u32) -> Result<Self>;
+
+    fn to_u32(&self) -> u32;
 }
 
 pub fn read_string(value: &Value) -> Result<String> {
@@ -71,7 +550,8 @@ pub fn read_bool(value: &Value) -> Result<bool> {
 }
 
 pub fn read_enum<E: Enum>(value: &Value) -> Result<E> {
-    E::from_u32(read_uint32(value)?)
+    // Enums are int32, so negative values are represented by their two's complement.
+    E::from_u32(read_int32(value)? as u32)
 }
 
 pub fn read_uint32(value: &Value) -> Result<u32> {
@@ -93,8 +573,47 @@ pub fn read_uint64(value: &Value) -> Result<u64> {
     }
 }
 
+pub fn read_int32(value: &Value) -> Result<i32> {
+    value
+        .as_i64()
+        .ok_or(expected_value("i64", value))
+        .and_then(|n| match n {
+            n if n > ::std::i32::MAX as i64 || n < ::std::i32::MIN as i64 => {
+                Err(expected_value("i32", value))
+            }
+            _ => Ok(n as i32),
+        })
+}
+
+pub fn read_int64(value: &Value) -> Result<i64> {
+    match value {
+        // int64 may be converted to string since JavaScript numbers lack precision.
+        &Value::Number(ref n) => n.as_i64().ok_or(expected_value("i64", value)),
+        &Value::String(ref s) => s.parse::<i64>().or(Err(expected_value("i64", value))),
+        _ => Err(expected_value("number or string", value)),
+    }
+}
+
 pub fn read_double(value: &Value) -> Result<f64> {
-    value.as_f64().ok_or(expected_value("f64", value))
+    match value {
+        // Values which aren't finite are strings, as in the canonical JSON mapping.
+        &Value::String(ref s) => match s.as_str() {
+            "NaN" => Ok(::std::f64::NAN),
+            "Infinity" => Ok(::std::f64::INFINITY),
+            "-Infinity" => Ok(::std::f64::NEG_INFINITY),
+            _ => Err(expected_value("f64", value)),
+        },
+        value => value.as_f64().ok_or(expected_value("f64", value)),
+    }
+}
+
+pub fn read_float(value: &Value) -> Result<f32> {
+    let double = read_double(value)?;
+    // Finite values which are too large for a float are rejected rather than becoming infinite.
+    match double as f32 {
+        n if n.is_infinite() && double.is_finite() => Err(expected_value("f32", value)),
+        n => Ok(n),
+    }
 }
 
 pub fn read_bytes(value: &Value) -> Result<Vec<u8>> {
@@ -105,16 +624,31 @@ pub fn read_bytes(value: &Value) -> Result<Vec<u8>> {
 }
 
 pub fn read_message<M: Message>(value: &Value) -> Result<M> {
+    read_message_with_sink(value, &mut Strict)
+}
+
+/// Like `read_message`, but records errors skipped within the message in `sink`. The
+/// `*_with_sink` functions are used for values which contain messages.
+pub fn read_message_with_sink<M: Message>(value: &Value, sink: &mut WarningSink) -> Result<M> {
     value
         .as_array()
         .ok_or(expected_value("array", value))
-        .and_then(|vec| M::from_vec(vec))
+        .and_then(|vec| M::from_vec_with_sink(vec, sink))
 }
 
 pub fn read_array<A>(value: &Value, read_elem: &Fn(&Value) -> Result<A>) -> Result<Option<Vec<A>>> {
+    read_array_with_sink(value, &mut Strict, &|value, _| read_elem(value))
+}
+
+pub fn read_array_with_sink<A>(
+    value: &Value,
+    sink: &mut WarningSink,
+    read_elem: &Fn(&Value, &mut WarningSink) -> Result<A>,
+) -> Result<Option<Vec<A>>> {
     match value {
         &Value::Array(ref vec) => Ok(Some(vec.iter()
-            .map(|val| read_elem(val))
+            .enumerate()
+            .map(|(index, val)| decode_element(index, sink, |sink| read_elem(val, sink)))
             .collect::<Result<_>>()?)),
         &Value::Null => Ok(None),
         _ => Err(expected_value("array", value)),
@@ -122,17 +656,676 @@ pub fn read_array<A>(value: &Value, read_elem: &Fn(&Value) -> Result<A>) -> Resu
 }
 
 pub fn read_optional<A>(value: &Value, read_inner: &Fn(&Value) -> Result<A>) -> Result<Option<A>> {
+    read_optional_with_sink(value, &mut Strict, &|value, _| read_inner(value))
+}
+
+pub fn read_optional_with_sink<A>(
+    value: &Value,
+    sink: &mut WarningSink,
+    read_inner: &Fn(&Value, &mut WarningSink) -> Result<A>,
+) -> Result<Option<A>> {
     match value {
         &Value::Null => Ok(None),
-        value => Ok(Some(read_inner(value)?)),
+        value => Ok(Some(read_inner(value, sink)?)),
+    }
+}
+
+/// Reads a map, encoded as an array of `[key, value]` entry messages.
+pub fn read_map<K: Ord, V>(
+    value: &Value,
+    read_key: &Fn(&Value) -> Result<K>,
+    read_value: &Fn(&Value) -> Result<V>,
+) -> Result<Option<BTreeMap<K, V>>> {
+    read_map_with_sink(value, &mut Strict, read_key, &|value, _| read_value(value))
+}
+
+pub fn read_map_with_sink<K: Ord, V>(
+    value: &Value,
+    sink: &mut WarningSink,
+    read_key: &Fn(&Value) -> Result<K>,
+    read_value: &Fn(&Value, &mut WarningSink) -> Result<V>,
+) -> Result<Option<BTreeMap<K, V>>> {
+    let read_entry = |entry: &Value, sink: &mut WarningSink| -> Result<(K, V)> {
+        let entry = entry.as_array().ok_or(expected_value("array", entry))?;
+        let get = |index: usize| entry.get(index).unwrap_or(&Value::Null);
+        Ok((read_key(get(0))?, read_value(get(1), sink)?))
+    };
+    Ok(read_array_with_sink(value, sink, &read_entry)?
+        .map(|entries| entries.into_iter().collect()))
+}
+
+pub fn write_string(value: &String) -> Value {
+    Value::String(value.to_owned())
+}
+
+pub fn write_bool(value: &bool) -> Value {
+    Value::from(*value as u32)
+}
+
+pub fn write_enum<E: Enum>(value: &E) -> Value {
+    write_int32(&(value.to_u32() as i32))
+}
+
+pub fn write_uint32(value: &u32) -> Value {
+    Value::from(*value)
+}
+
+pub fn write_uint64(value: &u64) -> Value {
+    Value::from(*value)
+}
+
+pub fn write_int32(value: &i32) -> Value {
+    Value::from(*value)
+}
+
+pub fn write_int64(value: &i64) -> Value {
+    Value::from(*value)
+}
+
+pub fn write_double(value: &f64) -> Value {
+    if value.is_nan() {
+        Value::from("NaN")
+    } else if *value == ::std::f64::INFINITY {
+        Value::from("Infinity")
+    } else if *value == ::std::f64::NEG_INFINITY {
+        Value::from("-Infinity")
+    } else {
+        Value::from(*value)
+    }
+}
+
+pub fn write_float(value: &f32) -> Value {
+    // Widen using the shortest representation, so that eg. 0.1 is not written as
+    // 0.10000000149011612.
+    write_double(&value.to_string().parse().unwrap_or(*value as f64))
+}
+
+pub fn write_bytes(value: &Vec<u8>) -> Value {
+    Value::String(base64::encode(value))
+}
+
+pub fn write_message<M: Message>(value: &M) -> Value {
+    value.to_value()
+}
+
+pub fn write_array<A>(value: &Option<Vec<A>>, write_elem: &Fn(&A) -> Value) -> Value {
+    match value {
+        &Some(ref vec) => Value::Array(vec.iter().map(|val| write_elem(val)).collect()),
+        &None => Value::Null,
+    }
+}
+
+pub fn write_optional<A>(value: &Option<A>, write_inner: &Fn(&A) -> Value) -> Value {
+    match value {
+        &Some(ref value) => write_inner(value),
+        &None => Value::Null,
+    }
+}
+
+pub fn write_map<K, V>(
+    value: &Option<BTreeMap<K, V>>,
+    write_key: &Fn(&K) -> Value,
+    write_value: &Fn(&V) -> Value,
+) -> Value {
+    match value {
+        &Some(ref map) => Value::Array(
+            map.iter()
+                .map(|(key, value)| Value::Array(vec![write_key(key), write_value(value)]))
+                .collect(),
+        ),
+        &None => Value::Null,
+    }
+}
+
+/// Sets a oneof to a member's value, unless the member is null.
+pub fn read_oneof<A, O>(
+    oneof: &mut Option<O>,
+    value: &Value,
+    read_inner: &Fn(&Value) -> Result<A>,
+    variant: fn(A) -> O,
+) -> Result<()> {
+    read_oneof_with_sink(oneof, value, &mut Strict, &|value, _| read_inner(value), variant)
+}
+
+pub fn read_oneof_with_sink<A, O>(
+    oneof: &mut Option<O>,
+    value: &Value,
+    sink: &mut WarningSink,
+    read_inner: &Fn(&Value, &mut WarningSink) -> Result<A>,
+    variant: fn(A) -> O,
+) -> Result<()> {
+    if let Some(inner) = read_optional_with_sink(value, sink, read_inner)? {
+        *oneof = Some(variant(inner));
+    }
+    Ok(())
+}
+
+pub fn check_required<A>(value: &Option<A>, name: &'static str) -> Result<()> {
+    match value {
+        &Some(_) => Ok(()),
+        &None => Err(ErrorKind::MissingField(name).into()),
+    }
+}
+
+pub fn read_unknown_field(unknown_fields: &mut UnknownFields, number: usize, value: &Value) {
+    match value {
+        &Value::Null => {}
+        value => {
+            unknown_fields.insert(number + 1, value.clone());
+        }
+    }
+}
+
+pub fn write_unknown_fields(unknown_fields: &UnknownFields) -> Vec<(usize, Value)> {
+    unknown_fields
+        .iter()
+        .map(|(number, value)| (number - 1, value.clone()))
+        .collect()
+}
+
+/// Returns whether `text` is a number in JSON's grammar.
+fn is_json_number(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let mut position = if text.starts_with('-') { 1 } else { 0 };
+    let skip_digits = |position: &mut usize| {
+        let start = *position;
+        while bytes.get(*position).map_or(false, u8::is_ascii_digit) {
+            *position += 1;
+        }
+        *position - start
+    };
+    match bytes.get(position) {
+        Some(&b'0') => position += 1,
+        Some(byte) if byte.is_ascii_digit() => {
+            skip_digits(&mut position);
+        }
+        _ => return false,
+    }
+    if bytes.get(position) == Some(&b'.') {
+        position += 1;
+        if skip_digits(&mut position) == 0 {
+            return false;
+        }
+    }
+    if let Some(&b'e') | Some(&b'E') = bytes.get(position) {
+        position += 1;
+        if let Some(&b'+') | Some(&b'-') = bytes.get(position) {
+            position += 1;
+        }
+        if skip_digits(&mut position) == 0 {
+            return false;
+        }
+    }
+    position == bytes.len()
+}
+
+/// Returns whether `text` is a JSON number which `serde_json` reads as an integer. It reads `-0`
+/// as a float.
+fn is_json_integer(text: &str) -> bool {
+    is_json_number(text) && !text.contains(|c: char| c == '.' || c == 'e' || c == 'E')
+        && text != "-0"
+}
+
+fn parse_integer<A: ::std::str::FromStr>(text: &str) -> Option<A> {
+    if is_json_integer(text) {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn parse_double(text: &str) -> Option<f64> {
+    if is_json_number(text) {
+        // `serde_json` rejects numbers which are out of range.
+        text.parse::<f64>().ok().filter(|n| n.is_finite())
+    } else {
+        None
+    }
+}
+
+/// Pull parser for pblite text, which decodes messages without building a `serde_json::Value`
+/// for the whole array.
+///
+/// Numbers are parsed from the text, and `read_str` borrows strings without escape sequences
+/// from it. A `serde_json::Value` is only built for unknown fields and for values of the wrong
+/// type, which are checked by the `read_*` functions so that errors are the same.
+///
+/// Generated and derived messages own their strings, so decoding them with a reader still
+/// allocates a `String` for each string field. Only callers of `read_str`, such as hand-written
+/// types deserialized with `pblite_serde`, can borrow strings from the text.
+pub struct Reader<'a> {
+    text: &'a str,
+    position: usize,
+    /// Errors skipped while decoding leniently, or `None` while decoding strictly.
+    warnings: Option<Vec<Error>>,
+}
+impl<'a> Reader<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Reader {
+            text: text,
+            position: 0,
+            warnings: None,
+        }
+    }
+
+    /// Returns a reader which decodes messages leniently, leaving fields with invalid values
+    /// unset instead of failing. The errors for the skipped fields are returned by
+    /// `take_warnings`.
+    pub fn new_lenient(text: &'a str) -> Self {
+        Reader {
+            warnings: Some(Vec::new()),
+            ..Reader::new(text)
+        }
+    }
+
+    /// Returns the errors skipped since the last call, if decoding leniently.
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        self.warnings
+            .as_mut()
+            .map_or_else(Vec::new, |warnings| warnings.drain(..).collect())
+    }
+
+    fn syntax_error(&self, message: &'static str) -> Error {
+        ErrorKind::Syntax(message, self.position).into()
+    }
+
+    /// Skips whitespace and returns the next byte without consuming it.
+    pub fn peek(&mut self) -> Option<u8> {
+        let bytes = self.text.as_bytes();
+        while let Some(&byte) = bytes.get(self.position) {
+            match byte {
+                b' ' | b'\t' | b'\n' | b'\r' => self.position += 1,
+                _ => return Some(byte),
+            }
+        }
+        None
+    }
+
+    /// Consumes the next byte if it is `byte`.
+    pub fn consume(&mut self, byte: u8) -> bool {
+        let is_next = self.peek() == Some(byte);
+        if is_next {
+            self.position += 1;
+        }
+        is_next
+    }
+
+    pub fn expect(&mut self, byte: u8, message: &'static str) -> Result<()> {
+        if self.consume(byte) {
+            Ok(())
+        } else {
+            Err(self.syntax_error(message))
+        }
+    }
+
+    /// Consumes the next value if it is null.
+    pub fn consume_null(&mut self) -> bool {
+        let is_null = self.peek() == Some(b'n') && self.text[self.position..].starts_with("null");
+        if is_null {
+            self.position += 4;
+        }
+        is_null
+    }
+
+    /// Calls `read_item` for each item of an array or object whose opening bracket has been
+    /// consumed, until the closing bracket `close`.
+    fn read_items<F: FnMut(&mut Self) -> Result<()>>(&mut self, close: u8, mut read_item: F) -> Result<()> {
+        if self.consume(close) {
+            return Ok(());
+        }
+        loop {
+            read_item(self)?;
+            if self.consume(close) {
+                return Ok(());
+            }
+            self.expect(b',', "expected ',' or closing bracket")?;
+        }
+    }
+
+    fn skip_string(&mut self) -> Result<()> {
+        self.expect(b'"', "expected string")?;
+        let bytes = self.text.as_bytes();
+        while let Some(&byte) = bytes.get(self.position) {
+            self.position += match byte {
+                b'"' => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                b'\\' => 2,
+                _ => 1,
+            };
+        }
+        Err(self.syntax_error("unterminated string"))
+    }
+
+    /// Skips over the next value and returns its text.
+    pub fn skip_value(&mut self) -> Result<&'a str> {
+        let start = match self.peek() {
+            Some(_) => self.position,
+            None => return Err(self.syntax_error("expected value")),
+        };
+        match self.text.as_bytes()[start] {
+            b'[' => {
+                self.position += 1;
+                self.read_items(b']', |reader| reader.skip_value().map(|_| ()))?;
+            }
+            b'{' => {
+                self.position += 1;
+                self.read_items(b'}', |reader| {
+                    reader.skip_string()?;
+                    reader.expect(b':', "expected ':'")?;
+                    reader.skip_value().map(|_| ())
+                })?;
+            }
+            b'"' => self.skip_string()?,
+            _ => {
+                // Numbers and literals, which are validated when they are read.
+                let bytes = self.text.as_bytes();
+                while let Some(&byte) = bytes.get(self.position) {
+                    match byte {
+                        b'-' | b'+' | b'.' => {}
+                        _ if byte.is_ascii_alphanumeric() => {}
+                        _ => break,
+                    }
+                    self.position += 1;
+                }
+                if self.position == start {
+                    return Err(self.syntax_error("expected value"));
+                }
+            }
+        }
+        Ok(&self.text[start..self.position])
+    }
+
+    /// Reads the next value into a `serde_json::Value`.
+    pub fn read_value(&mut self) -> Result<Value> {
+        self.peek();
+        let start = self.position;
+        let text = self.skip_value()?;
+        serde_json::from_str(text).or(Err(ErrorKind::Syntax("invalid value", start).into()))
+    }
+
+    /// Reads the next value with one of the `read_*` functions.
+    fn read_value_with<A>(&mut self, read: fn(&Value) -> Result<A>) -> Result<A> {
+        read(&self.read_value()?)
+    }
+
+    /// Reads a number with `parse` from its text, or with `read` if it isn't a number or `parse`
+    /// rejects it.
+    fn read_number_with<A>(&mut self, parse: fn(&str) -> Option<A>, read: fn(&Value) -> Result<A>) -> Result<A> {
+        match self.peek() {
+            Some(byte) if byte == b'-' || byte.is_ascii_digit() => {
+                let start = self.position;
+                if let Some(number) = parse(self.skip_value()?) {
+                    return Ok(number);
+                }
+                self.position = start;
+            }
+            _ => {}
+        }
+        self.read_value_with(read)
+    }
+
+    /// Reads an integer from a number or a string, in which 64-bit integers may be encoded.
+    fn read_integer_or_string_with<A: ::std::str::FromStr>(&mut self, read: fn(&Value) -> Result<A>) -> Result<A> {
+        if self.peek() == Some(b'"') {
+            let start = self.position;
+            if let Ok(number) = self.read_str()?.parse() {
+                return Ok(number);
+            }
+            self.position = start;
+        }
+        self.read_number_with(parse_integer, read)
+    }
+
+    /// Reads a string, borrowing it from the text if it has no escape sequences.
+    pub fn read_str(&mut self) -> Result<Cow<'a, str>> {
+        self.peek();
+        let start = self.position;
+        self.skip_string()?;
+        let text = &self.text[start + 1..self.position - 1];
+        if !text.contains('\\') {
+            return Ok(Cow::Borrowed(text));
+        }
+        serde_json::from_str(&self.text[start..self.position])
+            .map(Cow::Owned)
+            .or(Err(ErrorKind::Syntax("invalid string", start).into()))
+    }
+
+    /// Reads an owned string, which is copied even without escape sequences. Use `read_str` to
+    /// borrow it instead.
+    pub fn read_string(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(b'"') => self.read_str().map(Cow::into_owned),
+            _ => self.read_value_with(read_string),
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        self.read_number_with(
+            |text| match text {
+                "0" => Some(false),
+                "1" => Some(true),
+                _ => None,
+            },
+            read_bool,
+        )
+    }
+
+    pub fn read_enum<E: Enum>(&mut self) -> Result<E> {
+        E::from_u32(self.read_int32()? as u32)
+    }
+
+    pub fn read_uint32(&mut self) -> Result<u32> {
+        self.read_number_with(parse_integer, read_uint32)
+    }
+
+    pub fn read_uint64(&mut self) -> Result<u64> {
+        self.read_integer_or_string_with(read_uint64)
+    }
+
+    pub fn read_int32(&mut self) -> Result<i32> {
+        self.read_number_with(parse_integer, read_int32)
+    }
+
+    pub fn read_int64(&mut self) -> Result<i64> {
+        self.read_integer_or_string_with(read_int64)
+    }
+
+    pub fn read_double(&mut self) -> Result<f64> {
+        self.read_number_with(parse_double, read_double)
+    }
+
+    pub fn read_float(&mut self) -> Result<f32> {
+        // Values out of range are left to `read_float` to reject.
+        self.read_number_with(
+            |text| parse_double(text).map(|n| n as f32).filter(|n| n.is_finite()),
+            read_float,
+        )
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        match self.peek() {
+            Some(b'"') => {
+                let text = self.read_str()?;
+                base64::decode(&*text)
+                    .or(Err(expected_value("base64", &Value::String(text.into_owned()))))
+            }
+            _ => self.read_value_with(read_bytes),
+        }
+    }
+
+    pub fn read_message<M: Message>(&mut self) -> Result<M> {
+        self.read_message_skipping(0)
+    }
+
+    /// Reads a message, ignoring the first `skip` items of its array, such as the header of a
+    /// pblite response.
+    pub fn read_message_skipping<M: Message>(&mut self, skip: usize) -> Result<M> {
+        if !self.consume(b'[') {
+            return self.read_value_with(|value| Err(expected_value("array", value)));
+        }
+        let mut message = M::default();
+        let mut index = 0;
+        self.read_items(b']', |reader| {
+            if index < skip {
+                reader.skip_value()?;
+            } else if reader.peek() == Some(b'{') && reader.is_last_item()? {
+                reader.read_sparse_fields(&mut message)?;
+            } else {
+                reader.read_message_field(&mut message, index - skip)?;
+            }
+            index += 1;
+            Ok(())
+        })?;
+        check_message(&message, self)?;
+        Ok(message)
+    }
+
+    /// Returns whether the next value is the last item of its array. Like `get_field_values`,
+    /// only an object which ends the array holds sparse fields, and other objects are values of
+    /// positional fields.
+    fn is_last_item(&mut self) -> Result<bool> {
+        let start = self.position;
+        self.skip_value()?;
+        let is_last = self.peek() == Some(b']');
+        self.position = start;
+        Ok(is_last)
+    }
+
+    /// Reads the object of sparse fields which may end a message's array.
+    fn read_sparse_fields<M: Message>(&mut self, message: &mut M) -> Result<()> {
+        self.expect(b'{', "expected object")?;
+        self.read_items(b'}', |reader| {
+            let key = reader.read_str()?;
+            let number = key.parse::<usize>()
+                .ok()
+                .and_then(|number| number.checked_sub(1))
+                .ok_or_else(|| {
+                    map_decode_error(
+                        expected_value("field number", &Value::String(key.to_string())),
+                        |decode_error| DecodeError {
+                            message_name: Some(message.get_name().to_owned()),
+                            ..decode_error
+                        },
+                    )
+                })?;
+            reader.expect(b':', "expected ':'")?;
+            reader.read_message_field(message, number)
+        })
+    }
+
+    fn read_message_field<M: Message>(&mut self, message: &mut M, number: usize) -> Result<()> {
+        if self.consume_null() {
+            return Ok(());
+        }
+        let start = self.position;
+        if decode_field(message, number, self, |message, reader| message.read_field(number, reader))? {
+            // The field was skipped, so continue from the end of its value.
+            self.position = start;
+            self.skip_value()?;
+        }
+        Ok(())
+    }
+
+    pub fn read_array<A>(&mut self, read_elem: &Fn(&mut Self) -> Result<A>) -> Result<Option<Vec<A>>> {
+        if self.consume_null() {
+            return Ok(None);
+        }
+        if !self.consume(b'[') {
+            return self.read_value_with(|value| Err(expected_value("array", value)));
+        }
+        let mut elems = Vec::new();
+        self.read_items(b']', |reader| {
+            let index = elems.len();
+            elems.push(decode_element(index, reader, |reader| read_elem(reader))?);
+            Ok(())
+        })?;
+        Ok(Some(elems))
+    }
+
+    pub fn read_optional<A>(&mut self, read_inner: &Fn(&mut Self) -> Result<A>) -> Result<Option<A>> {
+        if self.consume_null() {
+            Ok(None)
+        } else {
+            read_inner(self).map(Some)
+        }
+    }
+
+    /// Reads a map, encoded as an array of `[key, value]` entry messages.
+    pub fn read_map<K: Ord, V>(
+        &mut self,
+        read_key: &Fn(&mut Self) -> Result<K>,
+        read_value: &Fn(&mut Self) -> Result<V>,
+    ) -> Result<Option<BTreeMap<K, V>>> {
+        let read_entry = |reader: &mut Self| -> Result<(K, V)> {
+            if !reader.consume(b'[') {
+                return reader.read_value_with(|value| Err(expected_value("array", value)));
+            }
+            let (mut key, mut value) = (None, None);
+            reader.read_items(b']', |reader| {
+                if key.is_none() {
+                    key = Some(read_key(reader)?);
+                } else if value.is_none() {
+                    value = Some(read_value(reader)?);
+                } else {
+                    reader.skip_value()?;
+                }
+                Ok(())
+            })?;
+            // Like `read_map`, a missing key or value is read as null.
+            let mut null: Reader<'a> = Reader::new("null");
+            Ok((
+                key.map_or_else(|| read_key(&mut null), Ok)?,
+                value.map_or_else(|| read_value(&mut null), Ok)?,
+            ))
+        };
+        Ok(self.read_array(&read_entry)?
+            .map(|entries| entries.into_iter().collect()))
+    }
+
+    pub fn read_oneof<A, O>(
+        &mut self,
+        oneof: &mut Option<O>,
+        read_inner: &Fn(&mut Self) -> Result<A>,
+        variant: fn(A) -> O,
+    ) -> Result<()> {
+        if let Some(inner) = self.read_optional(read_inner)? {
+            *oneof = Some(variant(inner));
+        }
+        Ok(())
+    }
+
+    pub fn read_unknown_field(&mut self, unknown_fields: &mut UnknownFields, number: usize) -> Result<()> {
+        let value = self.read_value()?;
+        read_unknown_field(unknown_fields, number, &value);
+        Ok(())
+    }
+
+    /// Returns an error unless only whitespace remains after the values that were read.
+    pub fn finish(&mut self) -> Result<()> {
+        match self.peek() {
+            Some(_) => Err(self.syntax_error("trailing characters")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> WarningSink for Reader<'a> {
+    fn get_warnings(&mut self) -> Option<&mut Vec<Error>> {
+        self.warnings.as_mut()
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use serde_json;
     use example;
+    use pblite;
     use pblite::Message;
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_i18n_data() {
@@ -143,6 +1336,7 @@ mod tests {
                 region_code: Some("CA".to_owned()),
                 is_valid: Some(true),
                 country_code: Some(123),
+                ..Default::default()
             }
         );
     }
@@ -156,6 +1350,7 @@ mod tests {
                 region_code: None,
                 is_valid: None,
                 country_code: None,
+                ..Default::default()
             }
         );
     }
@@ -172,58 +1367,365 @@ mod tests {
                     region_code: Some("CA".to_owned()),
                     is_valid: Some(true),
                     country_code: Some(123),
+                    ..Default::default()
                 }),
+                ..Default::default()
             }
         );
     }
 
     #[test]
-    fn test_empty() {
-        let empty = example::Empty::from_pblite("[]").unwrap();
-        assert_eq!(empty, example::Empty {});
-    }
-
-    #[test]
-    fn test_empty_unexpected_field() {
-        let empty = example::Empty::from_pblite("[1]").unwrap();
-        assert_eq!(empty, example::Empty {});
+    fn test_empty() {
+        let empty = example::Empty::from_pblite("[]").unwrap();
+        assert_eq!(empty, example::Empty::default());
+    }
+
+    #[test]
+    fn test_empty_unexpected_field() {
+        let empty = example::Empty::from_pblite("[1]").unwrap();
+        let mut unknown_fields = pblite::UnknownFields::new();
+        unknown_fields.insert(1, json!(1));
+        assert_eq!(
+            empty,
+            example::Empty {
+                unknown_fields: unknown_fields,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_fields_round_trip() {
+        let i18n_data =
+            example::I18nData::from_pblite("[\"CA\",null,null,null,[\"foo\",1],{\"150\":2}]").unwrap();
+        assert_eq!(i18n_data.region_code, Some("CA".to_owned()));
+        assert_eq!(i18n_data.unknown_fields.get(&5), Some(&json!(["foo", 1])));
+        assert_eq!(i18n_data.unknown_fields.get(&150), Some(&json!(2)));
+        assert_eq!(
+            i18n_data.to_pblite(),
+            "[\"CA\",null,null,null,[\"foo\",1],{\"150\":2}]"
+        );
+    }
+
+    #[test]
+    fn test_unknown_fields_serialize() {
+        let i18n_data = example::I18nData::from_pblite("[\"CA\",null,null,3]").unwrap();
+        assert_eq!(
+            serde_json::to_value(&i18n_data).unwrap(),
+            json!({"region_code": "CA", "unknown_fields": {"4": 3}})
+        );
+        let i18n_data = example::I18nData::from_pblite("[\"CA\"]").unwrap();
+        assert_eq!(
+            serde_json::to_value(&i18n_data).unwrap(),
+            json!({"region_code": "CA"})
+        );
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let empty = example::Empty::from_pblite("[");
+        assert_eq!(
+            empty.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_null_json() {
+        let empty = example::Empty::from_pblite("null");
+        println!("{:?}", empty);
+        assert_eq!(
+            empty.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_expected_array() {
+        let empty = example::Empty::from_pblite("1");
+        assert_eq!(
+            empty.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_expected_array_nested() {
+        let phone_number = example::PhoneNumber::from_pblite("[null,1]");
+        assert_eq!(
+            phone_number.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_decode_error_path() {
+        let phone_number = example::PhoneNumber::from_pblite("[null,[\"CA\",\"foo\"]]");
+        let error = phone_number.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        let decode_error = error.get_decode_error().expect("expected decode error");
+        assert_eq!(
+            decode_error,
+            &pblite::DecodeError {
+                message_name: Some("PhoneNumber".to_owned()),
+                path: vec![
+                    pblite::PathSegment::Field("i18n_data".to_owned()),
+                    pblite::PathSegment::Field("is_valid".to_owned()),
+                ],
+                field_number: Some(2),
+                expected: "u64",
+                snippet: "\"foo\"".to_owned(),
+            }
+        );
+        assert_eq!(
+            decode_error.to_string(),
+            "PhoneNumber.i18n_data.is_valid (field 2): expected u64 value but got: '\"foo\"'"
+        );
+    }
+
+    #[test]
+    fn test_decode_error_path_repeated() {
+        let example = example::Example::from_pblite(
+            "[null,null,null,null,null,null,null,null,null,null,null,null,[\"foo\",1]]",
+        );
+        let error = example.err().expect("expected error");
+        assert_eq!(
+            error.get_decode_error().expect("expected decode error").to_string(),
+            "Example.test_repeated_string[1] (field 13): expected string value but got: '1'"
+        );
+    }
+
+    #[test]
+    fn test_decode_error_path_sparse() {
+        let sparse = example::SparseExample::from_pblite("[{\"250\":[1]}]");
+        let error = sparse.err().expect("expected error");
+        assert_eq!(
+            error.get_decode_error().expect("expected decode error").get_path(),
+            "SparseExample.test_sparse_message.region_code"
+        );
+    }
+
+    #[test]
+    fn test_decode_error_unknown_field_name() {
+        let sparse = example::SparseExample::from_pblite("[\"foo\",{\"bar\":5}]");
+        let error = sparse.err().expect("expected error");
+        assert_eq!(
+            error.get_decode_error().expect("expected decode error").to_string(),
+            "SparseExample: expected field number value but got: '\"bar\"'"
+        );
+    }
+
+    #[test]
+    fn test_decode_error_snippet_truncated() {
+        let long_string = "x".repeat(100);
+        let phone_number = example::PhoneNumber::from_pblite(&format!("[null,[[\"{}\"]]]", long_string));
+        let error = phone_number.err().expect("expected error");
+        let decode_error = error.get_decode_error().expect("expected decode error");
+        assert_eq!(decode_error.snippet, format!("[\"{}...", &long_string[..78]));
+    }
+
+    #[test]
+    fn test_decode_error_top_level() {
+        let empty = example::Empty::from_pblite("1");
+        let error = empty.err().expect("expected error");
+        assert_eq!(
+            error.get_decode_error().expect("expected decode error").to_string(),
+            "expected array value but got: '1'"
+        );
+    }
+
+    #[test]
+    fn test_descriptor() {
+        let descriptor = example::NestedExample::descriptor();
+        assert_eq!(descriptor.name, "NestedExample");
+        assert_eq!(descriptor.full_name, "example.NestedExample");
+        let field = descriptor.get_field("test_repeated_enum").unwrap();
+        assert_eq!(field.number, 2);
+        assert_eq!(field.label, pblite::Label::Repeated);
+        assert_eq!(field.field_type, pblite::FieldType::Enum);
+        let enum_descriptor = (field.enum_type.unwrap())();
+        assert_eq!(enum_descriptor.full_name, "example.NestedExample.Inner.InnerEnum");
+        assert_eq!(
+            enum_descriptor.values[1],
+            pblite::EnumValueDescriptor {
+                name: "INNER_ENUM_VALUE_2",
+                number: 2,
+            }
+        );
+        let inner = (descriptor.nested_messages[0])();
+        assert_eq!(inner.name, "NestedExample.Inner");
+        assert_eq!(inner.fields.len(), 2);
+        assert_eq!((inner.nested_enums[0])().name, "NestedExample.Inner.InnerEnum");
+        let phone_number = descriptor.get_field_by_number(3).unwrap();
+        assert_eq!((phone_number.message_type.unwrap())().name, "PhoneNumber");
+        assert!(descriptor.get_field("test_missing").is_none());
+    }
+
+    #[test]
+    fn test_descriptor_map() {
+        let field = example::MapExample::descriptor()
+            .get_field("test_message_map")
+            .unwrap();
+        assert_eq!(field.label, pblite::Label::Repeated);
+        assert_eq!(field.field_type, pblite::FieldType::Message);
+        assert_eq!((field.message_type.unwrap())().name, "I18nData");
+        assert_eq!(field.map_key_type, Some(pblite::FieldType::Uint32));
+        assert!(example::MapExample::descriptor().nested_messages.is_empty());
+    }
+
+    #[test]
+    fn test_get_set_field_by_name() {
+        let mut phone_number = example::PhoneNumber::default();
+        phone_number
+            .set_field_by_name("e164", &json!("+15551234567"))
+            .unwrap();
+        phone_number
+            .set_field_by_name("i18n_data", &json!(["CA"]))
+            .unwrap();
+        assert_eq!(phone_number.e164, Some("+15551234567".to_owned()));
+        assert_eq!(
+            phone_number.get_field_by_name("i18n_data"),
+            Some(json!(["CA"]))
+        );
+        phone_number
+            .set_field_by_name("e164", &serde_json::Value::Null)
+            .unwrap();
+        assert_eq!(
+            phone_number.get_field_by_name("e164"),
+            Some(serde_json::Value::Null)
+        );
+        assert_eq!(phone_number.get_field_by_name("foo"), None);
+    }
+
+    #[test]
+    fn test_set_field_by_name_errors() {
+        let mut phone_number = example::PhoneNumber::default();
+        let error = phone_number.set_field_by_name("foo", &json!(1)).unwrap_err();
+        assert_eq!(error.to_string(), "field 'foo' is not defined");
+        let error = phone_number
+            .set_field_by_name("i18n_data", &json!([1]))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "PhoneNumber.i18n_data.region_code (field 1): expected string value but got: '1'"
+        );
+    }
+
+    #[test]
+    fn test_lenient() {
+        let pblite = "[5,[\"CA\",\"foo\",44]]";
+        assert!(example::PhoneNumber::from_pblite(pblite).is_err());
+        let (phone_number, warnings) = example::PhoneNumber::from_pblite_lenient(pblite).unwrap();
+        assert_eq!(
+            phone_number,
+            example::PhoneNumber {
+                i18n_data: Some(example::I18nData {
+                    region_code: Some("CA".to_owned()),
+                    country_code: Some(44),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        );
+        let warnings = warnings
+            .iter()
+            .map(|warning| warning.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![
+                "PhoneNumber.e164 (field 1): expected string value but got: '5'",
+                "PhoneNumber.i18n_data.is_valid (field 2): expected u64 value but got: '\"foo\"'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lenient_map() {
+        let (map_example, warnings) =
+            example::MapExample::from_pblite_lenient("[null,[[1,[\"CA\",2]]]]").unwrap();
+        assert_eq!(
+            map_example.test_message_map.unwrap()[&1],
+            example::I18nData {
+                region_code: Some("CA".to_owned()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].get_decode_error().unwrap().get_path(),
+            "MapExample.test_message_map[0].is_valid"
+        );
+    }
+
+    #[test]
+    fn test_lenient_required_field_missing() {
+        let (required_example, warnings) =
+            example::RequiredExample::from_pblite_lenient("[]").unwrap();
+        assert_eq!(required_example, example::RequiredExample::default());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].description(), "invalid message");
     }
 
     #[test]
-    fn test_invalid_json() {
-        let empty = example::Empty::from_pblite("[");
+    fn test_lenient_expected_array() {
+        let result = example::PhoneNumber::from_pblite_lenient("1");
         assert_eq!(
-            empty.err().expect("expected error").description(),
+            result.err().expect("expected error").description(),
             "invalid message"
         );
     }
 
     #[test]
-    fn test_null_json() {
-        let empty = example::Empty::from_pblite("null");
-        println!("{:?}", empty);
-        assert_eq!(
-            empty.err().expect("expected error").description(),
-            "invalid message"
-        );
+    fn test_lenient_from_vec() {
+        let value = serde_json::from_str::<serde_json::Value>("[5,[\"CA\",\"foo\",44]]").unwrap();
+        let (phone_number, warnings) =
+            example::PhoneNumber::from_vec_lenient(value.as_array().unwrap()).unwrap();
+        assert_eq!(phone_number.i18n_data.unwrap().country_code, Some(44));
+        assert_eq!(warnings.len(), 2);
+        assert!(example::PhoneNumber::from_vec(value.as_array().unwrap()).is_err());
+    }
+
+    fn assert_lenient_from_vec_matches_reader<M>(pblite: &str)
+    where
+        M: pblite::Message + PartialEq + ::std::fmt::Debug,
+    {
+        let (message, warnings) = M::from_pblite_lenient(pblite).unwrap();
+        let value = serde_json::from_str::<serde_json::Value>(pblite).unwrap();
+        let (vec_message, vec_warnings) = M::from_vec_lenient(value.as_array().unwrap()).unwrap();
+        assert_eq!(vec_message, message);
+        let to_strings = |warnings: Vec<pblite::Error>| {
+            warnings
+                .iter()
+                .map(|warning| warning.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(to_strings(vec_warnings), to_strings(warnings));
     }
 
     #[test]
-    fn test_expected_array() {
-        let empty = example::Empty::from_pblite("1");
-        assert_eq!(
-            empty.err().expect("expected error").description(),
-            "invalid message"
+    fn test_lenient_from_vec_nested() {
+        assert_lenient_from_vec_matches_reader::<example::PhoneNumber>("[5,[\"CA\",\"foo\",44]]");
+        assert_lenient_from_vec_matches_reader::<example::MapExample>(
+            "[[[\"foo\",\"bar\"]],[[1,[\"CA\",2]],[2,[1]]]]",
         );
+        assert_lenient_from_vec_matches_reader::<example::NestedExample>("[[1,2],[[1],2],[\"1\"]]");
+        assert_lenient_from_vec_matches_reader::<example::OneofExample>("[null,[1,1]]");
+        assert_lenient_from_vec_matches_reader::<example::RequiredExample>("[null,[]]");
     }
 
     #[test]
-    fn test_expected_array_nested() {
-        let phone_number = example::PhoneNumber::from_pblite("[null,1]");
-        assert_eq!(
-            phone_number.err().expect("expected error").description(),
-            "invalid message"
-        );
+    fn test_lenient_only_affects_reader() {
+        let mut lenient = pblite::Reader::new_lenient("[5] [\"CA\",\"foo\"]");
+        let phone_number = lenient.read_message::<example::PhoneNumber>().unwrap();
+        assert_eq!(phone_number, example::PhoneNumber::default());
+        // Only the lenient reader's decodes are lenient.
+        assert!(example::PhoneNumber::from_pblite("[5]").is_err());
+        let i18n_data = lenient.read_message::<example::I18nData>().unwrap();
+        assert_eq!(i18n_data.region_code, Some("CA".to_owned()));
+        assert_eq!(lenient.take_warnings().len(), 2);
+        assert_eq!(lenient.take_warnings().len(), 0);
+        lenient.finish().unwrap();
     }
 
     #[test]
@@ -294,7 +1796,27 @@ mod tests {
         let example = example::Example::from_pblite("[null,null,null,null,null,null,100]");
         assert_matches!(
             example.unwrap().test_enum,
-            Some(example::ExampleEnum::ExampleEnumValue1)
+            Some(example::ExampleEnum::Unknown(100))
+        );
+    }
+
+    #[test]
+    fn test_enum_unknown_value_to_pblite() {
+        let example = example::Example::from_pblite("[null,null,null,null,null,null,100,null,null,null,null,null,null,null,[2,101]]").unwrap();
+        assert_eq!(
+            example.test_repeated_enum,
+            Some(vec![
+                example::ExampleEnum::ExampleEnumValue2,
+                example::ExampleEnum::Unknown(101),
+            ])
+        );
+        assert_eq!(
+            example.to_pblite(),
+            "[null,null,null,null,null,null,100,null,null,null,null,null,null,null,[2,101]]"
+        );
+        assert_eq!(
+            serde_json::to_value(&example.test_enum).unwrap(),
+            json!({"Unknown": 100})
         );
     }
 
@@ -323,7 +1845,7 @@ mod tests {
                 test_string: Some("foo".to_owned()),
                 test_bytes: Some(vec![0, 1, 2]),
                 test_enum: Some(example::ExampleEnum::ExampleEnumValue2),
-                test_message: Some(example::Empty {}),
+                test_message: Some(example::Empty::default()),
 
                 test_repeated_double: Some(vec![3.14159, 1.1]),
                 test_repeated_uint64: Some(vec![64, 65]),
@@ -335,7 +1857,8 @@ mod tests {
                     example::ExampleEnum::ExampleEnumValue2,
                     example::ExampleEnum::ExampleEnumValue3,
                 ]),
-                test_repeated_message: Some(vec![example::Empty {}, example::Empty {}]),
+                test_repeated_message: Some(vec![example::Empty::default(), example::Empty::default()]),
+                ..Default::default()
             }
         );
     }
@@ -363,7 +1886,653 @@ mod tests {
                 test_repeated_bytes: None,
                 test_repeated_enum: None,
                 test_repeated_message: None,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_i18n_data_to_pblite() {
+        let i18n_data = example::I18nData {
+            region_code: Some("CA".to_owned()),
+            is_valid: Some(true),
+            country_code: Some(123),
+            ..Default::default()
+        };
+        assert_eq!(i18n_data.to_pblite(), "[\"CA\",1,123]");
+    }
+
+    #[test]
+    fn test_empty_to_pblite() {
+        assert_eq!(example::Empty::default().to_pblite(), "[]");
+        assert_eq!(example::I18nData::default().to_pblite(), "[]");
+    }
+
+    #[test]
+    fn test_to_pblite_trailing_nulls() {
+        let i18n_data = example::I18nData {
+            region_code: None,
+            is_valid: Some(false),
+            country_code: None,
+            ..Default::default()
+        };
+        assert_eq!(i18n_data.to_pblite(), "[null,0]");
+    }
+
+    #[test]
+    fn test_phone_number_to_pblite() {
+        let phone_number = example::PhoneNumber {
+            e164: None,
+            i18n_data: Some(example::I18nData {
+                region_code: Some("CA".to_owned()),
+                is_valid: None,
+                country_code: None,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(phone_number.to_pblite(), "[null,[\"CA\"]]");
+    }
+
+    #[test]
+    fn test_all_types_to_pblite() {
+        let example = example::Example::from_pblite("[3.14159,\"64\",32,1,\"foo\",\"AAEC\",2,[],[3.14159,1.1],[64,65],[32,33],[1,0],[\"foo\",\"bar\"],[\"AAEC\",\"AgEA\"],[2,3],[[],[]]]").unwrap();
+        assert_eq!(
+            example.to_pblite(),
+            "[3.14159,64,32,1,\"foo\",\"AAEC\",2,[],[3.14159,1.1],[64,65],[32,33],[1,0],[\"foo\",\"bar\"],[\"AAEC\",\"AgEA\"],[2,3],[[],[]]]"
+        );
+    }
+
+    #[test]
+    fn test_all_numbers() {
+        let numbers = example::ExampleNumbers::from_pblite("[-32,\"-64\",-32,-64,32,\"64\",-32,\"-9223372036854775808\",1.5,[1,-1],[\"1\",-1],[0.5,-0.5]]").unwrap();
+        assert_eq!(
+            numbers,
+            example::ExampleNumbers {
+                test_int32: Some(-32),
+                test_int64: Some(-64),
+                test_sint32: Some(-32),
+                test_sint64: Some(-64),
+                test_fixed32: Some(32),
+                test_fixed64: Some(64),
+                test_sfixed32: Some(-32),
+                test_sfixed64: Some(::std::i64::MIN),
+                test_float: Some(1.5),
+                test_repeated_int32: Some(vec![1, -1]),
+                test_repeated_int64: Some(vec![1, -1]),
+                test_repeated_float: Some(vec![0.5, -0.5]),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            numbers.to_pblite(),
+            "[-32,-64,-32,-64,32,64,-32,-9223372036854775808,1.5,[1,-1],[1,-1],[0.5,-0.5]]"
+        );
+    }
+
+    #[test]
+    fn test_float_shortest_representation() {
+        let numbers = example::ExampleNumbers {
+            test_float: Some(0.1),
+            test_repeated_float: Some(vec![3.4028235e38, 1e-45]),
+            ..Default::default()
+        };
+        let pblite = numbers.to_pblite();
+        assert_eq!(
+            pblite,
+            "[null,null,null,null,null,null,null,null,0.1,null,null,[3.4028235e+38,1e-45]]"
+        );
+        assert_eq!(example::ExampleNumbers::from_pblite(&pblite).unwrap(), numbers);
+    }
+
+    #[test]
+    fn test_non_finite_numbers() {
+        let example = example::Example::from_pblite("[\"NaN\"]").unwrap();
+        assert!(example.test_double.unwrap().is_nan());
+        assert_eq!(example.to_pblite(), "[\"NaN\"]");
+        let pblite = "[null,null,null,null,null,null,null,null,\"-Infinity\",null,null,\
+                      [\"Infinity\",\"NaN\"]]";
+        let numbers = example::ExampleNumbers::from_pblite(pblite).unwrap();
+        assert_eq!(numbers.test_float, Some(::std::f32::NEG_INFINITY));
+        let repeated_float = numbers.test_repeated_float.as_ref().unwrap();
+        assert_eq!(repeated_float[0], ::std::f32::INFINITY);
+        assert!(repeated_float[1].is_nan());
+        assert_eq!(numbers.to_pblite(), pblite);
+        let error = example::Example::from_pblite("[\"nan\"]")
+            .err()
+            .expect("expected error");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "Example.test_double (field 1): expected f64 value but got: '\"nan\"'"
+        );
+    }
+
+    #[test]
+    fn test_float_out_of_range() {
+        let pblite = "[null,null,null,null,null,null,null,null,3.4028235e38]";
+        let numbers = example::ExampleNumbers::from_pblite(pblite).unwrap();
+        assert_eq!(numbers.test_float, Some(::std::f32::MAX));
+        let array = serde_json::from_str(pblite).unwrap();
+        assert_eq!(example::ExampleNumbers::from_vec(&array).unwrap(), numbers);
+        let pblite = "[null,null,null,null,null,null,null,null,1e39]";
+        let error = example::ExampleNumbers::from_pblite(pblite)
+            .err()
+            .expect("expected error");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "ExampleNumbers.test_float (field 9): expected f32 value but got: '1e+39'"
+        );
+        let array = serde_json::from_str(pblite).unwrap();
+        let error = example::ExampleNumbers::from_vec(&array)
+            .err()
+            .expect("expected error");
+        assert_eq!(
+            error.to_string(),
+            "ExampleNumbers.test_float (field 9): expected f32 value but got: '1e+39'"
+        );
+    }
+
+    #[test]
+    fn test_int32_out_of_range() {
+        let numbers = example::ExampleNumbers::from_pblite("[2147483648]");
+        assert_eq!(
+            numbers.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_int64_expected_number() {
+        let numbers = example::ExampleNumbers::from_pblite("[null,\"foo\"]");
+        assert_eq!(
+            numbers.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_uint64_negative() {
+        let example = example::Example::from_pblite("[null,-1]");
+        assert_eq!(
+            example.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_nested_types() {
+        let nested = example::NestedExample::from_pblite("[[\"foo\",2],[1,2],[\"16067624137\"]]").unwrap();
+        assert_eq!(
+            nested,
+            example::NestedExample {
+                test_inner: Some(example::NestedExample_Inner {
+                    test_string: Some("foo".to_owned()),
+                    test_enum: Some(example::NestedExample_Inner_InnerEnum::InnerEnumValue2),
+                    ..Default::default()
+                }),
+                test_repeated_enum: Some(vec![
+                    example::NestedExample_Inner_InnerEnum::InnerEnumValue1,
+                    example::NestedExample_Inner_InnerEnum::InnerEnumValue2,
+                ]),
+                test_phone_number: Some(example::PhoneNumber {
+                    e164: Some("16067624137".to_owned()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        );
+        assert_eq!(nested.test_inner.unwrap().get_name(), "NestedExample.Inner");
+    }
+
+    #[test]
+    fn test_reader_matches_from_vec() {
+        let pblite = "[\n1.5, \"5\", 3,1 ,\"f\\u00f6o\\n\", \"AAE=\", 2, [],\n\
+                      [1,2.5],[\"4\",5],[4,5],[1,0],[\"a\",\"b\\\"\"],null,[1,7],[[],[]], \
+                      [1,{\"2\":[3]}],{\"101\":\"x\"}]";
+        let value = serde_json::from_str::<serde_json::Value>(pblite).unwrap();
+        let example = example::Example::from_pblite(pblite).unwrap();
+        assert_eq!(
+            example,
+            example::Example::from_vec(value.as_array().unwrap()).unwrap()
+        );
+        assert_eq!(example.test_string, Some("f\u{f6}o\n".to_owned()));
+        assert_eq!(example.unknown_fields.len(), 2);
+    }
+
+    /// Asserts that the reader decodes `pblite` like `from_vec`, including errors.
+    fn assert_reader_matches_from_vec<M: Message + PartialEq + ::std::fmt::Debug>(pblite: &str) {
+        let value = serde_json::from_str::<serde_json::Value>(pblite).unwrap();
+        match (M::from_pblite(pblite), M::from_vec(value.as_array().unwrap())) {
+            (Ok(read), Ok(from_vec)) => assert_eq!(read, from_vec, "{}", pblite),
+            (Err(read), Err(from_vec)) => assert_eq!(
+                read.iter().last().unwrap().to_string(),
+                from_vec.iter().last().unwrap().to_string(),
+                "{}",
+                pblite
+            ),
+            (read, from_vec) => panic!("{}: {:?} != {:?}", pblite, read, from_vec),
+        }
+    }
+
+    #[test]
+    fn test_reader_numbers_match_from_vec() {
+        let numbers = [
+            "0", "1", "-1", "2", "-0", "0.0", "1.0", "1.5", "1e2", "1E+2", "-1e-2", "0.1",
+            "2147483647", "2147483648", "-2147483649", "4294967295", "4294967296",
+            "9223372036854775807", "9223372036854775808", "18446744073709551615",
+            "18446744073709551616", "1e308", "\"5\"", "\"-5\"", "\"+5\"", "\" 5\"",
+            "\"1.0\"", "\"18446744073709551616\"", "\"x\"", "true", "[]", "\"Infinity\"",
+            "\"-Infinity\"",
+        ];
+        for number in &numbers {
+            for index in 0..9 {
+                let mut items = vec!["null"; index];
+                items.push(number);
+                let pblite = format!("[{}]", items.join(","));
+                assert_reader_matches_from_vec::<example::ExampleNumbers>(&pblite);
+                assert_reader_matches_from_vec::<example::Example>(&pblite);
             }
+        }
+    }
+
+    #[test]
+    fn test_reader_non_trailing_object() {
+        assert_reader_matches_from_vec::<example::SparseExample>("[{\"101\":5},\"foo\"]");
+        assert_reader_matches_from_vec::<example::SparseExample>("[{\"101\":5},null]");
+        assert_reader_matches_from_vec::<example::Example>(
+            "[null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,\
+             {\"1\":2},{\"101\":3}]",
+        );
+        let error = example::SparseExample::from_pblite("[{\"101\":5},\"foo\"]")
+            .err()
+            .expect("expected error");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "SparseExample.test_string (field 1): expected string value but got: '{\"101\":5}'"
+        );
+    }
+
+    #[test]
+    fn test_reader_borrows_strings() {
+        let mut reader = pblite::Reader::new(" \"foo\" ");
+        assert_matches!(reader.read_str(), Ok(Cow::Borrowed("foo")));
+        reader.finish().unwrap();
+        let mut reader = pblite::Reader::new("\"a\\\"b\\ud83d\\ude00\"");
+        assert_matches!(reader.read_str(), Ok(Cow::Owned(ref s)) if s == "a\"b\u{1f600}");
+    }
+
+    #[test]
+    fn test_reader_skip_header() {
+        let mut reader = pblite::Reader::new("[\"cbu\",\"+15551234567\"]");
+        let phone_number = reader
+            .read_message_skipping::<example::PhoneNumber>(1)
+            .unwrap();
+        assert_eq!(phone_number.e164, Some("+15551234567".to_owned()));
+    }
+
+    #[test]
+    fn test_reader_invalid_json() {
+        let invalid = [
+            "[\"a\",",
+            "[\"a\"] x",
+            "[\"foo]",
+            "[\"a\" \"b\"]",
+            "[{\"101\" 5}]",
+            "[tru]",
+        ];
+        for pblite in &invalid {
+            let error = example::PhoneNumber::from_pblite(pblite)
+                .err()
+                .expect("expected error");
+            assert_eq!(error.description(), "invalid message");
+            assert_eq!(
+                error.iter().last().unwrap().description(),
+                "invalid json",
+                "{}",
+                pblite
+            );
+        }
+    }
+
+    #[test]
+    fn test_reader_lenient_skips_value() {
+        let (example, warnings) = example::Example::from_pblite_lenient(
+            "[null,null,null,null,null,null,null,null,null,null,null,null,[\"a\",[1,[2]]],\
+             null,null,[[],[]]]",
+        ).unwrap();
+        assert_eq!(example.test_repeated_string, None);
+        assert_eq!(example.test_repeated_message.map(|m| m.len()), Some(2));
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_oneof() {
+        let oneof = example::OneofExample::from_pblite("[\"foo\",null,5]").unwrap();
+        assert_eq!(
+            oneof,
+            example::OneofExample {
+                test_oneof: Some(example::OneofExample_TestOneof::TestOneofString(
+                    "foo".to_owned(),
+                )),
+                test_uint32: Some(5),
+                ..Default::default()
+            }
+        );
+        let oneof = example::OneofExample::from_pblite("[null,[\"CA\"]]").unwrap();
+        assert_matches!(
+            oneof.test_oneof,
+            Some(example::OneofExample_TestOneof::TestOneofMessage(_))
+        );
+        let oneof = example::OneofExample::from_pblite("[]").unwrap();
+        assert_eq!(oneof.test_oneof, None);
+    }
+
+    #[test]
+    fn test_oneof_last_member_wins() {
+        let oneof = example::OneofExample::from_pblite("[\"foo\",[\"CA\"]]").unwrap();
+        assert_matches!(
+            oneof.test_oneof,
+            Some(example::OneofExample_TestOneof::TestOneofMessage(_))
+        );
+        assert_eq!(oneof.to_pblite(), "[null,[\"CA\"]]");
+    }
+
+    #[test]
+    fn test_oneof_to_pblite() {
+        let oneof = example::OneofExample {
+            test_oneof: Some(example::OneofExample_TestOneof::TestOneofString(
+                "foo".to_owned(),
+            )),
+            ..Default::default()
+        };
+        assert_eq!(oneof.to_pblite(), "[\"foo\"]");
+        assert_eq!(
+            serde_json::to_value(&oneof).unwrap(),
+            json!({"test_oneof": {"test_oneof_string": "foo"}})
+        );
+    }
+
+    #[test]
+    fn test_sparse_fields() {
+        let sparse = example::SparseExample::from_pblite("[\"foo\",{\"101\":5,\"250\":[\"CA\"]}]").unwrap();
+        assert_eq!(
+            sparse,
+            example::SparseExample {
+                test_string: Some("foo".to_owned()),
+                test_sparse_uint32: Some(5),
+                test_sparse_message: Some(example::I18nData {
+                    region_code: Some("CA".to_owned()),
+                    is_valid: None,
+                    country_code: None,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_sparse_fields_only() {
+        let sparse = example::SparseExample::from_pblite("[{\"101\":5}]").unwrap();
+        assert_eq!(sparse.test_string, None);
+        assert_eq!(sparse.test_sparse_uint32, Some(5));
+    }
+
+    #[test]
+    fn test_sparse_fields_invalid_key() {
+        // Field numbers start at 1.
+        let sparse = example::SparseExample::from_pblite("[\"foo\",{\"0\":5}]");
+        let error = sparse.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        assert_eq!(
+            error.get_decode_error().expect("expected decode error").to_string(),
+            "SparseExample: expected field number value but got: '\"0\"'"
+        );
+        let array = serde_json::from_str("[\"foo\",{\"-1\":5}]").unwrap();
+        let error = example::SparseExample::from_vec(&array)
+            .err()
+            .expect("expected error");
+        assert_eq!(
+            error.to_string(),
+            "SparseExample: expected field number value but got: '\"-1\"'"
+        );
+    }
+
+    #[test]
+    fn test_sparse_fields_to_pblite() {
+        let sparse = example::SparseExample {
+            test_string: None,
+            test_sparse_uint32: Some(5),
+            test_sparse_message: Some(example::I18nData::default()),
+            ..Default::default()
+        };
+        assert_eq!(sparse.to_pblite(), "[{\"101\":5,\"250\":[]}]");
+    }
+
+    #[test]
+    fn test_map() {
+        let map = example::MapExample::from_pblite("[[[\"foo\",1],[\"bar\",2]],[[1,[\"CA\"]]]]").unwrap();
+        let test_map = map.test_map.unwrap();
+        assert_eq!(test_map.len(), 2);
+        assert_eq!(test_map["foo"], 1);
+        assert_eq!(test_map["bar"], 2);
+        let test_message_map = map.test_message_map.unwrap();
+        assert_eq!(test_message_map[&1].region_code, Some("CA".to_owned()));
+    }
+
+    #[test]
+    fn test_map_duplicate_key() {
+        let map = example::MapExample::from_pblite("[[[\"foo\",1],[\"foo\",2]]]").unwrap();
+        assert_eq!(map.test_map.unwrap()["foo"], 2);
+    }
+
+    #[test]
+    fn test_map_expected_array() {
+        let map = example::MapExample::from_pblite("[[\"foo\"]]");
+        assert_eq!(
+            map.err().expect("expected error").description(),
+            "invalid message"
+        );
+    }
+
+    #[test]
+    fn test_map_to_pblite() {
+        let mut test_map = ::std::collections::BTreeMap::new();
+        test_map.insert("foo".to_owned(), 1);
+        test_map.insert("bar".to_owned(), 2);
+        let map = example::MapExample {
+            test_map: Some(test_map),
+            ..Default::default()
+        };
+        assert_eq!(map.to_pblite(), "[[[\"bar\",2],[\"foo\",1]]]");
+        assert_eq!(
+            serde_json::to_value(&map).unwrap(),
+            json!({"test_map": {"bar": 2, "foo": 1}})
+        );
+    }
+
+    #[test]
+    fn test_required_fields() {
+        let required = example::RequiredExample::from_pblite("[\"foo\",[1]]").unwrap();
+        assert_eq!(required.test_required_string, Some("foo".to_owned()));
+        assert_eq!(required.test_required_message.unwrap().test_required_uint32, Some(1));
+    }
+
+    #[test]
+    fn test_required_field_missing() {
+        let required = example::RequiredExample::from_pblite("[null,[1]]");
+        let error = required.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "required field 'test_required_string' is missing"
+        );
+    }
+
+    #[test]
+    fn test_required_field_missing_nested() {
+        let required = example::RequiredExample::from_pblite("[\"foo\",[]]");
+        let error = required.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "required field 'test_required_uint32' is missing"
+        );
+    }
+
+    #[test]
+    fn test_default_values() {
+        let defaults = example::DefaultExample::from_pblite("[]").unwrap();
+        assert_eq!(defaults.test_uint32(), 5);
+        assert_eq!(defaults.test_int64(), -10);
+        assert_eq!(defaults.test_double(), 1.5);
+        assert_eq!(defaults.test_bool(), true);
+        assert_eq!(defaults.test_string(), "foo");
+        assert_eq!(defaults.test_enum(), example::ExampleEnum::ExampleEnumValue2);
+        assert_eq!(defaults.test_enum_no_default(), example::ExampleEnum::ExampleEnumValue1);
+        assert_eq!(defaults.test_uint32_no_default(), 0);
+        assert_eq!(defaults.to_pblite(), "[]");
+        let defaults = example::DefaultExample::from_pblite("[1,2,3.5,0,\"bar\",3]").unwrap();
+        assert_eq!(defaults.test_uint32(), 1);
+        assert_eq!(defaults.test_int64(), 2);
+        assert_eq!(defaults.test_double(), 3.5);
+        assert_eq!(defaults.test_bool(), false);
+        assert_eq!(defaults.test_string(), "bar");
+        assert_eq!(defaults.test_enum(), example::ExampleEnum::ExampleEnumValue3);
+    }
+
+    fn assert_round_trip<M: Message + PartialEq + ::std::fmt::Debug>(pblite: &str) {
+        let message = M::from_pblite(pblite).unwrap();
+        assert_eq!(M::from_pblite(&message.to_pblite()).unwrap(), message);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_round_trip::<example::I18nData>("[\"CA\", 1, 123]");
+        assert_round_trip::<example::I18nData>("[]");
+        assert_round_trip::<example::PhoneNumber>("[\"16067624137\",[\"CA\", 1, 123]]");
+        assert_round_trip::<example::Empty>("[]");
+        assert_round_trip::<example::Example>("[null,\"64\"]");
+        assert_round_trip::<example::Example>("[null,64]");
+        assert_round_trip::<example::Example>("[3.14159,\"64\",32,1,\"foo\",\"AAEC\",2,[],[3.14159,1.1],[64,65],[32,33],[1,0],[\"foo\",\"bar\"],[\"AAEC\",\"AgEA\"],[2,3],[[],[]]]");
+        assert_round_trip::<example::Example>("[null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null,null]");
+        assert_round_trip::<example::SparseExample>("[\"foo\",{\"101\":5,\"250\":[\"CA\"]}]");
+        assert_round_trip::<example::MapExample>("[[[\"foo\",1]],[[1,[\"CA\"]],[2,[]]]]");
+    }
+
+    #[derive(Debug, PartialEq, PbliteMessage)]
+    #[pblite(crate = "crate")]
+    enum DerivedStatus {
+        #[pblite(value = 0)]
+        Unspecified,
+        #[pblite(value = 1)]
+        Active,
+        #[pblite(value = -1)]
+        Deleted,
+        #[pblite(value = 3)]
+        Away,
+        #[pblite(unknown)]
+        Unknown(u32),
+    }
+
+    #[derive(Debug, Default, PartialEq, PbliteMessage)]
+    #[pblite(crate = "crate")]
+    struct DerivedMessage {
+        #[pblite(field = 1, required)]
+        name: Option<String>,
+        #[pblite(field = 2, enumeration)]
+        status: Option<DerivedStatus>,
+        #[pblite(field = 3)]
+        phone_numbers: Option<Vec<example::PhoneNumber>>,
+        #[pblite(field = 4)]
+        counts: Option<BTreeMap<String, u32>>,
+        #[pblite(field = 5)]
+        data: Option<Vec<u8>>,
+        #[pblite(field = 120)]
+        sparse: Option<i64>,
+        #[pblite(unknown_fields)]
+        unknown_fields: pblite::UnknownFields,
+    }
+
+    #[test]
+    fn test_derived_message() {
+        let text = "[\"foo\",3,[[\"16067624137\"]],[[\"bar\",2]],\"AAEC\",7,{\"120\":\"-5\"}]";
+        let message = DerivedMessage::from_pblite(text).unwrap();
+        assert_eq!(
+            message,
+            DerivedMessage {
+                name: Some("foo".to_owned()),
+                status: Some(DerivedStatus::Away),
+                phone_numbers: Some(vec![
+                    example::PhoneNumber {
+                        e164: Some("16067624137".to_owned()),
+                        ..Default::default()
+                    },
+                ]),
+                counts: Some(vec![("bar".to_owned(), 2)].into_iter().collect()),
+                data: Some(vec![0, 1, 2]),
+                sparse: Some(-5),
+                unknown_fields: vec![(6, json!(7))].into_iter().collect(),
+            }
+        );
+        let value = serde_json::from_str::<serde_json::Value>(text).unwrap();
+        assert_eq!(
+            DerivedMessage::from_vec(value.as_array().unwrap()).unwrap(),
+            message
+        );
+        assert_eq!(
+            message.to_pblite(),
+            "[\"foo\",3,[[\"16067624137\"]],[[\"bar\",2]],\"AAEC\",7,{\"120\":-5}]"
+        );
+    }
+
+    #[test]
+    fn test_derived_enum() {
+        let message = DerivedMessage::from_pblite("[\"foo\",8]").unwrap();
+        assert_eq!(message.status, Some(DerivedStatus::Unknown(8)));
+        assert_eq!(message.to_pblite(), "[\"foo\",8]");
+        let message = DerivedMessage::from_pblite("[\"foo\",0]").unwrap();
+        assert_eq!(message.status, Some(DerivedStatus::Unspecified));
+        assert_eq!(message.to_pblite(), "[\"foo\",0]");
+        let message = DerivedMessage::from_pblite("[\"foo\",-1]").unwrap();
+        assert_eq!(message.status, Some(DerivedStatus::Deleted));
+        assert_eq!(message.to_pblite(), "[\"foo\",-1]");
+    }
+
+    #[test]
+    fn test_derived_required_field_missing() {
+        let derived = DerivedMessage::from_pblite("[null,1]");
+        let error = derived.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "required field 'name' is missing"
+        );
+    }
+
+    #[test]
+    fn test_derived_descriptor() {
+        let descriptor = DerivedMessage::descriptor();
+        assert_eq!(descriptor.name, "DerivedMessage");
+        let field = descriptor.get_field("status").unwrap();
+        assert_eq!(field.number, 2);
+        assert_eq!(field.field_type, pblite::FieldType::Enum);
+        assert_eq!(
+            field.enum_type.unwrap()().values,
+            &[
+                pblite::EnumValueDescriptor { name: "Unspecified", number: 0 },
+                pblite::EnumValueDescriptor { name: "Active", number: 1 },
+                pblite::EnumValueDescriptor { name: "Deleted", number: -1 },
+                pblite::EnumValueDescriptor { name: "Away", number: 3 },
+            ]
+        );
+        let field = descriptor.get_field_by_number(4).unwrap();
+        assert_eq!(field.label, pblite::Label::Repeated);
+        assert_eq!(field.map_key_type, Some(pblite::FieldType::String));
+        assert_eq!(
+            descriptor.get_field("phone_numbers").unwrap().message_type.unwrap()().name,
+            "PhoneNumber"
         );
+        assert_eq!(DerivedMessage::default().get_field_name(3), Some("counts"));
     }
 }
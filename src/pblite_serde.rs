@@ -0,0 +1,1199 @@
+//! Serde support for pblite, so types deriving `Deserialize` and `Serialize` can be decoded and
+//! encoded without running codegen.
+//!
+//! Struct fields are identified by field number, given with `#[serde(rename = "N")]`, and enum
+//! variants by value, given the same way. Fields missing from the array or set to null are
+//! absent, so they should be `Option`s or have `#[serde(default)]`. Maps are arrays of
+//! `[key, value]` entries and bytes are base64 strings, as in generated messages.
+//!
+//! Types deriving `PbliteMessage` with `#[pblite(field = N)]` attributes instead implement
+//! `Deserialize` and `Serialize` as pblite. Generated messages and enums serialize their field
+//! and variant names, as they do for `serde_json`, and don't implement `Deserialize`, so they are
+//! wrapped in `Proto` and `ProtoEnum` to be decoded and encoded as pblite.
+//!
+//! ```ignore
+//! #[derive(Deserialize)]
+//! struct Response {
+//!     #[serde(rename = "1")]
+//!     conversation_id: Option<String>,
+//!     #[serde(rename = "3", default)]
+//!     events: Vec<Proto<hangouts::Event>>,
+//! }
+//!
+//! #[derive(Default, PbliteMessage)]
+//! struct Request {
+//!     #[pblite(field = 1)]
+//!     conversation_id: Option<String>,
+//!     #[pblite(field = 2)]
+//!     event_types: Option<Vec<u32>>,
+//! }
+//! ```
+
+extern crate serde;
+extern crate serde_json;
+
+use pblite;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Impossible};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt::{self, Display};
+use std::marker::PhantomData;
+use std::mem;
+
+error_chain!{
+    links {
+        Pblite(pblite::Error, pblite::ErrorKind);
+    }
+    errors {
+        Unsupported(what: &'static str) {
+            description("unsupported type"),
+            display("{} can't be encoded as pblite", what),
+        }
+        InvalidFieldName(name: String) {
+            description("invalid field name"),
+            display("field name '{}' is not a field number", name),
+        }
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(message: T) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(message: T) -> Self {
+        message.to_string().into()
+    }
+}
+
+/// Name of the newtype struct used by `serialize_message` to pass an encoded message to
+/// `Serializer`, and by `deserialize_message` to have `Deserializer` pass a message's text.
+const PROTO_VALUE_NAME: &str = "$pblite_serde::Proto";
+
+thread_local! {
+    /// Error of the last message that `MessageVisitor` failed to decode from its text, which is
+    /// handed back to `Deserializer` so that it isn't reduced to a custom error message.
+    static MESSAGE_ERROR: RefCell<Option<pblite::Error>> = RefCell::new(None);
+}
+
+/// Applies a change to the pblite decode error in `error`, if it is one.
+fn map_decode_error<F>(error: Error, update: F) -> Error
+where
+    F: FnOnce(pblite::DecodeError) -> pblite::DecodeError,
+{
+    match error {
+        Error(ErrorKind::Pblite(pblite::ErrorKind::Decode(decode_error)), state) => Error(
+            ErrorKind::Pblite(pblite::ErrorKind::Decode(update(decode_error))),
+            state,
+        ),
+        error => error,
+    }
+}
+
+fn expected_value(expected: &'static str, reader: &mut pblite::Reader) -> Error {
+    match reader.read_value() {
+        Ok(value) => pblite::expected_value(expected, &value).into(),
+        Err(error) => error.into(),
+    }
+}
+
+/// Parses the field number of a struct field or sparse field.
+fn parse_field_number(name: &str) -> Option<usize> {
+    name.parse::<usize>().ok().and_then(|number| match number {
+        0 => None,
+        number => Some(number),
+    })
+}
+
+/// Decodes a value from pblite text.
+pub fn from_str<'de, T: Deserialize<'de>>(text: &'de str) -> Result<T> {
+    from_str_skipping(text, 0)
+}
+
+/// Decodes a struct from pblite text, ignoring the first `skip` items of its array, such as the
+/// header of a pblite response.
+pub fn from_str_skipping<'de, T: Deserialize<'de>>(text: &'de str, skip: usize) -> Result<T> {
+    let mut deserializer = Deserializer {
+        reader: pblite::Reader::new(text),
+        skip: skip,
+    };
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.reader.finish()?;
+    Ok(value)
+}
+
+/// Encodes a value as a pblite `serde_json::Value`.
+pub fn to_value<T: Serialize>(value: &T) -> Result<Value> {
+    value.serialize(Serializer)
+}
+
+/// Encodes a value as pblite text.
+pub fn to_string<T: Serialize>(value: &T) -> Result<String> {
+    to_value(value).map(|value| value.to_string())
+}
+
+/// Deserializer reading pblite text with a `pblite::Reader`, so strings without escape sequences
+/// can be borrowed.
+pub struct Deserializer<'de> {
+    reader: pblite::Reader<'de>,
+    /// Number of items to ignore at the start of the next struct's array.
+    skip: usize,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.reader.peek() {
+            Some(b'n') if self.reader.consume_null() => visitor.visit_unit(),
+            Some(b'"') => self.deserialize_str(visitor),
+            Some(b'[') => self.deserialize_seq(visitor),
+            Some(b'{') => {
+                self.reader.consume(b'{');
+                visitor.visit_map(ObjectAccess { de: self, count: 0 })
+            }
+            _ => match self.reader.read_value()? {
+                Value::Bool(value) => visitor.visit_bool(value),
+                Value::Number(ref number) if number.is_u64() => {
+                    visitor.visit_u64(number.as_u64().unwrap())
+                }
+                Value::Number(ref number) if number.is_i64() => {
+                    visitor.visit_i64(number.as_i64().unwrap())
+                }
+                Value::Number(ref number) => visitor.visit_f64(number.as_f64().unwrap()),
+                value => Err(pblite::expected_value("value", &value).into()),
+            },
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_bool(self.reader.read_bool()?)
+    }
+
+    // Smaller integers are range checked by their visitors.
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_i32(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_i32(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(self.reader.read_int32()?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(self.reader.read_int64()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(self.reader.read_uint32()?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(self.reader.read_uint64()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(self.reader.read_float()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(self.reader.read_double()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.reader.peek() != Some(b'"') {
+            return visitor.visit_string(self.reader.read_string()?);
+        }
+        match self.reader.read_str()? {
+            Cow::Borrowed(value) => visitor.visit_borrowed_str(value),
+            Cow::Owned(value) => visitor.visit_string(value),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.reader.read_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.reader.consume_null() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.reader.consume_null() {
+            visitor.visit_unit()
+        } else {
+            Err(expected_value("null", &mut self.reader))
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    /// Messages are decoded from their text by `deserialize_message` with a `pblite::Reader`.
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        if name != PROTO_VALUE_NAME {
+            return visitor.visit_newtype_struct(self);
+        }
+        let text = self.reader.skip_value()?;
+        visitor.visit_borrowed_str(text).map_err(|error| {
+            MESSAGE_ERROR
+                .with(|message_error| message_error.borrow_mut().take())
+                .map_or(error, Error::from)
+        })
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if !self.reader.consume(b'[') {
+            return Err(expected_value("array", &mut self.reader));
+        }
+        visitor.visit_seq(ElementAccess { de: self, index: 0 })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    /// Maps are arrays of `[key, value]` entries.
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if !self.reader.consume(b'[') {
+            return Err(expected_value("array", &mut self.reader));
+        }
+        visitor.visit_map(EntryAccess { de: self, index: 0 })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        if !self.reader.consume(b'[') {
+            return Err(expected_value("array", &mut self.reader));
+        }
+        let skip = mem::replace(&mut self.skip, 0);
+        visitor.visit_map(FieldAccess {
+            de: self,
+            name: name,
+            skip: skip,
+            index: 0,
+            sparse_count: None,
+            number: 0,
+        })
+    }
+
+    /// Enums are numbers, which are matched to variants by name.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let number = self.reader.read_uint32()?;
+        visitor.visit_enum(number.to_string().into_deserializer())
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.reader.skip_value()?;
+        visitor.visit_unit()
+    }
+}
+
+/// Reads the fields of a struct from its array, and the trailing object of sparse fields.
+struct FieldAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    name: &'static str,
+    skip: usize,
+    /// Number of items of the array started so far.
+    index: usize,
+    /// Number of sparse fields started so far, while reading the object of sparse fields.
+    sparse_count: Option<usize>,
+    /// Number of the field whose value is read next.
+    number: usize,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for FieldAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        loop {
+            let reader = &mut self.de.reader;
+            if let Some(count) = self.sparse_count {
+                if reader.consume(b'}') {
+                    self.sparse_count = None;
+                    continue;
+                }
+                if count > 0 {
+                    reader.expect(b',', "expected ',' or closing bracket")?;
+                }
+                self.sparse_count = Some(count + 1);
+                let key = reader.read_str()?;
+                self.number = parse_field_number(&key).ok_or_else(|| {
+                    pblite::expected_value("field number", &Value::String(key.to_string()))
+                })?;
+                reader.expect(b':', "expected ':'")?;
+            } else {
+                if reader.consume(b']') {
+                    return Ok(None);
+                }
+                if self.index > 0 {
+                    reader.expect(b',', "expected ',' or closing bracket")?;
+                }
+                self.index += 1;
+                if self.index <= self.skip {
+                    reader.skip_value()?;
+                    continue;
+                }
+                if reader.consume(b'{') {
+                    self.sparse_count = Some(0);
+                    continue;
+                }
+                self.number = self.index - self.skip;
+            }
+            if !reader.consume_null() {
+                return seed.deserialize(self.number.to_string().into_deserializer())
+                    .map(Some);
+            }
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let (name, number) = (self.name, self.number);
+        seed.deserialize(&mut *self.de).map_err(|error| {
+            map_decode_error(error, |decode_error| {
+                decode_error.in_field(name, None, number)
+            })
+        })
+    }
+}
+
+/// Reads the elements of an array.
+struct ElementAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    index: usize,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for ElementAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.de.reader.consume(b']') {
+            return Ok(None);
+        }
+        if self.index > 0 {
+            self.de.reader.expect(b',', "expected ',' or closing bracket")?;
+        }
+        let index = self.index;
+        self.index += 1;
+        seed.deserialize(&mut *self.de).map(Some).map_err(|error| {
+            map_decode_error(error, |mut decode_error| {
+                decode_error.path.insert(0, pblite::PathSegment::Index(index));
+                decode_error
+            })
+        })
+    }
+}
+
+/// Reads the `[key, value]` entries of a map.
+struct EntryAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    index: usize,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for EntryAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let reader = &mut self.de.reader;
+        if reader.consume(b']') {
+            return Ok(None);
+        }
+        if self.index > 0 {
+            reader.expect(b',', "expected ',' or closing bracket")?;
+        }
+        self.index += 1;
+        if !reader.consume(b'[') {
+            return Err(expected_value("array", reader));
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        // Like `pblite::read_map`, a missing value is read as null.
+        let value = if self.de.reader.consume(b',') {
+            seed.deserialize(&mut *self.de)?
+        } else {
+            seed.deserialize(&mut Deserializer {
+                reader: pblite::Reader::new("null"),
+                skip: 0,
+            })?
+        };
+        while self.de.reader.consume(b',') {
+            self.de.reader.skip_value()?;
+        }
+        self.de.reader.expect(b']', "expected ',' or closing bracket")?;
+        Ok(value)
+    }
+}
+
+/// Reads a JSON object, which only occurs in pblite as the object of sparse fields.
+struct ObjectAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    count: usize,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for ObjectAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let reader = &mut self.de.reader;
+        if reader.consume(b'}') {
+            return Ok(None);
+        }
+        if self.count > 0 {
+            reader.expect(b',', "expected ',' or closing bracket")?;
+        }
+        self.count += 1;
+        let key = reader.read_str()?;
+        reader.expect(b':', "expected ':'")?;
+        seed.deserialize(key.into_owned().into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+/// Serializer producing a pblite `serde_json::Value`.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = ArraySerializer;
+    type SerializeTuple = ArraySerializer;
+    type SerializeTupleStruct = ArraySerializer;
+    type SerializeTupleVariant = Impossible<Value, Error>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = StructSerializer;
+    type SerializeStructVariant = Impossible<Value, Error>;
+
+    fn serialize_bool(self, value: bool) -> Result<Value> {
+        Ok(pblite::write_bool(&value))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Value> {
+        self.serialize_i32(value as i32)
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Value> {
+        self.serialize_i32(value as i32)
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Value> {
+        Ok(pblite::write_int32(&value))
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Value> {
+        Ok(pblite::write_int64(&value))
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Value> {
+        self.serialize_u32(value as u32)
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Value> {
+        self.serialize_u32(value as u32)
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Value> {
+        Ok(pblite::write_uint32(&value))
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Value> {
+        Ok(pblite::write_uint64(&value))
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<Value> {
+        Ok(pblite::write_float(&value))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<Value> {
+        Ok(pblite::write_double(&value))
+    }
+
+    fn serialize_char(self, value: char) -> Result<Value> {
+        Ok(Value::String(value.to_string()))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Value> {
+        Ok(Value::String(value.to_owned()))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Value> {
+        Ok(pblite::write_bytes(&value.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Value> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value> {
+        Ok(Value::Null)
+    }
+
+    /// Enums are encoded as numbers, which are the names of their variants.
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value> {
+        variant
+            .parse::<u32>()
+            .map(|number| pblite::write_uint32(&number))
+            .or(Err(ErrorKind::InvalidFieldName(variant.to_owned()).into()))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Value> {
+        if name == PROTO_VALUE_NAME {
+            // The value is already encoded, so it's copied as is.
+            return serde_json::to_value(value).map_err(ser::Error::custom);
+        }
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Value> {
+        Err(ErrorKind::Unsupported("enum variant with data").into())
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ArraySerializer> {
+        Ok(ArraySerializer(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ArraySerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<ArraySerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Impossible<Value, Error>> {
+        Err(ErrorKind::Unsupported("enum variant with data").into())
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer> {
+        Ok(MapSerializer {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<StructSerializer> {
+        Ok(StructSerializer(Vec::with_capacity(len)))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Impossible<Value, Error>> {
+        Err(ErrorKind::Unsupported("enum variant with data").into())
+    }
+}
+
+pub struct ArraySerializer(Vec<Value>);
+
+impl ser::SerializeSeq for ArraySerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        self.0.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Array(self.0))
+    }
+}
+
+impl ser::SerializeTuple for ArraySerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for ArraySerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+/// Encodes a map as an array of `[key, value]` entries.
+pub struct MapSerializer {
+    entries: Vec<Value>,
+    key: Option<Value>,
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<()> {
+        let key = self.key.take().unwrap_or(Value::Null);
+        self.entries
+            .push(Value::Array(vec![key, value.serialize(Serializer)?]));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Array(self.entries))
+    }
+}
+
+/// Encodes a struct as a pblite array, using the field names as field numbers.
+pub struct StructSerializer(Vec<(usize, Value)>);
+
+impl ser::SerializeStruct for StructSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        let number = parse_field_number(key)
+            .ok_or_else(|| Error::from(ErrorKind::InvalidFieldName(key.to_owned())))?;
+        self.0.push((number - 1, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value> {
+        Ok(Value::Array(pblite::write_fields(self.0)))
+    }
+}
+
+/// Decodes a message from a pblite array, which `Deserializer` passes as text.
+struct MessageVisitor<M>(PhantomData<M>);
+
+impl<'de, M: pblite::Message> Visitor<'de> for MessageVisitor<M> {
+    type Value = M;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a pblite array")
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, text: &'de str) -> ::std::result::Result<M, E> {
+        pblite::Reader::new(text).read_message().map_err(|error| {
+            let message = error.to_string();
+            MESSAGE_ERROR.with(|message_error| *message_error.borrow_mut() = Some(error));
+            E::custom(message)
+        })
+    }
+
+    /// Other deserializers pass themselves, so the array is read as a `serde_json::Value`.
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> ::std::result::Result<M, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let array = value
+            .as_array()
+            .ok_or_else(|| de::Error::custom(pblite::expected_value("array", &value)))?;
+        M::from_vec(array).map_err(|error| {
+            // Custom errors are only messages, so the causes are included.
+            let messages = error.iter().map(|cause| cause.to_string()).collect::<Vec<_>>();
+            de::Error::custom(messages.join(": "))
+        })
+    }
+}
+
+/// Decodes a message from a pblite array. Types deriving `PbliteMessage` implement
+/// `Deserialize` with this.
+pub fn deserialize_message<'de, D, M>(deserializer: D) -> ::std::result::Result<M, D::Error>
+where
+    D: de::Deserializer<'de>,
+    M: pblite::Message,
+{
+    deserializer.deserialize_newtype_struct(PROTO_VALUE_NAME, MessageVisitor(PhantomData))
+}
+
+/// Encodes a message as a pblite array. Types deriving `PbliteMessage` implement `Serialize`
+/// with this.
+pub fn serialize_message<S, M>(
+    message: &M,
+    serializer: S,
+) -> ::std::result::Result<S::Ok, S::Error>
+where
+    S: ser::Serializer,
+    M: pblite::Message,
+{
+    serializer.serialize_newtype_struct(PROTO_VALUE_NAME, &message.to_value())
+}
+
+/// Decodes an enum from its number. Enums deriving `PbliteMessage` implement `Deserialize` with
+/// this.
+pub fn deserialize_enum<'de, D, E>(deserializer: D) -> ::std::result::Result<E, D::Error>
+where
+    D: de::Deserializer<'de>,
+    E: pblite::Enum,
+{
+    // Negative values are represented by their two's complement.
+    let number = i32::deserialize(deserializer)?;
+    E::from_u32(number as u32).map_err(de::Error::custom)
+}
+
+/// Encodes an enum as its number. Enums deriving `PbliteMessage` implement `Serialize` with
+/// this.
+pub fn serialize_enum<S, E>(value: &E, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+where
+    S: ser::Serializer,
+    E: pblite::Enum,
+{
+    serializer.serialize_i32(value.to_u32() as i32)
+}
+
+/// A generated message, for use as a field of a type deriving `Deserialize` or `Serialize`.
+/// Generated messages serialize their field names, as they do for `serde_json`, so they are
+/// wrapped to be decoded and encoded as pblite arrays instead.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Proto<M>(pub M);
+
+impl<'de, M: pblite::Message> Deserialize<'de> for Proto<M> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        deserialize_message(deserializer).map(Proto)
+    }
+}
+
+impl<M: pblite::Message> Serialize for Proto<M> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serialize_message(&self.0, serializer)
+    }
+}
+
+/// A generated enum, for use like `Proto`. Generated enums serialize their variant names, so
+/// they are wrapped to be decoded and encoded as numbers instead.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ProtoEnum<E>(pub E);
+
+impl<'de, E: pblite::Enum> Deserialize<'de> for ProtoEnum<E> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        deserialize_enum(deserializer).map(ProtoEnum)
+    }
+}
+
+impl<E: pblite::Enum> Serialize for ProtoEnum<E> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serialize_enum(&self.0, serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use example;
+    use pblite;
+    use pblite_serde;
+    use pblite_serde::{Proto, ProtoEnum};
+    use serde_json;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    enum Status {
+        #[serde(rename = "1")]
+        Active,
+        #[serde(rename = "3")]
+        Archived,
+    }
+
+    #[derive(Debug, PartialEq, Default, Deserialize, Serialize)]
+    struct Participant<'a> {
+        #[serde(rename = "1", borrow)]
+        name: Option<&'a str>,
+        #[serde(rename = "2")]
+        is_self: Option<bool>,
+    }
+
+    #[derive(Debug, PartialEq, Default, Deserialize, Serialize)]
+    struct Conversation<'a> {
+        #[serde(rename = "1")]
+        id: String,
+        #[serde(rename = "2")]
+        status: Option<Status>,
+        #[serde(rename = "4", borrow, default)]
+        participants: Vec<Participant<'a>>,
+        #[serde(rename = "5", default)]
+        read_timestamps: BTreeMap<String, u64>,
+        #[serde(rename = "6")]
+        phone_number: Option<Proto<example::PhoneNumber>>,
+        #[serde(rename = "120")]
+        sparse: Option<i32>,
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let conversation: Conversation = pblite_serde::from_str(
+            "[\"UgzJ\", 3, 42, [[\"Ann\", 1], [null, 0]], [[\"Ann\", \"1521002546845000\"]], \
+             [\"16067624137\", [\"CA\", 1, 123]], {\"120\": -5}]",
+        ).unwrap();
+        assert_eq!(
+            conversation,
+            Conversation {
+                id: "UgzJ".to_owned(),
+                status: Some(Status::Archived),
+                participants: vec![
+                    Participant {
+                        name: Some("Ann"),
+                        is_self: Some(true),
+                    },
+                    Participant {
+                        name: None,
+                        is_self: Some(false),
+                    },
+                ],
+                read_timestamps: vec![("Ann".to_owned(), 1521002546845000)]
+                    .into_iter()
+                    .collect(),
+                phone_number: Some(Proto(example::PhoneNumber {
+                    e164: Some("16067624137".to_owned()),
+                    i18n_data: Some(example::I18nData {
+                        region_code: Some("CA".to_owned()),
+                        is_valid: Some(true),
+                        country_code: Some(123),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })),
+                sparse: Some(-5),
+            }
+        );
+    }
+
+    #[test]
+    fn test_deserialize_skipping() {
+        let conversation: Conversation =
+            pblite_serde::from_str_skipping("[\"cgserp\", \"UgzJ\", null, null, [[\"Ann\"]]]", 1)
+                .unwrap();
+        assert_eq!(
+            conversation,
+            Conversation {
+                id: "UgzJ".to_owned(),
+                participants: vec![
+                    Participant {
+                        name: Some("Ann"),
+                        is_self: None,
+                    },
+                ],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_deserialize_missing_field() {
+        let error = pblite_serde::from_str::<Conversation>("[null, 1]").unwrap_err();
+        assert_eq!(error.to_string(), "missing field `1`");
+    }
+
+    #[test]
+    fn test_deserialize_invalid_value() {
+        let error =
+            pblite_serde::from_str::<Conversation>("[\"UgzJ\", null, null, [[\"Ann\", 2]]]")
+                .unwrap_err();
+        assert_matches!(
+            error.kind(),
+            &pblite_serde::ErrorKind::Pblite(pblite::ErrorKind::Decode(ref decode_error)) => {
+                assert_eq!(decode_error.get_path(), "Conversation.4[0].2");
+                assert_eq!(decode_error.expected, "0 or 1");
+            }
+        );
+    }
+
+    #[test]
+    fn test_deserialize_invalid_json() {
+        let error = pblite_serde::from_str::<Conversation>("[\"UgzJ\" 1]").unwrap_err();
+        assert_matches!(
+            error.kind(),
+            &pblite_serde::ErrorKind::Pblite(pblite::ErrorKind::Syntax(_, 8))
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        let conversation = Conversation {
+            id: "UgzJ".to_owned(),
+            status: Some(Status::Active),
+            participants: vec![
+                Participant {
+                    name: Some("Ann"),
+                    is_self: Some(true),
+                },
+            ],
+            read_timestamps: vec![("Ann".to_owned(), 1521002546845000)]
+                .into_iter()
+                .collect(),
+            phone_number: Some(Proto(example::PhoneNumber {
+                e164: Some("16067624137".to_owned()),
+                ..Default::default()
+            })),
+            sparse: Some(-5),
+        };
+        let text = pblite_serde::to_string(&conversation).unwrap();
+        assert_eq!(
+            text,
+            "[\"UgzJ\",1,null,[[\"Ann\",1]],[[\"Ann\",1521002546845000]],[\"16067624137\"],\
+             {\"120\":-5}]"
+        );
+        assert_eq!(pblite_serde::from_str::<Conversation>(&text).unwrap(), conversation);
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct GeneratedFields {
+        #[serde(rename = "1")]
+        phone_number: Option<Proto<example::PhoneNumber>>,
+        #[serde(rename = "2")]
+        test_enum: Option<ProtoEnum<example::ExampleEnum>>,
+        #[serde(rename = "3", default)]
+        test_enums: Vec<ProtoEnum<example::ExampleEnum>>,
+    }
+
+    #[test]
+    fn test_generated() {
+        let text = "[[\"16067624137\",[\"CA\"]],2,[1,100]]";
+        let fields: GeneratedFields = pblite_serde::from_str(text).unwrap();
+        assert_eq!(
+            fields,
+            GeneratedFields {
+                phone_number: Some(Proto(example::PhoneNumber {
+                    e164: Some("16067624137".to_owned()),
+                    i18n_data: Some(example::I18nData {
+                        region_code: Some("CA".to_owned()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                })),
+                test_enum: Some(ProtoEnum(example::ExampleEnum::ExampleEnumValue2)),
+                test_enums: vec![
+                    ProtoEnum(example::ExampleEnum::ExampleEnumValue1),
+                    ProtoEnum(example::ExampleEnum::Unknown(100)),
+                ],
+            }
+        );
+        assert_eq!(pblite_serde::to_string(&fields).unwrap(), text);
+        let phone_number: Proto<example::PhoneNumber> =
+            pblite_serde::from_str("[\"16067624137\"]").unwrap();
+        assert_eq!(phone_number.0.e164, Some("16067624137".to_owned()));
+    }
+
+    #[test]
+    fn test_generated_decode_error() {
+        let error = pblite_serde::from_str::<GeneratedFields>("[[\"16067624137\",[\"CA\",2]]]")
+            .err()
+            .expect("expected error");
+        assert_matches!(
+            error.kind(),
+            &pblite_serde::ErrorKind::Pblite(pblite::ErrorKind::Decode(ref decode_error)) => {
+                assert_eq!(decode_error.get_path(), "GeneratedFields.1.i18n_data.is_valid");
+                assert_eq!(decode_error.expected, "0 or 1");
+            }
+        );
+    }
+
+    #[test]
+    fn test_generated_with_serde_json() {
+        // Other serializers and deserializers get the pblite array as a JSON value.
+        let phone_number = Proto(example::PhoneNumber {
+            e164: Some("16067624137".to_owned()),
+            ..Default::default()
+        });
+        let text = serde_json::to_string(&phone_number).unwrap();
+        assert_eq!(text, "[\"16067624137\"]");
+        assert_eq!(
+            serde_json::from_str::<Proto<example::PhoneNumber>>(&text).unwrap(),
+            phone_number
+        );
+        let error = serde_json::from_str::<Proto<example::PhoneNumber>>("[\"16067624137\",[1]]")
+            .err()
+            .expect("expected error");
+        assert_eq!(
+            error.to_string(),
+            "PhoneNumber.i18n_data.region_code (field 1): expected string value but got: '1'"
+        );
+    }
+
+    #[derive(Debug, PartialEq, PbliteMessage)]
+    #[pblite(crate = "crate")]
+    enum Presence {
+        #[pblite(value = 1)]
+        Online,
+        #[pblite(value = -1)]
+        Offline,
+    }
+
+    #[derive(Debug, Default, PartialEq, PbliteMessage)]
+    #[pblite(crate = "crate")]
+    struct Contact {
+        #[pblite(field = 1, required)]
+        name: Option<String>,
+        #[pblite(field = 2, enumeration)]
+        presence: Option<Presence>,
+        #[pblite(field = 3)]
+        phone_number: Option<example::PhoneNumber>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct ContactList {
+        #[serde(rename = "1", default)]
+        contacts: Vec<Contact>,
+        #[serde(rename = "2")]
+        presence: Option<Presence>,
+    }
+
+    #[test]
+    fn test_derived() {
+        let text = "[[[\"Ann\",-1,[\"16067624137\"]],[\"Bob\"]],1]";
+        let contact_list: ContactList = pblite_serde::from_str(text).unwrap();
+        assert_eq!(
+            contact_list,
+            ContactList {
+                contacts: vec![
+                    Contact {
+                        name: Some("Ann".to_owned()),
+                        presence: Some(Presence::Offline),
+                        phone_number: Some(example::PhoneNumber {
+                            e164: Some("16067624137".to_owned()),
+                            ..Default::default()
+                        }),
+                    },
+                    Contact {
+                        name: Some("Bob".to_owned()),
+                        ..Default::default()
+                    },
+                ],
+                presence: Some(Presence::Online),
+            }
+        );
+        assert_eq!(pblite_serde::to_string(&contact_list).unwrap(), text);
+        let contact: Contact = pblite_serde::from_str("[\"Bob\",1]").unwrap();
+        assert_eq!(contact.presence, Some(Presence::Online));
+        assert_eq!(pblite_serde::to_string(&contact).unwrap(), "[\"Bob\",1]");
+        let error = pblite_serde::from_str::<Contact>("[null,1]")
+            .err()
+            .expect("expected error");
+        let messages = error.iter().map(|cause| cause.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec!["message 'Contact' is invalid", "required field 'name' is missing"]
+        );
+    }
+
+    #[test]
+    fn test_serialize_invalid_field_name() {
+        #[derive(Serialize)]
+        struct Named {
+            name: u32,
+        }
+        let error = pblite_serde::to_value(&Named { name: 1 }).unwrap_err();
+        assert_matches!(
+            error.kind(),
+            &pblite_serde::ErrorKind::InvalidFieldName(ref name) if name == "name"
+        );
+    }
+}
@@ -0,0 +1,861 @@
+//! Binary protobuf wire format, for the same generated message types as `pblite`.
+
+use pblite;
+use pblite::Enum;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+error_chain!{
+    links {
+        Pblite(pblite::Error, pblite::ErrorKind);
+    }
+    errors {
+        InvalidMessage(name: String) {
+            description("invalid message"),
+            display("message '{}' is invalid", name),
+        }
+        InvalidField(number: u32) {
+            description("invalid field"),
+            display("field {} is invalid", number),
+        }
+        Truncated {
+            description("truncated message"),
+            display("message is truncated"),
+        }
+        InvalidVarint {
+            description("invalid varint"),
+            display("varint is longer than 10 bytes"),
+        }
+        InvalidWireType(wire_type: u64) {
+            description("invalid wire type"),
+            display("wire type {} is invalid", wire_type),
+        }
+        UnexpectedWireType(expected: WireType, actual: WireType) {
+            description("unexpected wire type"),
+            display("expected {:?} value but got {:?} value", expected, actual),
+        }
+        InvalidString {
+            description("invalid string"),
+            display("string is not valid utf-8"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WireType {
+    Varint,
+    Fixed64,
+    LengthDelimited,
+    Fixed32,
+}
+impl WireType {
+    fn from_u64(value: u64) -> Result<Self> {
+        match value {
+            0 => Ok(WireType::Varint),
+            1 => Ok(WireType::Fixed64),
+            2 => Ok(WireType::LengthDelimited),
+            5 => Ok(WireType::Fixed32),
+            // Groups are deprecated and not supported.
+            _ => Err(ErrorKind::InvalidWireType(value).into()),
+        }
+    }
+
+    fn to_u64(&self) -> u64 {
+        match self {
+            &WireType::Varint => 0,
+            &WireType::Fixed64 => 1,
+            &WireType::LengthDelimited => 2,
+            &WireType::Fixed32 => 5,
+        }
+    }
+}
+
+/// A single encoded value, which is decoded once its field's type is known.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FieldValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    LengthDelimited(Cow<'a, [u8]>),
+    Fixed32(u32),
+}
+impl<'a> FieldValue<'a> {
+    pub fn get_wire_type(&self) -> WireType {
+        match self {
+            &FieldValue::Varint(_) => WireType::Varint,
+            &FieldValue::Fixed64(_) => WireType::Fixed64,
+            &FieldValue::LengthDelimited(_) => WireType::LengthDelimited,
+            &FieldValue::Fixed32(_) => WireType::Fixed32,
+        }
+    }
+
+    pub fn into_owned(self) -> FieldValue<'static> {
+        match self {
+            FieldValue::Varint(value) => FieldValue::Varint(value),
+            FieldValue::Fixed64(value) => FieldValue::Fixed64(value),
+            FieldValue::LengthDelimited(bytes) => {
+                FieldValue::LengthDelimited(Cow::Owned(bytes.into_owned()))
+            }
+            FieldValue::Fixed32(value) => FieldValue::Fixed32(value),
+        }
+    }
+}
+
+/// Fields that were not recognized when decoding the binary wire format, by field number.
+pub type UnknownFields = BTreeMap<u32, Vec<FieldValue<'static>>>;
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes: bytes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn read_slice(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            return Err(ErrorKind::Truncated.into());
+        }
+        let (slice, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(slice)
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in 0..10 {
+            let byte = self.read_slice(1)?[0];
+            value |= ((byte & 0x7f) as u64) << (7 * shift);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(ErrorKind::InvalidVarint.into())
+    }
+
+    fn read_fixed(&mut self, length: usize) -> Result<u64> {
+        let bytes = self.read_slice(length)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | byte as u64))
+    }
+
+    pub fn read_value(&mut self, wire_type: WireType) -> Result<FieldValue<'a>> {
+        Ok(match wire_type {
+            WireType::Varint => FieldValue::Varint(self.read_varint()?),
+            WireType::Fixed64 => FieldValue::Fixed64(self.read_fixed(8)?),
+            WireType::LengthDelimited => {
+                let length = self.read_varint()? as usize;
+                FieldValue::LengthDelimited(Cow::Borrowed(self.read_slice(length)?))
+            }
+            WireType::Fixed32 => FieldValue::Fixed32(self.read_fixed(4)? as u32),
+        })
+    }
+
+    /// Reads the next field number and value, or returns `None` at the end of the message.
+    pub fn read_field(&mut self) -> Result<Option<(u32, FieldValue<'a>)>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.read_varint()?;
+        let value = self.read_value(WireType::from_u64(tag & 0x7)?)?;
+        Ok(Some(((tag >> 3) as u32, value)))
+    }
+}
+
+#[derive(Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+impl Writer {
+    pub fn new() -> Self {
+        Writer::default()
+    }
+
+    pub fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn write_fixed(&mut self, value: u64, length: usize) {
+        for index in 0..length {
+            self.bytes.push((value >> (8 * index)) as u8);
+        }
+    }
+
+    pub fn write_value(&mut self, value: &FieldValue) {
+        match value {
+            &FieldValue::Varint(value) => self.write_varint(value),
+            &FieldValue::Fixed64(value) => self.write_fixed(value, 8),
+            &FieldValue::LengthDelimited(ref bytes) => {
+                self.write_varint(bytes.len() as u64);
+                self.bytes.extend_from_slice(bytes);
+            }
+            &FieldValue::Fixed32(value) => self.write_fixed(value as u64, 4),
+        }
+    }
+
+    pub fn write_field(&mut self, number: u32, value: FieldValue) {
+        self.write_field_ref(number, &value);
+    }
+
+    fn write_field_ref(&mut self, number: u32, value: &FieldValue) {
+        self.write_varint((number as u64) << 3 | value.get_wire_type().to_u64());
+        self.write_value(value);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// A message that can also be encoded in the binary wire format.
+///
+/// Unknown fields are kept in `binary_unknown_fields` when decoding and written back when
+/// encoding. `pblite::UnknownFields` are not encoded, since pblite doesn't record the types of
+/// their values and guessing them could produce fields that are misread.
+pub trait Message: pblite::Message {
+    fn merge_field(&mut self, number: u32, field_value: FieldValue) -> Result<()>;
+
+    fn write_fields(&self, writer: &mut Writer);
+
+    /// Checks the required fields of the message and its nested messages, once all of their
+    /// values have been merged.
+    fn check_initialized(&self) -> Result<()> {
+        Ok(self.check_required()?)
+    }
+
+    /// Merges the fields in `bytes` into the message, without checking required fields.
+    ///
+    /// Singular fields are replaced, except messages, which are merged, and repeated fields are
+    /// appended to.
+    fn merge_binary(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut reader = Reader::new(bytes);
+        while let Some((number, field_value)) = reader
+            .read_field()
+            .chain_err(|| ErrorKind::InvalidMessage(self.get_name().to_owned()))?
+        {
+            self.merge_field(number, field_value)
+                .chain_err(|| ErrorKind::InvalidField(number))
+                .chain_err(|| ErrorKind::InvalidMessage(self.get_name().to_owned()))?;
+        }
+        Ok(())
+    }
+
+    fn from_binary(bytes: &[u8]) -> Result<Self> {
+        let mut message = Self::default();
+        message.merge_binary(bytes)?;
+        check_message(&message)?;
+        Ok(message)
+    }
+
+    fn to_binary(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.write_fields(&mut writer);
+        writer.into_bytes()
+    }
+}
+
+fn unexpected_wire_type(expected: WireType, actual: &FieldValue) -> Error {
+    ErrorKind::UnexpectedWireType(expected, actual.get_wire_type()).into()
+}
+
+fn get_varint(value: FieldValue) -> Result<u64> {
+    match value {
+        FieldValue::Varint(value) => Ok(value),
+        value => Err(unexpected_wire_type(WireType::Varint, &value)),
+    }
+}
+
+fn get_fixed64(value: FieldValue) -> Result<u64> {
+    match value {
+        FieldValue::Fixed64(value) => Ok(value),
+        value => Err(unexpected_wire_type(WireType::Fixed64, &value)),
+    }
+}
+
+fn get_fixed32(value: FieldValue) -> Result<u32> {
+    match value {
+        FieldValue::Fixed32(value) => Ok(value),
+        value => Err(unexpected_wire_type(WireType::Fixed32, &value)),
+    }
+}
+
+fn get_bytes(value: FieldValue) -> Result<Cow<[u8]>> {
+    match value {
+        FieldValue::LengthDelimited(bytes) => Ok(bytes),
+        value => Err(unexpected_wire_type(WireType::LengthDelimited, &value)),
+    }
+}
+
+pub fn read_int32(value: FieldValue) -> Result<i32> {
+    get_varint(value).map(|value| value as i32)
+}
+
+pub fn read_int64(value: FieldValue) -> Result<i64> {
+    get_varint(value).map(|value| value as i64)
+}
+
+pub fn read_uint32(value: FieldValue) -> Result<u32> {
+    get_varint(value).map(|value| value as u32)
+}
+
+pub fn read_uint64(value: FieldValue) -> Result<u64> {
+    get_varint(value)
+}
+
+pub fn read_sint32(value: FieldValue) -> Result<i32> {
+    read_sint64(value).map(|value| value as i32)
+}
+
+pub fn read_sint64(value: FieldValue) -> Result<i64> {
+    get_varint(value).map(|value| (value >> 1) as i64 ^ -((value & 1) as i64))
+}
+
+pub fn read_bool(value: FieldValue) -> Result<bool> {
+    get_varint(value).map(|value| value != 0)
+}
+
+pub fn read_enum<E: Enum>(value: FieldValue) -> Result<E> {
+    Ok(E::from_u32(get_varint(value)? as u32)?)
+}
+
+pub fn read_fixed32(value: FieldValue) -> Result<u32> {
+    get_fixed32(value)
+}
+
+pub fn read_sfixed32(value: FieldValue) -> Result<i32> {
+    get_fixed32(value).map(|value| value as i32)
+}
+
+pub fn read_float(value: FieldValue) -> Result<f32> {
+    get_fixed32(value).map(f32::from_bits)
+}
+
+pub fn read_fixed64(value: FieldValue) -> Result<u64> {
+    get_fixed64(value)
+}
+
+pub fn read_sfixed64(value: FieldValue) -> Result<i64> {
+    get_fixed64(value).map(|value| value as i64)
+}
+
+pub fn read_double(value: FieldValue) -> Result<f64> {
+    get_fixed64(value).map(f64::from_bits)
+}
+
+pub fn read_string(value: FieldValue) -> Result<String> {
+    String::from_utf8(get_bytes(value)?.into_owned()).or(Err(ErrorKind::InvalidString.into()))
+}
+
+pub fn read_bytes(value: FieldValue) -> Result<Vec<u8>> {
+    get_bytes(value).map(|bytes| bytes.into_owned())
+}
+
+/// Reads a nested message, whose required fields are checked by `check_initialized` once the
+/// outer message has been read, since later values of the field may still be merged into it.
+pub fn read_message<M: Message>(value: FieldValue) -> Result<M> {
+    let mut message = M::default();
+    message.merge_binary(&get_bytes(value)?)?;
+    Ok(message)
+}
+
+/// Merges another value of a singular message field into `message`.
+pub fn merge_message<M: Message>(message: &mut M, value: FieldValue) -> Result<()> {
+    message.merge_binary(&get_bytes(value)?)
+}
+
+pub fn read_unknown_field(unknown_fields: &mut UnknownFields, number: u32, value: FieldValue) {
+    unknown_fields
+        .entry(number)
+        .or_insert_with(Vec::new)
+        .push(value.into_owned());
+}
+
+pub fn check_message<M: Message>(message: &M) -> Result<()> {
+    message
+        .check_initialized()
+        .chain_err(|| ErrorKind::InvalidMessage(message.get_name().to_owned()))
+}
+
+pub fn check_optional<M: Message>(field: &Option<M>) -> Result<()> {
+    match field {
+        &Some(ref message) => check_message(message),
+        &None => Ok(()),
+    }
+}
+
+pub fn check_repeated<M: Message>(field: &Option<Vec<M>>) -> Result<()> {
+    if let &Some(ref vec) = field {
+        for message in vec {
+            check_message(message)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn check_map<K, M: Message>(field: &Option<BTreeMap<K, M>>) -> Result<()> {
+    if let &Some(ref map) = field {
+        for message in map.values() {
+            check_message(message)?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends to a repeated field, which may be packed into a single length-delimited value if its
+/// elements are scalars of `wire_type`.
+pub fn read_repeated<A>(
+    field: &mut Option<Vec<A>>,
+    value: FieldValue,
+    wire_type: WireType,
+    read_elem: &Fn(FieldValue) -> Result<A>,
+) -> Result<()> {
+    let values = field.get_or_insert_with(Vec::new);
+    match value {
+        FieldValue::LengthDelimited(ref bytes) if wire_type != WireType::LengthDelimited => {
+            let mut reader = Reader::new(bytes);
+            while !reader.is_empty() {
+                values.push(read_elem(reader.read_value(wire_type)?)?);
+            }
+        }
+        value => values.push(read_elem(value)?),
+    }
+    Ok(())
+}
+
+/// Inserts an entry message into a map field. Missing keys and values take their defaults.
+pub fn read_map_entry<K: Ord + Default, V: Default>(
+    field: &mut Option<BTreeMap<K, V>>,
+    value: FieldValue,
+    read_key: &Fn(FieldValue) -> Result<K>,
+    read_value: &Fn(FieldValue) -> Result<V>,
+) -> Result<()> {
+    let bytes = get_bytes(value)?;
+    let mut reader = Reader::new(&bytes);
+    let mut key = None;
+    let mut entry_value = None;
+    while let Some((number, field_value)) = reader.read_field()? {
+        match number {
+            1 => key = Some(read_key(field_value)?),
+            2 => entry_value = Some(read_value(field_value)?),
+            _ => {}
+        }
+    }
+    field
+        .get_or_insert_with(BTreeMap::new)
+        .insert(key.unwrap_or_default(), entry_value.unwrap_or_default());
+    Ok(())
+}
+
+pub fn write_int32<'a>(value: &'a i32) -> FieldValue<'a> {
+    // Negative values are sign-extended to 64 bits.
+    FieldValue::Varint(*value as i64 as u64)
+}
+
+pub fn write_int64<'a>(value: &'a i64) -> FieldValue<'a> {
+    FieldValue::Varint(*value as u64)
+}
+
+pub fn write_uint32<'a>(value: &'a u32) -> FieldValue<'a> {
+    FieldValue::Varint(*value as u64)
+}
+
+pub fn write_uint64<'a>(value: &'a u64) -> FieldValue<'a> {
+    FieldValue::Varint(*value)
+}
+
+fn encode_zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub fn write_sint32<'a>(value: &'a i32) -> FieldValue<'a> {
+    FieldValue::Varint(encode_zigzag(*value as i64))
+}
+
+pub fn write_sint64<'a>(value: &'a i64) -> FieldValue<'a> {
+    FieldValue::Varint(encode_zigzag(*value))
+}
+
+pub fn write_bool<'a>(value: &'a bool) -> FieldValue<'a> {
+    FieldValue::Varint(*value as u64)
+}
+
+pub fn write_enum<'a, E: Enum>(value: &'a E) -> FieldValue<'a> {
+    // Negative values are sign-extended like int32.
+    FieldValue::Varint(value.to_u32() as i32 as i64 as u64)
+}
+
+pub fn write_fixed32<'a>(value: &'a u32) -> FieldValue<'a> {
+    FieldValue::Fixed32(*value)
+}
+
+pub fn write_sfixed32<'a>(value: &'a i32) -> FieldValue<'a> {
+    FieldValue::Fixed32(*value as u32)
+}
+
+pub fn write_float<'a>(value: &'a f32) -> FieldValue<'a> {
+    FieldValue::Fixed32(value.to_bits())
+}
+
+pub fn write_fixed64<'a>(value: &'a u64) -> FieldValue<'a> {
+    FieldValue::Fixed64(*value)
+}
+
+pub fn write_sfixed64<'a>(value: &'a i64) -> FieldValue<'a> {
+    FieldValue::Fixed64(*value as u64)
+}
+
+pub fn write_double<'a>(value: &'a f64) -> FieldValue<'a> {
+    FieldValue::Fixed64(value.to_bits())
+}
+
+pub fn write_string<'a>(value: &'a String) -> FieldValue<'a> {
+    FieldValue::LengthDelimited(Cow::Borrowed(value.as_bytes()))
+}
+
+pub fn write_bytes<'a>(value: &'a Vec<u8>) -> FieldValue<'a> {
+    FieldValue::LengthDelimited(Cow::Borrowed(value))
+}
+
+pub fn write_message<'a, M: Message>(value: &'a M) -> FieldValue<'a> {
+    FieldValue::LengthDelimited(Cow::Owned(value.to_binary()))
+}
+
+pub fn write_optional<A>(
+    writer: &mut Writer,
+    number: u32,
+    value: &Option<A>,
+    write_inner: &Fn(&A) -> FieldValue,
+) {
+    if let &Some(ref value) = value {
+        writer.write_field(number, write_inner(value));
+    }
+}
+
+pub fn write_repeated<A>(
+    writer: &mut Writer,
+    number: u32,
+    value: &Option<Vec<A>>,
+    write_elem: &Fn(&A) -> FieldValue,
+) {
+    if let &Some(ref vec) = value {
+        for value in vec {
+            writer.write_field(number, write_elem(value));
+        }
+    }
+}
+
+/// Writes a repeated field of scalars as a single length-delimited value.
+pub fn write_packed<A>(
+    writer: &mut Writer,
+    number: u32,
+    value: &Option<Vec<A>>,
+    write_elem: &Fn(&A) -> FieldValue,
+) {
+    match value {
+        &Some(ref vec) if !vec.is_empty() => {
+            let mut packed = Writer::new();
+            for value in vec {
+                packed.write_value(&write_elem(value));
+            }
+            writer.write_field(number, FieldValue::LengthDelimited(Cow::Owned(packed.into_bytes())));
+        }
+        _ => {}
+    }
+}
+
+pub fn write_map<K, V>(
+    writer: &mut Writer,
+    number: u32,
+    value: &Option<BTreeMap<K, V>>,
+    write_key: &Fn(&K) -> FieldValue,
+    write_value: &Fn(&V) -> FieldValue,
+) {
+    if let &Some(ref map) = value {
+        for (key, value) in map {
+            let mut entry = Writer::new();
+            entry.write_field(1, write_key(key));
+            entry.write_field(2, write_value(value));
+            writer.write_field(number, FieldValue::LengthDelimited(Cow::Owned(entry.into_bytes())));
+        }
+    }
+}
+
+/// Writes the unknown fields that were kept when decoding the binary wire format.
+pub fn write_unknown_fields(writer: &mut Writer, unknown_fields: &UnknownFields) {
+    for (&number, values) in unknown_fields {
+        for value in values {
+            writer.write_field_ref(number, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use example;
+    use pblite::Message;
+    use std::borrow::Cow;
+    use wire;
+    use wire::Message as WireMessage;
+
+    #[test]
+    fn test_varint() {
+        let mut writer = wire::Writer::new();
+        writer.write_varint(300);
+        let bytes = writer.into_bytes();
+        assert_eq!(bytes, vec![0xac, 0x02]);
+        assert_eq!(wire::Reader::new(&bytes).read_varint().unwrap(), 300);
+    }
+
+    #[test]
+    fn test_varint_too_long() {
+        let result = wire::Reader::new(&[0xff; 11]).read_varint();
+        assert_eq!(
+            result.err().expect("expected error").description(),
+            "invalid varint"
+        );
+    }
+
+    #[test]
+    fn test_i18n_data() {
+        let bytes = [0x0a, 0x02, b'C', b'A', 0x10, 0x01, 0x18, 0x01];
+        let i18n_data = example::I18nData::from_binary(&bytes).unwrap();
+        assert_eq!(
+            i18n_data,
+            example::I18nData {
+                region_code: Some("CA".to_owned()),
+                is_valid: Some(true),
+                country_code: Some(1),
+                ..Default::default()
+            }
+        );
+        assert_eq!(i18n_data.to_binary(), bytes);
+    }
+
+    #[test]
+    fn test_empty() {
+        let empty = example::Empty::from_binary(&[]).unwrap();
+        assert_eq!(empty, example::Empty::default());
+        assert_eq!(empty.to_binary(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let empty =
+            example::Empty::from_binary(&[0x08, 0x96, 0x01, 0x12, 0x01, 0x00, 0x08, 0x01]).unwrap();
+        assert_eq!(
+            empty.binary_unknown_fields.get(&1),
+            Some(&vec![wire::FieldValue::Varint(150), wire::FieldValue::Varint(1)])
+        );
+        assert_eq!(
+            empty.binary_unknown_fields.get(&2),
+            Some(&vec![wire::FieldValue::LengthDelimited(Cow::Owned(vec![0x00]))])
+        );
+        assert_eq!(
+            empty.to_binary(),
+            vec![0x08, 0x96, 0x01, 0x08, 0x01, 0x12, 0x01, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_pblite_unknown_fields() {
+        let i18n_data =
+            example::I18nData::from_pblite("[\"CA\",null,null,5,\"foo\",[1,2,3],1.5]").unwrap();
+        assert_eq!(i18n_data.unknown_fields.len(), 4);
+        assert_eq!(i18n_data.to_binary(), vec![0x0a, 0x02, b'C', b'A']);
+    }
+
+    #[test]
+    fn test_message_merged() {
+        let phone_number = example::PhoneNumber::from_binary(&[
+            0x12, 0x04, 0x0a, 0x02, b'C', b'A', 0x12, 0x02, 0x18, 0x01,
+        ]).unwrap();
+        assert_eq!(
+            phone_number.i18n_data,
+            Some(example::I18nData {
+                region_code: Some("CA".to_owned()),
+                country_code: Some(1),
+                ..Default::default()
+            })
+        );
+        let oneof = example::OneofExample::from_binary(&[
+            0x12, 0x04, 0x0a, 0x02, b'C', b'A', 0x12, 0x02, 0x18, 0x01,
+        ]).unwrap();
+        assert_eq!(
+            oneof.test_oneof,
+            Some(example::OneofExample_TestOneof::TestOneofMessage(
+                example::I18nData {
+                    region_code: Some("CA".to_owned()),
+                    country_code: Some(1),
+                    ..Default::default()
+                },
+            ))
+        );
+        let oneof = example::OneofExample::from_binary(&[
+            0x0a, 0x01, b'a', 0x12, 0x02, 0x18, 0x01,
+        ]).unwrap();
+        assert_eq!(
+            oneof.test_oneof,
+            Some(example::OneofExample_TestOneof::TestOneofMessage(
+                example::I18nData {
+                    country_code: Some(1),
+                    ..Default::default()
+                },
+            ))
+        );
+    }
+
+    #[test]
+    fn test_required_field_merged() {
+        let required = example::RequiredExample::from_binary(&[
+            0x0a, 0x01, b'a', 0x12, 0x00, 0x12, 0x02, 0x08, 0x01,
+        ]).unwrap();
+        assert_eq!(
+            required.test_required_message.unwrap().test_required_uint32,
+            Some(1)
+        );
+        let result = example::RequiredExample::from_binary(&[0x0a, 0x01, b'a', 0x12, 0x00]);
+        let error = result.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "required field 'test_required_uint32' is missing"
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        let result = example::I18nData::from_binary(&[0x0a, 0x05, b'C', b'A']);
+        let error = result.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        assert_eq!(error.iter().last().unwrap().to_string(), "message is truncated");
+    }
+
+    #[test]
+    fn test_unexpected_wire_type() {
+        let result = example::I18nData::from_binary(&[0x08, 0x01]);
+        let error = result.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "expected LengthDelimited value but got Varint value"
+        );
+    }
+
+    #[test]
+    fn test_invalid_wire_type() {
+        let result = example::Empty::from_binary(&[0x0b]);
+        let error = result.err().expect("expected error");
+        assert_eq!(error.iter().last().unwrap().to_string(), "wire type 3 is invalid");
+    }
+
+    #[test]
+    fn test_numbers() {
+        let numbers = example::ExampleNumbers {
+            test_int32: Some(-1),
+            test_sint32: Some(-2),
+            test_sint64: Some(1),
+            test_fixed32: Some(1),
+            test_float: Some(1.5),
+            ..Default::default()
+        };
+        let bytes = numbers.to_binary();
+        assert_eq!(
+            bytes,
+            vec![
+                0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+                0x18, 0x03,
+                0x20, 0x02,
+                0x2d, 0x01, 0x00, 0x00, 0x00,
+                0x4d, 0x00, 0x00, 0xc0, 0x3f,
+            ]
+        );
+        assert_eq!(example::ExampleNumbers::from_binary(&bytes).unwrap(), numbers);
+    }
+
+    #[test]
+    fn test_repeated_unpacked() {
+        let numbers = example::ExampleNumbers::from_binary(&[0x50, 0x01, 0x50, 0x02]).unwrap();
+        assert_eq!(numbers.test_repeated_int32, Some(vec![1, 2]));
+        assert_eq!(numbers.to_binary(), vec![0x50, 0x01, 0x50, 0x02]);
+    }
+
+    #[test]
+    fn test_repeated_packed() {
+        let numbers = example::ExampleNumbers::from_binary(&[0x52, 0x02, 0x01, 0x02]).unwrap();
+        assert_eq!(numbers.test_repeated_int32, Some(vec![1, 2]));
+        let packed = example::PackedExample {
+            test_packed_sint32: Some(vec![-1, 1]),
+            test_packed_double: Some(vec![]),
+            ..Default::default()
+        };
+        assert_eq!(packed.to_binary(), vec![0x0a, 0x02, 0x01, 0x02]);
+        assert_eq!(
+            example::PackedExample::from_binary(&[0x08, 0x01, 0x0a, 0x01, 0x02]).unwrap(),
+            example::PackedExample {
+                test_packed_sint32: Some(vec![-1, 1]),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        let bytes = [0x0a, 0x07, 0x0a, 0x03, b'f', b'o', b'o', 0x10, 0x01];
+        let map = example::MapExample::from_binary(&bytes).unwrap();
+        assert_eq!(map.test_map.as_ref().unwrap()["foo"], 1);
+        assert_eq!(map.to_binary(), bytes);
+        let map = example::MapExample::from_binary(&[0x12, 0x02, 0x08, 0x01]).unwrap();
+        assert_eq!(map.test_message_map.unwrap()[&1], example::I18nData::default());
+    }
+
+    #[test]
+    fn test_oneof() {
+        let oneof = example::OneofExample::from_binary(&[0x0a, 0x01, b'a', 0x12, 0x00]).unwrap();
+        assert_eq!(
+            oneof.test_oneof,
+            Some(example::OneofExample_TestOneof::TestOneofMessage(
+                example::I18nData::default(),
+            ))
+        );
+        assert_eq!(oneof.to_binary(), vec![0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_required_field_missing() {
+        let result = example::RequiredExample::from_binary(&[]);
+        let error = result.err().expect("expected error");
+        assert_eq!(error.description(), "invalid message");
+        assert_eq!(
+            error.iter().last().unwrap().to_string(),
+            "required field 'test_required_string' is missing"
+        );
+    }
+
+    fn assert_round_trip<M: WireMessage + PartialEq + ::std::fmt::Debug>(pblite: &str) {
+        let message = M::from_pblite(pblite).unwrap();
+        let binary_message = M::from_binary(&message.to_binary()).unwrap();
+        assert_eq!(binary_message, message);
+        assert_eq!(M::from_pblite(&binary_message.to_pblite()).unwrap(), message);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_round_trip::<example::PhoneNumber>("[\"16067624137\",[\"CA\", 1, 123]]");
+        assert_round_trip::<example::Example>("[3.14159,\"64\",32,1,\"foo\",\"AAEC\",2,[],[3.14159,1.1],[64,65],[32,33],[1,0],[\"foo\",\"bar\"],[\"AAEC\",\"AgEA\"],[2,3],[[],[]]]");
+        assert_round_trip::<example::ExampleNumbers>("[-1,\"-2\",-3,\"-4\",5,\"6\",-7,\"-8\",9.5,[-1,2],[\"-3\",4],[1.5]]");
+        assert_round_trip::<example::Example>("[null,null,null,null,null,null,100]");
+        assert_round_trip::<example::NestedExample>("[[\"foo\",2],[1,2],[\"1\"]]");
+        assert_round_trip::<example::SparseExample>("[\"foo\",{\"101\":5,\"250\":[\"CA\"]}]");
+        assert_round_trip::<example::MapExample>("[[[\"foo\",1]],[[1,[\"CA\"]],[2,[]]]]");
+    }
+}
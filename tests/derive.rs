@@ -0,0 +1,102 @@
+//! Tests deriving `PbliteMessage` outside of hangups, which only depends on hangups.
+
+extern crate hangups;
+
+use hangups::pblite::Message;
+use hangups::{pblite, pblite_serde, PbliteMessage};
+use std::collections::BTreeMap;
+
+#[derive(Debug, PartialEq, PbliteMessage)]
+enum Status {
+    #[pblite(value = 1)]
+    Active,
+    #[pblite(value = 2)]
+    Away,
+    #[pblite(unknown)]
+    Unknown(u32),
+}
+
+#[derive(Debug, Default, PartialEq, PbliteMessage)]
+struct Participant {
+    #[pblite(field = 1, required)]
+    name: Option<String>,
+    #[pblite(field = 2, enumeration)]
+    status: Option<Status>,
+    #[pblite(field = 3)]
+    read_timestamps: Option<BTreeMap<String, u64>>,
+    #[pblite(unknown_fields)]
+    unknown_fields: pblite::UnknownFields,
+}
+
+#[derive(Debug, Default, PartialEq, PbliteMessage)]
+struct Conversation {
+    #[pblite(field = 1)]
+    id: Option<String>,
+    #[pblite(field = 2)]
+    participants: Option<Vec<Participant>>,
+}
+
+#[test]
+fn test_derived_message() {
+    let text =
+        "[\"UgzJ\",[[\"Ann\",2,[[\"UgzJ\",\"1521002546845000\"]]],[\"Bob\",7,null,\"foo\"]]]";
+    let conversation = Conversation::from_pblite(text).unwrap();
+    assert_eq!(
+        conversation,
+        Conversation {
+            id: Some("UgzJ".to_owned()),
+            participants: Some(vec![
+                Participant {
+                    name: Some("Ann".to_owned()),
+                    status: Some(Status::Away),
+                    read_timestamps: Some(
+                        vec![("UgzJ".to_owned(), 1521002546845000)]
+                            .into_iter()
+                            .collect(),
+                    ),
+                    ..Default::default()
+                },
+                Participant {
+                    name: Some("Bob".to_owned()),
+                    status: Some(Status::Unknown(7)),
+                    unknown_fields: vec![(4, "foo".into())].into_iter().collect(),
+                    ..Default::default()
+                },
+            ]),
+        }
+    );
+    assert_eq!(
+        Conversation::from_pblite(&conversation.to_pblite()).unwrap(),
+        conversation
+    );
+}
+
+#[test]
+fn test_derived_message_invalid() {
+    let error = Conversation::from_pblite("[\"UgzJ\",[[\"Ann\",\"away\"]]]")
+        .err()
+        .expect("expected error");
+    let messages = error.iter().map(|cause| cause.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "message 'Conversation' is invalid",
+            "Conversation.participants[0].status (field 2): expected i64 value but got: '\"away\"'",
+        ]
+    );
+}
+
+#[test]
+fn test_derived_pblite_serde() {
+    let participant = Participant {
+        name: Some("Ann".to_owned()),
+        status: Some(Status::Active),
+        ..Default::default()
+    };
+    let text = pblite_serde::to_string(&participant).unwrap();
+    assert_eq!(text, "[\"Ann\",1]");
+    assert_eq!(
+        pblite_serde::from_str::<Participant>(&text).unwrap(),
+        participant
+    );
+}